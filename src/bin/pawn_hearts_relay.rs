@@ -0,0 +1,24 @@
+//! Lobby and relay server for players who cannot reach each other directly, see
+//! [`pawn_hearts::network::server`]. It is headless, no window is ever opened.
+
+use pawn_hearts::network::lobby::DEFAULT_RELAY_PORT;
+use pawn_hearts::network::server;
+use std::process::exit;
+
+const USAGE: &str = "usage: pawn_hearts_relay [address]";
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let address = match args.as_slice() {
+        [_] => format!("0.0.0.0:{DEFAULT_RELAY_PORT}"),
+        [_, address] => address.clone(),
+        _ => {
+            eprintln!("{USAGE}");
+            exit(-1)
+        }
+    };
+    if let Err(e) = server::run(&address) {
+        eprintln!("{e}");
+        exit(-1)
+    }
+}
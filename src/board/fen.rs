@@ -0,0 +1,202 @@
+use super::{BoardPos, ChessBoard, ChessBoardCell, ChessPiece, LongStart, Side};
+use anyhow::{anyhow, bail, Result};
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+impl ChessBoard {
+    /// Reads a position in Forsyth-Edwards Notation.
+    ///
+    /// The board only remembers whether a king has moved, so any castling right of a colour
+    /// lets its king castle to both sides as long as the rook is still in its corner. The move
+    /// counters are not kept either.
+    /// # Returns
+    /// the board and the side to move
+    pub fn from_fen(fen: &str) -> Result<(Self, Side)> {
+        let mut fields = fen.split_whitespace();
+        let placement = fields.next().ok_or(anyhow!("empty FEN"))?;
+        let side = match fields.next() {
+            Some("w") | None => Side::White,
+            Some("b") => Side::Black,
+            Some(other) => bail!("invalid side to move '{other}'"),
+        };
+        let castling = fields.next().unwrap_or("-");
+        let en_passant = fields.next().unwrap_or("-");
+
+        let mut board = Self::new_empty();
+        let ranks = placement.split('/').collect::<Vec<_>>();
+        if ranks.len() != 8 {
+            bail!("FEN has {} ranks instead of 8", ranks.len());
+        }
+        for (row, rank) in ranks.into_iter().enumerate() {
+            let mut col = 0;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    col += empty as usize;
+                    continue;
+                }
+                if col >= 8 {
+                    bail!("rank {} is too long", 8 - row);
+                }
+                let pawn = |start_row| {
+                    if row == start_row {
+                        ChessPiece::Pawn(LongStart::Before)
+                    } else {
+                        ChessPiece::Pawn(LongStart::After)
+                    }
+                };
+                let cell = match c {
+                    'P' => ChessBoardCell::White(pawn(6)),
+                    'N' => ChessBoardCell::White(ChessPiece::Knight),
+                    'B' => ChessBoardCell::White(ChessPiece::Bishop),
+                    'R' => ChessBoardCell::White(ChessPiece::Rook),
+                    'Q' => ChessBoardCell::White(ChessPiece::Queen),
+                    'K' => ChessBoardCell::White(ChessPiece::King(!castling.contains(['K', 'Q']))),
+                    'p' => ChessBoardCell::Black(pawn(1)),
+                    'n' => ChessBoardCell::Black(ChessPiece::Knight),
+                    'b' => ChessBoardCell::Black(ChessPiece::Bishop),
+                    'r' => ChessBoardCell::Black(ChessPiece::Rook),
+                    'q' => ChessBoardCell::Black(ChessPiece::Queen),
+                    'k' => ChessBoardCell::Black(ChessPiece::King(!castling.contains(['k', 'q']))),
+                    other => bail!("invalid piece '{other}'"),
+                };
+                board.place_at(BoardPos { row, col }, cell)?;
+                col += 1;
+            }
+            if col != 8 {
+                bail!("rank {} does not have 8 cells", 8 - row);
+            }
+        }
+        // a king away from its starting cell cannot castle no matter what the FEN says
+        for (index, cell) in board.cells.iter_mut().enumerate() {
+            let (home, moved) = match cell {
+                ChessBoardCell::White(ChessPiece::King(moved)) => (7 * 8 + 4, moved),
+                ChessBoardCell::Black(ChessPiece::King(moved)) => (4, moved),
+                _ => continue,
+            };
+            if index != home {
+                *moved = true;
+            }
+        }
+        if en_passant != "-" {
+            let target = BoardPos::from_square(en_passant)
+                .ok_or(anyhow!("invalid en passant square '{en_passant}'"))?;
+            // the pawn which just made its long start stands one cell past the target
+            let (pawn_pos, cell) = match target.row {
+                5 => (
+                    BoardPos {
+                        row: 4,
+                        col: target.col,
+                    },
+                    ChessBoardCell::White(ChessPiece::Pawn(LongStart::RightNow)),
+                ),
+                2 => (
+                    BoardPos {
+                        row: 3,
+                        col: target.col,
+                    },
+                    ChessBoardCell::Black(ChessPiece::Pawn(LongStart::RightNow)),
+                ),
+                _ => bail!("invalid en passant square '{en_passant}'"),
+            };
+            if board.at(pawn_pos).and_then(ChessBoardCell::side) == cell.side() {
+                board.place_at(pawn_pos, cell)?;
+            }
+        }
+        Ok((board, side))
+    }
+
+    /// Writes the position in Forsyth-Edwards Notation. The board does not keep track of the
+    /// move counters so they are always `0 1`.
+    pub fn to_fen(&self, side: Side) -> String {
+        let mut placement = String::new();
+        for row in 0..8 {
+            let mut empty = 0;
+            for col in 0..8 {
+                let cell = self.cells[BoardPos { row, col }.to_index()];
+                let c = match cell.take_piece() {
+                    Some(ChessPiece::Pawn(_)) => 'p',
+                    Some(ChessPiece::Knight) => 'n',
+                    Some(ChessPiece::Bishop) => 'b',
+                    Some(ChessPiece::Rook) => 'r',
+                    Some(ChessPiece::Queen) => 'q',
+                    Some(ChessPiece::King(_)) => 'k',
+                    None => {
+                        empty += 1;
+                        continue;
+                    }
+                };
+                if empty > 0 {
+                    placement.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                placement.push(match cell {
+                    ChessBoardCell::White(_) => c.to_ascii_uppercase(),
+                    _ => c,
+                });
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if row != 7 {
+                placement.push('/');
+            }
+        }
+        let side_char = match side {
+            Side::White => 'w',
+            Side::Black => 'b',
+        };
+        format!(
+            "{placement} {side_char} {} {} 0 1",
+            self.castling_rights(),
+            self.en_passant_target()
+                .map(BoardPos::to_square)
+                .unwrap_or("-".to_owned())
+        )
+    }
+
+    /// Castling rights in the FEN format, `-` if nobody can castle
+    pub fn castling_rights(&self) -> String {
+        let mut ret = String::new();
+        let rights = [
+            (
+                7,
+                ChessBoardCell::White as fn(ChessPiece) -> ChessBoardCell,
+                ['K', 'Q'],
+            ),
+            (0, ChessBoardCell::Black, ['k', 'q']),
+        ];
+        for (row, cell, [short, long]) in rights {
+            if self.at(BoardPos { row, col: 4 }) != Some(&cell(ChessPiece::King(false))) {
+                continue;
+            }
+            if self.at(BoardPos { row, col: 7 }) == Some(&cell(ChessPiece::Rook)) {
+                ret.push(short);
+            }
+            if self.at(BoardPos { row, col: 0 }) == Some(&cell(ChessPiece::Rook)) {
+                ret.push(long);
+            }
+        }
+        if ret.is_empty() {
+            ret.push('-');
+        }
+        ret
+    }
+
+    /// The cell a pawn skipped over with its long start on the last move, if any
+    pub fn en_passant_target(&self) -> Option<BoardPos> {
+        self.cells
+            .iter()
+            .enumerate()
+            .find_map(|(index, cell)| match cell {
+                ChessBoardCell::White(ChessPiece::Pawn(LongStart::RightNow)) => Some(BoardPos {
+                    row: index / 8 + 1,
+                    col: index % 8,
+                }),
+                ChessBoardCell::Black(ChessPiece::Pawn(LongStart::RightNow)) => Some(BoardPos {
+                    row: index / 8 - 1,
+                    col: index % 8,
+                }),
+                _ => None,
+            })
+    }
+}
@@ -1,4 +1,10 @@
+mod fen;
+mod move_generation;
 mod move_validation;
+mod notation;
+pub mod pgn;
+
+pub use fen::STARTING_FEN;
 
 use anyhow::anyhow;
 use bytes::BufMut;
@@ -41,6 +47,12 @@ impl BoardMove {
         bytes.put_u8(self.columns as u8);
         bytes.into()
     }
+    pub fn from(&self) -> BoardPos {
+        self.from
+    }
+    pub fn to(&self) -> BoardPos {
+        self.to
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -114,7 +126,51 @@ pub enum ChessBoardCell {
     Empty,
 }
 
+/// The colour a player controls
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Side {
+    White,
+    Black,
+}
+
+impl Side {
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::White => Side::Black,
+            Side::Black => Side::White,
+        }
+    }
+    /// Whose turn it is once this many moves were played from the standard position
+    pub fn to_move_after(plies: usize) -> Self {
+        if plies.is_multiple_of(2) {
+            Side::White
+        } else {
+            Side::Black
+        }
+    }
+}
+
+impl std::str::FromStr for Side {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "white" | "w" => Ok(Side::White),
+            "black" | "b" => Ok(Side::Black),
+            _ => Err(anyhow!("unknown side '{s}'")),
+        }
+    }
+}
+
 impl ChessBoardCell {
+    /// # Returns
+    /// `Some` with the colour of the piece in the cell, `None` if the cell is empty
+    pub fn side(&self) -> Option<Side> {
+        match self {
+            Self::White(_) => Some(Side::White),
+            Self::Black(_) => Some(Side::Black),
+            Self::Empty => None,
+        }
+    }
     /// extracts the piece from the cell
     /// # Returns
     /// `Some` if the cell contained a piece, otherwise `None`
@@ -234,7 +290,7 @@ pub struct ChessBoard {
 
 impl ChessBoard {
     pub fn at(&self, pos: BoardPos) -> Option<&ChessBoardCell> {
-        if pos.row > 8 || pos.col > 8 {
+        if pos.row >= 8 || pos.col >= 8 {
             None
         } else {
             self.cells.get(pos.to_index())
@@ -242,7 +298,7 @@ impl ChessBoard {
     }
     pub fn take_from(&mut self, pos: BoardPos) -> Option<ChessBoardCell> {
         let (row, col) = (pos.row, pos.col);
-        if row > 8 || col > 8 {
+        if row >= 8 || col >= 8 {
             None
         } else {
             let index = pos.to_index();
@@ -254,7 +310,7 @@ impl ChessBoard {
     }
     pub fn place_at(&mut self, pos: BoardPos, cell: ChessBoardCell) -> anyhow::Result<()> {
         let (row, col) = (pos.row, pos.col);
-        if row > 8 || col > 8 {
+        if row >= 8 || col >= 8 {
             Err(anyhow!("position out of bounds"))
         } else {
             let index = pos.to_index();
@@ -273,6 +329,18 @@ impl ChessBoard {
             pieces_moved: vec![],
             pieces_set: vec![],
         };
+        // a pawn can only be taken en passant right after its long start
+        for cell in self.cells.iter_mut() {
+            match cell {
+                ChessBoardCell::White(ChessPiece::Pawn(ls))
+                | ChessBoardCell::Black(ChessPiece::Pawn(ls))
+                    if *ls == LongStart::RightNow =>
+                {
+                    *ls = LongStart::After
+                }
+                _ => (),
+            }
+        }
         let side_effects = side_effects.unwrap_or_default();
         for side_effect in side_effects.into_iter().rev() {
             match side_effect {
@@ -373,4 +441,22 @@ impl ChessBoard {
     pub fn cells(&self) -> &Vec<ChessBoardCell> {
         &self.cells
     }
+    /// Whether `side` has enough material left to ever take the opposing king, a lone minor
+    /// piece does not
+    pub fn can_mate(&self, side: Side) -> bool {
+        let mut minors = 0;
+        for piece in self
+            .cells
+            .iter()
+            .filter(|c| c.side() == Some(side))
+            .filter_map(|c| c.take_piece())
+        {
+            match piece {
+                ChessPiece::Pawn(_) | ChessPiece::Rook | ChessPiece::Queen => return true,
+                ChessPiece::Bishop | ChessPiece::Knight => minors += 1,
+                ChessPiece::King(_) => (),
+            }
+        }
+        minors >= 2
+    }
 }
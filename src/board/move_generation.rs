@@ -0,0 +1,129 @@
+use super::move_validation::ValidationResult;
+use super::{BoardMove, BoardPos, ChessBoard, ChessBoardCell, ChessPiece, Side};
+
+const KNIGHT_JUMPS: [(isize, isize); 8] = [
+    (-2, -1),
+    (-2, 1),
+    (-1, -2),
+    (-1, 2),
+    (1, -2),
+    (1, 2),
+    (2, -1),
+    (2, 1),
+];
+const DIAGONALS: [(isize, isize); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+const STRAIGHTS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+impl ChessBoard {
+    /// Every move of `side` that passes validation. There is no check detection so moves
+    /// which leave the king open to capture are included as well.
+    pub fn moves_for(&self, side: Side) -> Vec<BoardMove> {
+        let mut ret = vec![];
+        for (index, cell) in self.cells.iter().enumerate() {
+            if cell.side() != Some(side) {
+                continue;
+            }
+            let from = BoardPos {
+                row: index / 8,
+                col: index % 8,
+            };
+            for to in self.candidate_targets(*cell, from) {
+                let m = BoardMove::new(from, to);
+                if let ValidationResult::Valid(_) = self.validate_move(m) {
+                    ret.push(m);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Whether `side` could take the opposing king with its next move, what check looks like
+    /// without check detection
+    pub fn attacks_king(&self, side: Side) -> bool {
+        self.moves_for(side).into_iter().any(|m| {
+            matches!(
+                self.at(m.to).and_then(|c| c.take_piece()),
+                Some(ChessPiece::King(_))
+            )
+        })
+    }
+
+    /// Squares a piece could geometrically reach, validation decides which of them are
+    /// actually allowed
+    fn candidate_targets(&self, cell: ChessBoardCell, from: BoardPos) -> Vec<BoardPos> {
+        let Some(piece) = cell.take_piece() else {
+            return vec![];
+        };
+        let forward = if cell.side() == Some(Side::White) {
+            -1
+        } else {
+            1
+        };
+        match piece {
+            ChessPiece::Pawn(_) => [(forward, 0), (forward * 2, 0), (forward, -1), (forward, 1)]
+                .iter()
+                .filter_map(|&(r, c)| offset(from, r, c))
+                .collect(),
+            ChessPiece::Knight => KNIGHT_JUMPS
+                .iter()
+                .filter_map(|&(r, c)| offset(from, r, c))
+                .collect(),
+            ChessPiece::Bishop => self.rays(from, &DIAGONALS),
+            ChessPiece::Rook => self.rays(from, &STRAIGHTS),
+            ChessPiece::Queen => {
+                let mut ret = self.rays(from, &DIAGONALS);
+                ret.append(&mut self.rays(from, &STRAIGHTS));
+                ret
+            }
+            ChessPiece::King(moved) => {
+                let mut ret = DIAGONALS
+                    .iter()
+                    .chain(STRAIGHTS.iter())
+                    .filter_map(|&(r, c)| offset(from, r, c))
+                    .collect::<Vec<_>>();
+                if !moved {
+                    // castling targets, see the king checkers in move_validation
+                    ret.push(BoardPos {
+                        row: from.row,
+                        col: 6,
+                    });
+                    ret.push(BoardPos {
+                        row: from.row,
+                        col: 2,
+                    });
+                }
+                ret
+            }
+        }
+    }
+
+    /// Walks every direction until the edge of the board or the first occupied cell
+    /// (which is included)
+    fn rays(&self, from: BoardPos, directions: &[(isize, isize)]) -> Vec<BoardPos> {
+        let mut ret = vec![];
+        for &(r, c) in directions {
+            let mut pos = from;
+            while let Some(next) = offset(pos, r, c) {
+                ret.push(next);
+                if !matches!(self.at(next), Some(ChessBoardCell::Empty)) {
+                    break;
+                }
+                pos = next;
+            }
+        }
+        ret
+    }
+}
+
+fn offset(pos: BoardPos, rows: isize, columns: isize) -> Option<BoardPos> {
+    let row = pos.row as isize + rows;
+    let col = pos.col as isize + columns;
+    if (0..8).contains(&row) && (0..8).contains(&col) {
+        Some(BoardPos {
+            row: row as usize,
+            col: col as usize,
+        })
+    } else {
+        None
+    }
+}
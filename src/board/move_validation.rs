@@ -83,38 +83,25 @@ fn black_pawn(mv: Move, b: &Board) -> VRes {
             if let Some(&Cell::White(_)) = b.at(mv.to) {
                 VRes::Valid(Some(vec![SEffect::SetAt(
                     mv.to,
-                    Cell::Black(Piece::Pawn(LongStart::After)),
-                )]))
-            } else if let Some(&Cell::White(Piece::Pawn(LongStart::RightNow))) = b.at(BoardPos {
-                row: mv.from.row,
-                col: mv.from.col - 1,
-            }) {
-                VRes::Valid(Some(vec![SEffect::Delete(
-                    BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col - 1,
-                    },
-                    *b.at(BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col - 1,
-                    })
-                    .unwrap(),
-                )]))
-            } else if let Some(&Cell::White(Piece::Pawn(LongStart::RightNow))) = b.at(BoardPos {
-                row: mv.from.row,
-                col: mv.from.col + 1,
-            }) {
-                VRes::Valid(Some(vec![SEffect::Delete(
-                    BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col + 1,
-                    },
-                    *b.at(BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col + 1,
-                    })
-                    .unwrap(),
+                    black_pawn_arriving(mv.to),
                 )]))
+            } else if let Some(&passed @ Cell::White(Piece::Pawn(LongStart::RightNow))) =
+                b.at(BoardPos {
+                    row: mv.from.row,
+                    col: mv.to.col,
+                })
+            {
+                // en passant, the captured pawn sits right beside the capturing one
+                VRes::Valid(Some(vec![
+                    SEffect::Delete(
+                        BoardPos {
+                            row: mv.from.row,
+                            col: mv.to.col,
+                        },
+                        passed,
+                    ),
+                    SEffect::SetAt(mv.to, Cell::Black(Piece::Pawn(LongStart::After))),
+                ]))
             } else {
                 VRes::NotValid
             }
@@ -124,7 +111,11 @@ fn black_pawn(mv: Move, b: &Board) -> VRes {
             columns: 0,
             ..
         } if mv.from.row == 1 => {
-            if let Some(&Cell::Empty) = b.at(mv.to) {
+            let passed = BoardPos {
+                row: 2,
+                col: mv.from.col,
+            };
+            if let (Some(&Cell::Empty), Some(&Cell::Empty)) = (b.at(passed), b.at(mv.to)) {
                 VRes::Valid(Some(vec![SEffect::SetAt(
                     mv.to,
                     Cell::Black(Piece::Pawn(LongStart::RightNow)),
@@ -141,15 +132,13 @@ fn black_pawn(mv: Move, b: &Board) -> VRes {
             if let Some(&Cell::Empty) = b.at(mv.to) {
                 VRes::Valid(Some(vec![SEffect::SetAt(
                     mv.to,
-                    Cell::Black(Piece::Pawn(LongStart::After)),
+                    black_pawn_arriving(mv.to),
                 )]))
             } else {
                 VRes::NotValid
             }
         }
-        _ => {
-            VRes::NotValid
-        }
+        _ => VRes::NotValid,
     }
 }
 
@@ -168,38 +157,25 @@ fn white_pawn(mv: Move, b: &Board) -> VRes {
             if let Some(&Cell::Black(_)) = b.at(mv.to) {
                 VRes::Valid(Some(vec![SEffect::SetAt(
                     mv.to,
-                    Cell::White(Piece::Pawn(LongStart::After)),
-                )]))
-            } else if let Some(&Cell::Black(Piece::Pawn(LongStart::RightNow))) = b.at(BoardPos {
-                row: mv.from.row,
-                col: mv.from.col - 1,
-            }) {
-                VRes::Valid(Some(vec![SEffect::Delete(
-                    BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col - 1,
-                    },
-                    *b.at(BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col - 1,
-                    })
-                    .unwrap(),
-                )]))
-            } else if let Some(&Cell::Black(Piece::Pawn(LongStart::RightNow))) = b.at(BoardPos {
-                row: mv.from.row,
-                col: mv.from.col + 1,
-            }) {
-                VRes::Valid(Some(vec![SEffect::Delete(
-                    BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col + 1,
-                    },
-                    *b.at(BoardPos {
-                        row: mv.from.row,
-                        col: mv.from.col + 1,
-                    })
-                    .unwrap(),
+                    white_pawn_arriving(mv.to),
                 )]))
+            } else if let Some(&passed @ Cell::Black(Piece::Pawn(LongStart::RightNow))) =
+                b.at(BoardPos {
+                    row: mv.from.row,
+                    col: mv.to.col,
+                })
+            {
+                // en passant, the captured pawn sits right beside the capturing one
+                VRes::Valid(Some(vec![
+                    SEffect::Delete(
+                        BoardPos {
+                            row: mv.from.row,
+                            col: mv.to.col,
+                        },
+                        passed,
+                    ),
+                    SEffect::SetAt(mv.to, Cell::White(Piece::Pawn(LongStart::After))),
+                ]))
             } else {
                 VRes::NotValid
             }
@@ -209,7 +185,11 @@ fn white_pawn(mv: Move, b: &Board) -> VRes {
             columns: 0,
             ..
         } if mv.from.row == 6 => {
-            if let Some(&Cell::Empty) = b.at(mv.to) {
+            let passed = BoardPos {
+                row: 5,
+                col: mv.from.col,
+            };
+            if let (Some(&Cell::Empty), Some(&Cell::Empty)) = (b.at(passed), b.at(mv.to)) {
                 VRes::Valid(Some(vec![SEffect::SetAt(
                     mv.to,
                     Cell::White(Piece::Pawn(LongStart::RightNow)),
@@ -226,7 +206,7 @@ fn white_pawn(mv: Move, b: &Board) -> VRes {
             if let Some(&Cell::Empty) = b.at(mv.to) {
                 VRes::Valid(Some(vec![SEffect::SetAt(
                     mv.to,
-                    Cell::White(Piece::Pawn(LongStart::After)),
+                    white_pawn_arriving(mv.to),
                 )]))
             } else {
                 VRes::NotValid
@@ -236,6 +216,23 @@ fn white_pawn(mv: Move, b: &Board) -> VRes {
     }
 }
 
+/// A pawn which reaches the far end of the board is promoted to a queen
+fn black_pawn_arriving(at: BoardPos) -> Cell {
+    if at.row == 7 {
+        Cell::Black(Piece::Queen)
+    } else {
+        Cell::Black(Piece::Pawn(LongStart::After))
+    }
+}
+
+fn white_pawn_arriving(at: BoardPos) -> Cell {
+    if at.row == 0 {
+        Cell::White(Piece::Queen)
+    } else {
+        Cell::White(Piece::Pawn(LongStart::After))
+    }
+}
+
 fn bishop(mv: Move, b: &Board) -> VRes {
     if mv.columns.abs() == mv.rows.abs() {
         for (r, c) in bisex_range(0, mv.rows)
@@ -324,12 +321,13 @@ fn black_king_can_castle(mv: Move, b: &Board) -> VRes {
             }),
             SEffect::SetAt(mv.to, Cell::Black(Piece::King(true))),
         ]));
-    } else if mv.from.row == 0 && mv.from.col == 4 && mv.to.row == 0 && mv.to.col == 1 {
+    } else if mv.from.row == 0 && mv.from.col == 4 && mv.to.row == 0 && mv.to.col == 2 {
         //long castling
         let Some(Cell::Black(Piece::Rook)) = b.at(BoardPos { row: 0, col: 0 }) else {
             return VRes::NotValid;
         };
-        for c in bisex_range(0, mv.columns).skip(1) {
+        // the cell next to the rook has to be empty as well
+        for c in bisex_range(0, mv.columns - 2).skip(1) {
             let to_check = BoardPos {
                 row: mv.from.row,
                 col: (mv.from.col as isize + c) as usize,
@@ -341,9 +339,9 @@ fn black_king_can_castle(mv: Move, b: &Board) -> VRes {
         return VRes::Valid(Some(vec![
             SEffect::Move(Move {
                 from: BoardPos { row: 0, col: 0 },
-                to: BoardPos { row: 0, col: 2 },
+                to: BoardPos { row: 0, col: 3 },
                 rows: 0,
-                columns: 2,
+                columns: 3,
             }),
             SEffect::SetAt(mv.to, Cell::Black(Piece::King(true))),
         ]));
@@ -381,12 +379,13 @@ fn white_king_can_castle(mv: Move, b: &Board) -> VRes {
             }),
             SEffect::SetAt(mv.to, Cell::White(Piece::King(true))),
         ]));
-    } else if mv.from.row == 7 && mv.from.col == 4 && mv.to.row == 7 && mv.to.col == 1 {
+    } else if mv.from.row == 7 && mv.from.col == 4 && mv.to.row == 7 && mv.to.col == 2 {
         //long castling
         let Some(Cell::White(Piece::Rook)) = b.at(BoardPos { row: 7, col: 0 }) else {
             return VRes::NotValid;
         };
-        for c in bisex_range(0, mv.columns).skip(1) {
+        // the cell next to the rook has to be empty as well
+        for c in bisex_range(0, mv.columns - 2).skip(1) {
             let to_check = BoardPos {
                 row: mv.from.row,
                 col: (mv.from.col as isize + c) as usize,
@@ -398,9 +397,9 @@ fn white_king_can_castle(mv: Move, b: &Board) -> VRes {
         return VRes::Valid(Some(vec![
             SEffect::Move(Move {
                 from: BoardPos { row: 7, col: 0 },
-                to: BoardPos { row: 7, col: 2 },
+                to: BoardPos { row: 7, col: 3 },
                 rows: 0,
-                columns: 2,
+                columns: 3,
             }),
             SEffect::SetAt(mv.to, Cell::White(Piece::King(true))),
         ]));
@@ -441,3 +440,54 @@ fn bisex_range(a: isize, b: isize) -> impl Iterator<Item = isize> {
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(fen: &str, uci: &str) -> Option<ChessBoard> {
+        let (mut board, _) = ChessBoard::from_fen(fen).unwrap();
+        board.move_piece(BoardMove::from_uci(uci).unwrap())?;
+        Some(board)
+    }
+
+    fn at(board: &ChessBoard, square: &str) -> Option<Cell> {
+        board.at(BoardPos::from_square(square)?).copied()
+    }
+
+    #[test]
+    fn long_castling_puts_the_king_on_the_c_file() {
+        let board = play("r3k3/8/8/8/8/8/8/R3K3 w Qq - 0 1", "e1c1").unwrap();
+        assert_eq!(at(&board, "c1"), Some(Cell::White(Piece::King(true))));
+        assert_eq!(at(&board, "d1"), Some(Cell::White(Piece::Rook)));
+        assert_eq!(at(&board, "a1"), Some(Cell::Empty));
+        let board = play("r3k3/8/8/8/8/8/8/R3K3 b Qq - 0 1", "e8c8").unwrap();
+        assert_eq!(at(&board, "c8"), Some(Cell::Black(Piece::King(true))));
+        assert_eq!(at(&board, "d8"), Some(Cell::Black(Piece::Rook)));
+    }
+
+    #[test]
+    fn long_castling_needs_the_b_file_empty() {
+        assert!(play("r3k3/8/8/8/8/8/8/RN2K3 w Qq - 0 1", "e1c1").is_none());
+        assert!(play("rn2k3/8/8/8/8/8/8/R3K3 b Qq - 0 1", "e8c8").is_none());
+    }
+
+    #[test]
+    fn pawns_are_promoted_to_queens() {
+        let board = play("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8").unwrap();
+        assert_eq!(at(&board, "a8"), Some(Cell::White(Piece::Queen)));
+        let board = play("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8").unwrap();
+        assert_eq!(at(&board, "b8"), Some(Cell::White(Piece::Queen)));
+        let board = play("4k3/8/8/8/8/8/7p/4K3 b - - 0 1", "h2h1").unwrap();
+        assert_eq!(at(&board, "h1"), Some(Cell::Black(Piece::Queen)));
+    }
+
+    #[test]
+    fn pawns_short_of_the_last_rank_stay_pawns() {
+        let board = play("4k3/8/P7/8/8/8/8/4K3 w - - 0 1", "a6a7").unwrap();
+        assert!(matches!(
+            at(&board, "a7"),
+            Some(Cell::White(Piece::Pawn(_)))
+        ));
+    }
+}
@@ -0,0 +1,326 @@
+use super::{BoardMove, BoardPos, ChessBoard, ChessBoardCell, ChessPiece, Side};
+use std::mem::discriminant;
+
+impl BoardPos {
+    /// Name of the cell in algebraic notation, row 0 is the eighth rank
+    pub fn to_square(self) -> String {
+        let file = (b'a' + self.col as u8) as char;
+        let rank = (b'8' - self.row as u8) as char;
+        format!("{file}{rank}")
+    }
+    /// # Returns
+    /// `None` if `square` is not a valid square name like `e4`
+    pub fn from_square(square: &str) -> Option<Self> {
+        let &[file, rank] = square.as_bytes() else {
+            return None;
+        };
+        if !(b'a'..=b'h').contains(&file) || !(b'1'..=b'8').contains(&rank) {
+            return None;
+        }
+        Some(Self {
+            row: (b'8' - rank) as usize,
+            col: (file - b'a') as usize,
+        })
+    }
+}
+
+impl BoardMove {
+    /// Long algebraic notation as used by UCI, e.g. `e2e4` or `e7e8q`. The board is the one the
+    /// move is about to be played on and is needed to tell whether the move is a promotion.
+    pub fn to_uci(&self, board: &ChessBoard) -> String {
+        let mut ret = self.from.to_square() + &self.to.to_square();
+        if self.promotion(board).is_some() {
+            ret.push('q');
+        }
+        ret
+    }
+    /// The piece the move promotes to when played on `board`, which is always a queen
+    pub fn promotion(&self, board: &ChessBoard) -> Option<ChessPiece> {
+        let promotes = match board.at(self.from) {
+            Some(ChessBoardCell::White(ChessPiece::Pawn(_))) => self.to.row == 0,
+            Some(ChessBoardCell::Black(ChessPiece::Pawn(_))) => self.to.row == 7,
+            _ => false,
+        };
+        promotes.then_some(ChessPiece::Queen)
+    }
+    /// Parses long algebraic notation. Pawns are always promoted to queens so the promotion
+    /// suffix is accepted but otherwise ignored, see [`Self::promotion_from_uci`].
+    pub fn from_uci(uci: &str) -> Option<Self> {
+        if !(4..=5).contains(&uci.len()) || !uci.is_ascii() {
+            return None;
+        }
+        let from = BoardPos::from_square(&uci[0..2])?;
+        let to = BoardPos::from_square(&uci[2..4])?;
+        match uci.as_bytes().get(4) {
+            None | Some(b'q' | b'r' | b'b' | b'n') => Some(Self::new(from, to)),
+            Some(_) => None,
+        }
+    }
+    /// The piece named by the promotion suffix of a move in long algebraic notation, `None`
+    /// without one
+    pub fn promotion_from_uci(uci: &str) -> Option<ChessPiece> {
+        match uci.as_bytes().get(4) {
+            Some(b'q') => Some(ChessPiece::Queen),
+            Some(b'r') => Some(ChessPiece::Rook),
+            Some(b'b') => Some(ChessPiece::Bishop),
+            Some(b'n') => Some(ChessPiece::Knight),
+            _ => None,
+        }
+    }
+    /// Standard algebraic notation, e.g. `Nbd7`, `exd5`, `O-O` or `e8=Q+`. The board is the one
+    /// the move is about to be played on. A `+` is added when the moved side could take the king
+    /// next, there is no `#` since games end with the capture of the king.
+    pub fn to_san(&self, board: &ChessBoard) -> String {
+        let cell = board
+            .at(self.from)
+            .copied()
+            .unwrap_or(ChessBoardCell::Empty);
+        let (Some(side), Some(piece)) = (cell.side(), cell.take_piece()) else {
+            return self.to_uci(board);
+        };
+        let target = board.at(self.to).copied().unwrap_or(ChessBoardCell::Empty);
+        // en passant is the only way for a pawn to change its file without taking on the target
+        let en_passant = matches!(piece, ChessPiece::Pawn(_)) && self.from.col != self.to.col;
+        let capture = target != ChessBoardCell::Empty || en_passant;
+        let mut ret = match piece {
+            ChessPiece::King(_) if self.columns == 2 => "O-O".to_owned(),
+            ChessPiece::King(_) if self.columns == -2 => "O-O-O".to_owned(),
+            ChessPiece::Pawn(_) => {
+                let mut ret = String::new();
+                if capture {
+                    ret.push((b'a' + self.from.col as u8) as char);
+                    ret.push('x');
+                }
+                ret += &self.to.to_square();
+                if self.to.row == 0 || self.to.row == 7 {
+                    ret += "=Q";
+                }
+                ret
+            }
+            _ => {
+                let mut ret = piece_letter(piece).to_string();
+                ret += &self.disambiguation(board, cell, side);
+                if capture {
+                    ret.push('x');
+                }
+                ret + &self.to.to_square()
+            }
+        };
+        let mut after = board.clone();
+        if after.move_piece(*self).is_some() && after.attacks_king(side) {
+            ret.push('+');
+        }
+        ret
+    }
+    /// Reads standard algebraic notation like `Nf3`, `exd5`, `O-O` or `e8=Q+` for `side`, check
+    /// marks and annotations are ignored. Long algebraic notation as used by UCI is accepted as
+    /// well.
+    /// # Returns
+    /// `None` if the text is not a move or does not name exactly one valid move
+    pub fn from_san(san: &str, board: &ChessBoard, side: Side) -> Option<Self> {
+        let san = san.trim().trim_end_matches(['+', '#', '!', '?']);
+        let moves = board.moves_for(side);
+        if let Some(m) = Self::from_uci(san).filter(|m| moves.contains(m)) {
+            return Some(m);
+        }
+        let castle = match san {
+            "O-O" | "0-0" => Some(2),
+            "O-O-O" | "0-0-0" => Some(-2),
+            _ => None,
+        };
+        if let Some(columns) = castle {
+            return moves.into_iter().find(|m| {
+                m.columns == columns
+                    && matches!(
+                        board.at(m.from).and_then(|c| c.take_piece()),
+                        Some(ChessPiece::King(_))
+                    )
+            });
+        }
+        // pieces are always promoted to queens
+        let san = san
+            .split_once('=')
+            .map(|(s, _)| s)
+            .unwrap_or(san)
+            .replace('x', "");
+        if !san.is_ascii() || san.len() < 2 {
+            return None;
+        }
+        let (letter, rest) = match san.as_bytes()[0] {
+            b'N' | b'B' | b'R' | b'Q' | b'K' => (&san[0..1], &san[1..]),
+            _ => ("", &san[..]),
+        };
+        if rest.len() < 2 {
+            return None;
+        }
+        let to = BoardPos::from_square(&rest[rest.len() - 2..])?;
+        let hint = &rest[..rest.len() - 2];
+        let mut found = moves.into_iter().filter(|m| {
+            let Some(piece) = board.at(m.from).and_then(|c| c.take_piece()) else {
+                return false;
+            };
+            let square = m.from.to_square();
+            m.to == to && piece_letter(piece) == letter && hint.chars().all(|c| square.contains(c))
+        });
+        match (found.next(), found.next()) {
+            (Some(m), None) => Some(m),
+            _ => None,
+        }
+    }
+    /// The file, rank or both of the starting cell when another piece of the same kind and
+    /// colour could move to the same cell
+    fn disambiguation(&self, board: &ChessBoard, cell: ChessBoardCell, side: Side) -> String {
+        let rivals = board
+            .moves_for(side)
+            .into_iter()
+            .filter(|m| m.to == self.to && m.from != self.from)
+            .filter(|m| {
+                board
+                    .at(m.from)
+                    .is_some_and(|c| discriminant(c) == discriminant(&cell) && same_kind(*c, cell))
+            })
+            .map(|m| m.from)
+            .collect::<Vec<_>>();
+        let square = self.from.to_square();
+        if rivals.is_empty() {
+            String::new()
+        } else if rivals.iter().all(|r| r.col != self.from.col) {
+            square[0..1].to_owned()
+        } else if rivals.iter().all(|r| r.row != self.from.row) {
+            square[1..2].to_owned()
+        } else {
+            square
+        }
+    }
+}
+
+fn same_kind(a: ChessBoardCell, b: ChessBoardCell) -> bool {
+    match (a.take_piece(), b.take_piece()) {
+        (Some(a), Some(b)) => discriminant(&a) == discriminant(&b),
+        _ => false,
+    }
+}
+
+/// Upper case letter used for the piece in algebraic notation, empty for pawns
+fn piece_letter(piece: ChessPiece) -> &'static str {
+    match piece {
+        ChessPiece::Pawn(_) => "",
+        ChessPiece::Knight => "N",
+        ChessPiece::Bishop => "B",
+        ChessPiece::Rook => "R",
+        ChessPiece::Queen => "Q",
+        ChessPiece::King(_) => "K",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::LongStart;
+
+    fn pos(square: &str) -> BoardPos {
+        BoardPos::from_square(square).unwrap()
+    }
+
+    fn mv(from: &str, to: &str) -> BoardMove {
+        BoardMove::new(pos(from), pos(to))
+    }
+
+    /// A board with just `pieces` on it
+    fn board(pieces: &[(&str, ChessBoardCell)]) -> ChessBoard {
+        let mut board = ChessBoard::new_empty();
+        for &(square, cell) in pieces {
+            board.place_at(pos(square), cell).unwrap();
+        }
+        board
+    }
+
+    /// Writes `m` down and reads it back, which has to give `san` and the same move
+    fn round_trip(board: &ChessBoard, m: BoardMove, side: Side, san: &str) {
+        assert_eq!(m.to_san(board), san);
+        assert_eq!(BoardMove::from_san(san, board, side), Some(m));
+    }
+
+    #[test]
+    fn files_and_ranks_tell_pieces_apart() {
+        let knights = board(&[
+            ("b8", ChessBoardCell::Black(ChessPiece::Knight)),
+            ("f6", ChessBoardCell::Black(ChessPiece::Knight)),
+            ("h8", ChessBoardCell::Black(ChessPiece::King(true))),
+            ("h1", ChessBoardCell::White(ChessPiece::King(true))),
+        ]);
+        round_trip(&knights, mv("b8", "d7"), Side::Black, "Nbd7");
+        round_trip(&knights, mv("f6", "d7"), Side::Black, "Nfd7");
+        // either knight could go there
+        assert_eq!(BoardMove::from_san("Nd7", &knights, Side::Black), None);
+
+        let rooks = board(&[
+            ("e1", ChessBoardCell::White(ChessPiece::Rook)),
+            ("e3", ChessBoardCell::White(ChessPiece::Rook)),
+            ("a1", ChessBoardCell::White(ChessPiece::King(true))),
+            ("a8", ChessBoardCell::Black(ChessPiece::King(true))),
+        ]);
+        round_trip(&rooks, mv("e1", "e2"), Side::White, "R1e2");
+        round_trip(&rooks, mv("e3", "e2"), Side::White, "R3e2");
+    }
+
+    #[test]
+    fn castling_goes_both_ways() {
+        let board = board(&[
+            ("e1", ChessBoardCell::White(ChessPiece::King(false))),
+            ("a1", ChessBoardCell::White(ChessPiece::Rook)),
+            ("h1", ChessBoardCell::White(ChessPiece::Rook)),
+            ("e8", ChessBoardCell::Black(ChessPiece::King(true))),
+        ]);
+        round_trip(&board, mv("e1", "g1"), Side::White, "O-O");
+        round_trip(&board, mv("e1", "c1"), Side::White, "O-O-O");
+        assert_eq!(
+            BoardMove::from_san("0-0", &board, Side::White),
+            Some(mv("e1", "g1"))
+        );
+        assert_eq!(BoardMove::from_san("O-O", &board, Side::Black), None);
+    }
+
+    #[test]
+    fn captures_are_marked() {
+        let mut board = ChessBoard::new_full();
+        board.move_piece(mv("e2", "e4")).unwrap();
+        board.move_piece(mv("d7", "d5")).unwrap();
+        round_trip(&board, mv("e4", "d5"), Side::White, "exd5");
+        board.move_piece(mv("e4", "d5")).unwrap();
+        round_trip(&board, mv("d8", "d5"), Side::Black, "Qxd5");
+    }
+
+    #[test]
+    fn pawns_promote_to_queens() {
+        let board = board(&[
+            (
+                "a7",
+                ChessBoardCell::White(ChessPiece::Pawn(LongStart::After)),
+            ),
+            ("e1", ChessBoardCell::White(ChessPiece::King(true))),
+            ("h6", ChessBoardCell::Black(ChessPiece::King(true))),
+        ]);
+        round_trip(&board, mv("a7", "a8"), Side::White, "a8=Q");
+        assert_eq!(
+            BoardMove::from_san("a8", &board, Side::White),
+            Some(mv("a7", "a8"))
+        );
+    }
+
+    #[test]
+    fn nonsense_is_not_a_move() {
+        let board = ChessBoard::new_full();
+        for san in [
+            "", "e", "hello", "Nz9", "Ke5", "e5", "Nf4", "Zf3", "O-O", "e2e5",
+        ] {
+            assert_eq!(BoardMove::from_san(san, &board, Side::White), None, "{san}");
+        }
+        assert_eq!(
+            BoardMove::from_san("Nf3", &board, Side::Black),
+            None,
+            "not black's knight"
+        );
+        assert!(BoardMove::from_san("Nf6", &board, Side::Black).is_some());
+    }
+}
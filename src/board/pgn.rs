@@ -0,0 +1,295 @@
+//! Reading and writing games in Portable Game Notation
+
+use super::{BoardMove, ChessBoard, Side};
+use anyhow::{anyhow, bail, Result};
+
+/// Tags every PGN file is supposed to have, in their required order
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+const LINE_LENGTH: usize = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWon,
+    BlackWon,
+    Draw,
+    Unknown,
+}
+
+impl GameResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GameResult::WhiteWon => "1-0",
+            GameResult::BlackWon => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Unknown => "*",
+        }
+    }
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "1-0" => Some(GameResult::WhiteWon),
+            "0-1" => Some(GameResult::BlackWon),
+            "1/2-1/2" => Some(GameResult::Draw),
+            "*" => Some(GameResult::Unknown),
+            _ => None,
+        }
+    }
+    pub fn won_by(side: Side) -> Self {
+        match side {
+            Side::White => GameResult::WhiteWon,
+            Side::Black => GameResult::BlackWon,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnMove {
+    pub mv: BoardMove,
+    /// Numeric annotation glyph, e.g. `2` for `?`
+    pub nag: Option<u8>,
+    /// Written after the move
+    pub comment: Option<String>,
+}
+
+impl From<BoardMove> for PgnMove {
+    fn from(mv: BoardMove) -> Self {
+        Self {
+            mv,
+            nag: None,
+            comment: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnGame {
+    /// Tags of the seven tag roster which are missing get filled with `?`
+    pub tags: Vec<(String, String)>,
+    /// FEN of the starting position, the standard one when `None`
+    pub start: Option<String>,
+    pub moves: Vec<PgnMove>,
+    pub result: GameResult,
+}
+
+impl PgnGame {
+    pub fn new(moves: Vec<PgnMove>, result: GameResult) -> Self {
+        Self {
+            tags: vec![],
+            start: None,
+            moves,
+            result,
+        }
+    }
+    pub fn tag(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.tags.push((name.to_owned(), value.into()));
+        self
+    }
+    /// # Errors
+    /// when the starting position cannot be read or one of the moves is not valid
+    pub fn to_pgn(&self) -> Result<String> {
+        let mut ret = String::new();
+        for name in SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => self.result.as_str(),
+                "Date" => self.tag_value(name).unwrap_or("????.??.??"),
+                _ => self.tag_value(name).unwrap_or("?"),
+            };
+            ret += &format_tag(name, value);
+        }
+        for (name, value) in &self.tags {
+            if !SEVEN_TAG_ROSTER.contains(&name.as_str()) {
+                ret += &format_tag(name, value);
+            }
+        }
+        let (mut board, mut side) = match &self.start {
+            Some(fen) => {
+                ret += &format_tag("SetUp", "1");
+                ret += &format_tag("FEN", fen);
+                ChessBoard::from_fen(fen)?
+            }
+            None => (ChessBoard::new_full(), Side::White),
+        };
+        ret.push('\n');
+
+        let mut tokens = vec![];
+        let mut number = 1;
+        // black's moves need their number after anything that interrupts the move list
+        let mut needs_number = true;
+        for pgn_move in &self.moves {
+            match side {
+                Side::White => tokens.push(format!("{number}.")),
+                Side::Black if needs_number => tokens.push(format!("{number}...")),
+                Side::Black => (),
+            }
+            tokens.push(pgn_move.mv.to_san(&board));
+            board
+                .move_piece(pgn_move.mv)
+                .ok_or(anyhow!("invalid move {}", pgn_move.mv.to_uci(&board)))?;
+            needs_number = false;
+            if let Some(nag) = pgn_move.nag {
+                tokens.push(format!("${nag}"));
+            }
+            if let Some(comment) = &pgn_move.comment {
+                // comments end at the first closing brace
+                tokens.push(format!("{{ {} }}", comment.replace('}', ")")));
+                needs_number = true;
+            }
+            if side == Side::Black {
+                number += 1;
+            }
+            side = side.opposite();
+        }
+        tokens.push(self.result.as_str().to_owned());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+                ret += &line;
+                ret.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &token;
+        }
+        ret += &line;
+        ret.push('\n');
+        Ok(ret)
+    }
+    fn tag_value(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn format_tag(name: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("[{name} \"{value}\"]\n")
+}
+
+enum Token {
+    Tag(String, String),
+    Word(String),
+}
+
+/// Reads every game of a PGN file. Comments, variations and annotation glyphs are skipped.
+/// # Errors
+/// when a tag cannot be read or a move is not valid in its game
+pub fn read_games(text: &str) -> Result<Vec<PgnGame>> {
+    let mut games = vec![];
+    let mut game = PgnGame::new(vec![], GameResult::Unknown);
+    // position the next move is played on, set up once the first move shows up
+    let mut position: Option<(ChessBoard, Side)> = None;
+    for token in tokenize(text)? {
+        match token {
+            Token::Tag(name, value) => {
+                // a game without a result ends where the tags of the next one start
+                if position.is_some() {
+                    games.push(std::mem::replace(
+                        &mut game,
+                        PgnGame::new(vec![], GameResult::Unknown),
+                    ));
+                    position = None;
+                }
+                if name == "FEN" {
+                    game.start = Some(value);
+                } else if name != "SetUp" {
+                    game.tags.push((name, value));
+                }
+            }
+            Token::Word(word) => {
+                if let Some(result) = GameResult::parse(&word) {
+                    game.result = result;
+                    games.push(std::mem::replace(
+                        &mut game,
+                        PgnGame::new(vec![], GameResult::Unknown),
+                    ));
+                    position = None;
+                    continue;
+                }
+                // move numbers may be glued to the move like `1.e4`
+                let san = match word.rfind('.') {
+                    Some(at) if word.starts_with(|c: char| c.is_ascii_digit()) => &word[at + 1..],
+                    _ => &word[..],
+                };
+                if san.is_empty() || san.starts_with('$') {
+                    continue;
+                }
+                let (board, side) = match &mut position {
+                    Some(p) => p,
+                    None => position.insert(match &game.start {
+                        Some(fen) => ChessBoard::from_fen(fen)?,
+                        None => (ChessBoard::new_full(), Side::White),
+                    }),
+                };
+                let mv = BoardMove::from_san(san, board, *side)
+                    .ok_or(anyhow!("invalid move '{san}' in game {}", games.len() + 1))?;
+                board.move_piece(mv);
+                *side = side.opposite();
+                game.moves.push(mv.into());
+            }
+        }
+    }
+    if position.is_some() {
+        games.push(game);
+    }
+    Ok(games)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let tag = chars.by_ref().take_while(|&c| c != ']').collect::<String>();
+                tokens.push(parse_tag(&tag)?);
+            }
+            '{' => chars.by_ref().take_while(|&c| c != '}').for_each(drop),
+            ';' => chars.by_ref().take_while(|&c| c != '\n').for_each(drop),
+            '(' => {
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('(') => depth += 1,
+                        Some(')') => depth -= 1,
+                        Some('{') => chars.by_ref().take_while(|&c| c != '}').for_each(drop),
+                        Some(_) => (),
+                        None => bail!("unclosed variation"),
+                    }
+                }
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "[{(;".contains(next) {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_tag(tag: &str) -> Result<Token> {
+    let (name, value) = tag
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or(anyhow!("invalid tag [{tag}]"))?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or(anyhow!("invalid tag [{tag}]"))?;
+    Ok(Token::Tag(
+        name.to_owned(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
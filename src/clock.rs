@@ -0,0 +1,226 @@
+//! Time controls and the clocks which keep them, for network games and engine matches
+
+use crate::board::Side;
+use anyhow::{anyhow, bail, Result};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Every player gets `base` for the whole game and `increment` after each of their moves. With a
+/// Bronstein `delay` the time a move took is given back as well, up to the delay.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+    pub delay: Duration,
+}
+
+impl TimeControl {
+    pub const fn from_secs(base: u64, increment: u64, delay: u64) -> Self {
+        Self {
+            base: Duration::from_secs(base),
+            increment: Duration::from_secs(increment),
+            delay: Duration::from_secs(delay),
+        }
+    }
+}
+
+/// Longer times could not be sent to the peer, which gets them in milliseconds as 32 bits
+pub const MAX_TIME: Duration = Duration::from_millis(u32::MAX as u64);
+
+/// What the setup screen offers, from bullet to rapid
+pub const PRESETS: [TimeControl; 6] = [
+    TimeControl::from_secs(60, 0, 0),
+    TimeControl::from_secs(180, 2, 0),
+    TimeControl::from_secs(300, 3, 0),
+    TimeControl::from_secs(300, 0, 3),
+    TimeControl::from_secs(600, 0, 0),
+    TimeControl::from_secs(900, 10, 0),
+];
+
+impl FromStr for TimeControl {
+    type Err = anyhow::Error;
+    /// Seconds like `60`, `60+0.5` or `300d3`, the delay follows the `d`
+    fn from_str(s: &str) -> Result<Self> {
+        let (rest, delay) = s.split_once('d').unwrap_or((s, "0"));
+        let (base, increment) = rest.split_once('+').unwrap_or((rest, "0"));
+        let seconds = |v: &str| {
+            v.parse::<f64>()
+                .ok()
+                .and_then(|v| Duration::try_from_secs_f64(v).ok())
+                .filter(|d| *d <= MAX_TIME)
+                .ok_or(anyhow!("invalid time control '{s}'"))
+        };
+        let tc = Self {
+            base: seconds(base)?,
+            increment: seconds(increment)?,
+            delay: seconds(delay)?,
+        };
+        if tc.base.is_zero() {
+            bail!("the time control needs some base time");
+        }
+        Ok(tc)
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}+{}",
+            self.base.as_secs_f64(),
+            self.increment.as_secs_f64()
+        )?;
+        if !self.delay.is_zero() {
+            write!(f, "d{}", self.delay.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// Time left for both sides
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clocks {
+    pub white: Duration,
+    pub black: Duration,
+    pub increment: Duration,
+    pub delay: Duration,
+}
+
+impl Clocks {
+    pub fn new(tc: TimeControl) -> Self {
+        Self {
+            white: tc.base,
+            black: tc.base,
+            increment: tc.increment,
+            delay: tc.delay,
+        }
+    }
+    pub fn left(&self, side: Side) -> Duration {
+        match side {
+            Side::White => self.white,
+            Side::Black => self.black,
+        }
+    }
+    fn clock_mut(&mut self, side: Side) -> &mut Duration {
+        match side {
+            Side::White => &mut self.white,
+            Side::Black => &mut self.black,
+        }
+    }
+    /// Takes the time a move took off the clock of `side`
+    /// # Returns
+    /// `false` if it was more than what was left
+    pub fn spend(&mut self, side: Side, elapsed: Duration) -> bool {
+        let (increment, delay) = (self.increment, self.delay);
+        let clock = self.clock_mut(side);
+        match clock.checked_sub(elapsed) {
+            Some(left) => {
+                *clock = left + elapsed.min(delay) + increment;
+                true
+            }
+            None => {
+                *clock = Duration::ZERO;
+                false
+            }
+        }
+    }
+}
+
+/// The clocks of a game being played, only the one of the side to move runs
+#[derive(Clone, Debug)]
+pub struct ChessClock {
+    clocks: Clocks,
+    running: Option<Side>,
+    /// when the running clock was started
+    since: Instant,
+}
+
+impl ChessClock {
+    pub fn new(tc: TimeControl, running: Side) -> Self {
+        Self {
+            clocks: Clocks::new(tc),
+            running: Some(running),
+            since: Instant::now(),
+        }
+    }
+    /// Time left of `side` as of now
+    pub fn left(&self, side: Side) -> Duration {
+        let left = self.clocks.left(side);
+        if self.running == Some(side) {
+            left.saturating_sub(self.since.elapsed())
+        } else {
+            left
+        }
+    }
+    pub fn running(&self) -> Option<Side> {
+        self.running
+    }
+    /// Stops the running clock once its side moved and starts the other one
+    /// # Returns
+    /// `false` if the side which moved ran out of time before
+    pub fn press(&mut self) -> bool {
+        let Some(side) = self.running else {
+            return true;
+        };
+        let in_time = self.clocks.spend(side, self.since.elapsed());
+        self.running = Some(side.opposite());
+        self.since = Instant::now();
+        in_time
+    }
+    /// Takes the timekeeper's word for the time left, the clock of `running` goes on from now
+    pub fn set(&mut self, white: Duration, black: Duration, running: Side) {
+        self.clocks.white = white;
+        self.clocks.black = black;
+        self.running = Some(running);
+        self.since = Instant::now();
+    }
+    /// Starts the clock of `running` from what it had left, like after a takeback
+    pub fn restart(&mut self, running: Side) {
+        self.stop();
+        self.running = Some(running);
+        self.since = Instant::now();
+    }
+    /// Stops the running clock without an increment, once the game is over
+    pub fn stop(&mut self) {
+        if let Some(side) = self.running.take() {
+            *self.clocks.clock_mut(side) =
+                self.clocks.left(side).saturating_sub(self.since.elapsed());
+        }
+    }
+}
+
+/// Minutes and seconds, with tenths once it gets tight
+pub fn format(left: Duration) -> String {
+    let secs = left.as_secs();
+    if secs < 10 {
+        format!("0:{:02}.{}", secs, left.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_controls_parse() {
+        let tc = "60+0.5d3".parse::<TimeControl>().unwrap();
+        assert_eq!(tc.base, Duration::from_secs(60));
+        assert_eq!(tc.increment, Duration::from_millis(500));
+        assert_eq!(tc.delay, Duration::from_secs(3));
+        assert_eq!(
+            "300".parse::<TimeControl>().unwrap(),
+            TimeControl::from_secs(300, 0, 0)
+        );
+    }
+
+    #[test]
+    fn unusable_time_controls_are_errors() {
+        for tc in [
+            "1e30", "60+1e30", "60d1e30", "-1", "NaN", "inf", "60+x", "0", "5e6",
+        ] {
+            assert!(tc.parse::<TimeControl>().is_err(), "{tc} parsed");
+        }
+    }
+}
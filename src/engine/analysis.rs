@@ -0,0 +1,241 @@
+//! Reviewing finished games with the built-in search
+
+use super::{SearchLimits, MATE};
+use crate::board::pgn::{GameResult, PgnGame, PgnMove};
+use crate::board::{BoardMove, ChessBoard, ChessPiece, Side};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+
+/// Swings beyond this many centipawns are all the same, so that a won position getting a bit
+/// less won does not count as a blunder
+const SCORE_CAP: i32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    /// # Arguments
+    /// * `loss` - how many centipawns the move threw away compared to the best one
+    fn from_loss(loss: i32) -> Option<Self> {
+        match loss {
+            300.. => Some(Judgement::Blunder),
+            100.. => Some(Judgement::Mistake),
+            50.. => Some(Judgement::Inaccuracy),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "Inaccuracy",
+            Judgement::Mistake => "Mistake",
+            Judgement::Blunder => "Blunder",
+        }
+    }
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "?!",
+            Judgement::Mistake => "?",
+            Judgement::Blunder => "??",
+        }
+    }
+    /// Numeric annotation glyph used in PGN
+    pub fn nag(self) -> u8 {
+        match self {
+            Judgement::Inaccuracy => 6,
+            Judgement::Mistake => 2,
+            Judgement::Blunder => 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveReview {
+    pub played: BoardMove,
+    pub side: Side,
+    /// the move the search preferred, `None` when it agrees with the one played
+    pub best: Option<BoardMove>,
+    /// centipawns lost compared to the best move
+    pub loss: i32,
+    pub judgement: Option<Judgement>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameReview {
+    pub moves: Vec<MoveReview>,
+    /// centipawns from white's point of view before every move and after the last one
+    pub evals: Vec<i32>,
+}
+
+impl GameReview {
+    /// Position before the move at `ply`, or after the last move when `ply` is past the end
+    pub fn board_at(&self, ply: usize) -> ChessBoard {
+        let mut board = ChessBoard::new_full();
+        for review in self.moves.iter().take(ply) {
+            board.move_piece(review.played);
+        }
+        board
+    }
+    /// The game with the evaluation after every move and the best alternative to every flagged
+    /// move as comments
+    pub fn to_pgn(&self, result: GameResult) -> PgnGame {
+        let mut board = ChessBoard::new_full();
+        let mut moves = vec![];
+        for (ply, review) in self.moves.iter().enumerate() {
+            let mut comment = format!("[%eval {}]", format_score(self.evals[ply + 1]));
+            if let Some(judgement) = review.judgement {
+                comment += &format!(" {}.", judgement.name());
+                if let Some(best) = review.best {
+                    comment += &format!(" {} was best.", best.to_san(&board));
+                }
+            }
+            moves.push(PgnMove {
+                mv: review.played,
+                nag: review.judgement.map(Judgement::nag),
+                comment: Some(comment),
+            });
+            board.move_piece(review.played);
+        }
+        PgnGame::new(moves, result).tag("Annotator", "Pawn Hearts")
+    }
+}
+
+/// Pawns from white's point of view like `+0.35`, or moves until the king falls like `#-3`
+pub fn format_score(score: i32) -> String {
+    if super::is_mate_score(score) {
+        let moves = ((MATE - score.abs()) / 2).max(1);
+        format!("#{}", moves * score.signum())
+    } else {
+        format!("{:+.2}", score as f32 / 100.)
+    }
+}
+
+/// Searches every position of the game, the slow part of a review
+/// # Returns
+/// `None` when stopped or when the moves do not make up a valid game
+pub fn review(
+    moves: &[BoardMove],
+    limits: &SearchLimits,
+    stop: Arc<AtomicBool>,
+    mut progress: impl FnMut(usize),
+) -> Option<GameReview> {
+    let mut board = ChessBoard::new_full();
+    let mut side = Side::White;
+    // best move and score for the side to move of every position
+    let mut searched = vec![];
+    for ply in 0..=moves.len() {
+        if stop.load(Ordering::Relaxed) {
+            return None;
+        }
+        searched.push(evaluate_position(&board, side, limits, stop.clone()));
+        progress(ply + 1);
+        if let Some(m) = moves.get(ply) {
+            board.move_piece(*m)?;
+            side = side.opposite();
+        }
+    }
+    let evals = searched
+        .iter()
+        .enumerate()
+        .map(|(ply, (_, score))| if ply % 2 == 0 { *score } else { -*score })
+        .collect::<Vec<_>>();
+    let moves = moves
+        .iter()
+        .enumerate()
+        .map(|(ply, played)| {
+            let (best, before) = searched[ply];
+            let after = -searched[ply + 1].1;
+            let best = best.filter(|b| b != played);
+            let loss = match best {
+                Some(_) => (cap(before) - cap(after)).max(0),
+                None => 0,
+            };
+            MoveReview {
+                played: *played,
+                side: if ply % 2 == 0 {
+                    Side::White
+                } else {
+                    Side::Black
+                },
+                best,
+                loss,
+                judgement: Judgement::from_loss(loss),
+            }
+        })
+        .collect();
+    Some(GameReview { moves, evals })
+}
+
+fn cap(score: i32) -> i32 {
+    score.clamp(-SCORE_CAP, SCORE_CAP)
+}
+
+/// Best move and score from the point of view of `side`
+fn evaluate_position(
+    board: &ChessBoard,
+    side: Side,
+    limits: &SearchLimits,
+    stop: Arc<AtomicBool>,
+) -> (Option<BoardMove>, i32) {
+    let has_king = board
+        .cells()
+        .iter()
+        .any(|c| c.side() == Some(side) && matches!(c.take_piece(), Some(ChessPiece::King(_))));
+    if !has_king {
+        return (None, -MATE);
+    }
+    match super::search_with_stop(board, side, limits, stop, |_| ()) {
+        Some(res) => (Some(res.best), res.score),
+        None => (None, super::eval::evaluate_for(board, side)),
+    }
+}
+
+/// A review running on its own thread, stopped when dropped
+pub struct ReviewJob {
+    total: usize,
+    done: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    result: Receiver<Option<GameReview>>,
+}
+
+impl ReviewJob {
+    pub fn start(moves: Vec<BoardMove>, limits: SearchLimits) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let done = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let total = moves.len() + 1;
+        let (thread_done, thread_stop) = (done.clone(), stop.clone());
+        std::thread::spawn(move || {
+            let res = review(&moves, &limits, thread_stop, |n| {
+                thread_done.store(n, Ordering::Relaxed)
+            });
+            // nobody is waiting any more if the job was dropped
+            let _ = tx.send(res);
+        });
+        Self {
+            total,
+            done,
+            stop,
+            result: rx,
+        }
+    }
+    /// Positions searched so far and how many there are
+    pub fn progress(&self) -> (usize, usize) {
+        (self.done.load(Ordering::Relaxed), self.total)
+    }
+    /// # Returns
+    /// `Some` once the review is finished, `Some(None)` if it failed
+    pub fn poll(&self) -> Option<Option<GameReview>> {
+        self.result.try_recv().ok()
+    }
+}
+
+impl Drop for ReviewJob {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,54 @@
+//! Searches which run on their own thread while the window keeps drawing, used for hints and
+//! the evaluation bar
+
+use super::{SearchLimits, SearchResult};
+use crate::board::{ChessBoard, Side};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Stops searching when dropped
+pub struct BackgroundSearch {
+    side: Side,
+    stop: Arc<AtomicBool>,
+    latest: Arc<Mutex<Option<SearchResult>>>,
+}
+
+impl BackgroundSearch {
+    pub fn start(board: ChessBoard, side: Side, limits: SearchLimits) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let latest = Arc::new(Mutex::new(None));
+        let (thread_stop, thread_latest) = (stop.clone(), latest.clone());
+        std::thread::spawn(move || {
+            super::search_with_stop(&board, side, &limits, thread_stop, |res| {
+                *thread_latest.lock().unwrap() = Some(*res);
+            });
+        });
+        Self { side, stop, latest }
+    }
+    /// Result of the deepest iteration finished so far
+    pub fn latest(&self) -> Option<SearchResult> {
+        *self.latest.lock().unwrap()
+    }
+    /// Score of the deepest iteration in centipawns from white's point of view
+    pub fn white_score(&self) -> Option<i32> {
+        self.latest().map(|res| match self.side {
+            Side::White => res.score,
+            Side::Black => -res.score,
+        })
+    }
+}
+
+impl Drop for BackgroundSearch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Maps a score from white's point of view onto how much of the evaluation bar is white, a
+/// pawn's advantage moving the bar noticeably and anything past a few pieces nearly filling it
+pub fn white_share(score: i32) -> f32 {
+    if super::is_mate_score(score) {
+        return if score > 0 { 1. } else { 0. };
+    }
+    1. / (1. + (-score as f32 / 400.).exp())
+}
@@ -0,0 +1,244 @@
+use crate::board::{BoardMove, BoardPos, ChessBoard, ChessBoardCell, ChessPiece, Side};
+use anyhow::{bail, Result};
+use std::path::Path;
+
+mod random;
+
+use random::RANDOM64;
+
+const ENTRY_SZ: usize = 16;
+const CASTLING_OFFSET: usize = 768;
+const EN_PASSANT_OFFSET: usize = 772;
+const TURN_OFFSET: usize = 780;
+
+/// A single record of a Polyglot `.bin` file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BookEntry {
+    pub key: u64,
+    pub mv: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+/// Opening book in the Polyglot format, the whole file is kept in memory
+pub struct Book {
+    /// sorted by key, as they are in the file
+    entries: Vec<BookEntry>,
+}
+
+impl Book {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.len().is_multiple_of(ENTRY_SZ) {
+            bail!("book size is not a multiple of {ENTRY_SZ} bytes");
+        }
+        let mut entries = bytes
+            .chunks_exact(ENTRY_SZ)
+            .map(|e| BookEntry {
+                key: u64::from_be_bytes(e[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes(e[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(e[10..12].try_into().unwrap()),
+                learn: u32::from_be_bytes(e[12..16].try_into().unwrap()),
+            })
+            .collect::<Vec<_>>();
+        // books are supposed to be sorted already but lookups break badly if one is not
+        entries.sort_by_key(|e| e.key);
+        Ok(Self { entries })
+    }
+    /// Book moves for the position which are valid on the board, the most weighted first
+    pub fn moves(&self, board: &ChessBoard, side: Side) -> Vec<(BoardMove, u16)> {
+        let key = polyglot_key(board, side);
+        let start = self.entries.partition_point(|e| e.key < key);
+        let mut ret = self.entries[start..]
+            .iter()
+            .take_while(|e| e.key == key)
+            .filter_map(|e| {
+                let m = decode_move(board, e.mv);
+                board.clone().move_piece(m).map(|_| (m, e.weight))
+            })
+            .collect::<Vec<_>>();
+        ret.sort_by_key(|(_, w)| std::cmp::Reverse(*w));
+        ret
+    }
+    /// Picks one of the book moves at random, moves with bigger weights being more likely
+    /// # Returns
+    /// `None` if the position is not in the book
+    pub fn pick(&self, board: &ChessBoard, side: Side) -> Option<BoardMove> {
+        let moves = self.moves(board, side);
+        let total = moves.iter().map(|(_, w)| *w as u32).sum::<u32>();
+        if total == 0 {
+            return moves.first().map(|(m, _)| *m);
+        }
+        let mut roll = rand::random_range(0..total);
+        for (m, w) in moves {
+            if roll < w as u32 {
+                return Some(m);
+            }
+            roll -= w as u32;
+        }
+        None
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Polyglot rows count from the first rank while board rows count from the eighth
+fn polyglot_row(pos: BoardPos) -> usize {
+    7 - pos.row
+}
+
+/// Computes the standard Polyglot hash of a position
+pub fn polyglot_key(board: &ChessBoard, side: Side) -> u64 {
+    let mut key = 0;
+    for (index, cell) in board.cells().iter().enumerate() {
+        let (piece, white) = match *cell {
+            ChessBoardCell::White(p) => (p, 1),
+            ChessBoardCell::Black(p) => (p, 0),
+            ChessBoardCell::Empty => continue,
+        };
+        let piece_type = match piece {
+            ChessPiece::Pawn(_) => 0,
+            ChessPiece::Knight => 1,
+            ChessPiece::Bishop => 2,
+            ChessPiece::Rook => 3,
+            ChessPiece::Queen => 4,
+            ChessPiece::King(_) => 5,
+        };
+        let kind = piece_type * 2 + white;
+        let pos = BoardPos {
+            row: index / 8,
+            col: index % 8,
+        };
+        key ^= RANDOM64[64 * kind + 8 * polyglot_row(pos) + pos.col];
+    }
+    for (n, right) in ['K', 'Q', 'k', 'q'].into_iter().enumerate() {
+        if board.castling_rights().contains(right) {
+            key ^= RANDOM64[CASTLING_OFFSET + n];
+        }
+    }
+    // the en passant file only counts if a pawn can actually take en passant
+    if let Some(target) = board.en_passant_target() {
+        // the pawn which made the long start sits right beside any pawn able to take it
+        let row = match side {
+            Side::White => 3,
+            Side::Black => 4,
+        };
+        let can_capture = [target.col.checked_sub(1), Some(target.col + 1)]
+            .into_iter()
+            .flatten()
+            .filter(|&col| col < 8)
+            .any(|col| {
+                board
+                    .at(BoardPos { row, col })
+                    .is_some_and(|c| c.side() == Some(side) && is_pawn(c))
+            });
+        if can_capture {
+            key ^= RANDOM64[EN_PASSANT_OFFSET + target.col];
+        }
+    }
+    if side == Side::White {
+        key ^= RANDOM64[TURN_OFFSET];
+    }
+    key
+}
+
+fn is_pawn(cell: &ChessBoardCell) -> bool {
+    matches!(cell.take_piece(), Some(ChessPiece::Pawn(_)))
+}
+
+/// Polyglot stores castling as the king capturing its own rook
+fn decode_move(board: &ChessBoard, mv: u16) -> BoardMove {
+    let pos = |bits: u16| BoardPos {
+        row: 7 - ((bits >> 3) & 7) as usize,
+        col: (bits & 7) as usize,
+    };
+    let from = pos(mv >> 6);
+    let mut to = pos(mv);
+    let king = matches!(
+        board.at(from).and_then(|c| c.take_piece()),
+        Some(ChessPiece::King(false))
+    );
+    if king && from.col == 4 && to.row == from.row {
+        match to.col {
+            7 => to.col = 6,
+            0 => to.col = 2,
+            _ => (),
+        }
+    }
+    BoardMove::new(from, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A move in the Polyglot encoding, files and ranks counting from zero
+    fn encode(from: (u16, u16), to: (u16, u16)) -> u16 {
+        (from.1 << 9) | (from.0 << 6) | (to.1 << 3) | to.0
+    }
+
+    #[test]
+    fn keys_match_the_polyglot_reference() {
+        let mut board = ChessBoard::new_full();
+        assert_eq!(polyglot_key(&board, Side::White), 0x463b96181691fc9c);
+        let e2e4 = BoardMove::new(BoardPos { row: 6, col: 4 }, BoardPos { row: 4, col: 4 });
+        board.move_piece(e2e4).unwrap();
+        assert_eq!(polyglot_key(&board, Side::Black), 0x823c9b50fd114196);
+    }
+
+    #[test]
+    fn castling_is_decoded_from_the_king_taking_its_rook() {
+        let mut board = ChessBoard::new_empty();
+        let pieces = [
+            ((7, 4), ChessBoardCell::White(ChessPiece::King(false))),
+            ((7, 0), ChessBoardCell::White(ChessPiece::Rook)),
+            ((7, 7), ChessBoardCell::White(ChessPiece::Rook)),
+            ((0, 4), ChessBoardCell::Black(ChessPiece::King(false))),
+            ((0, 0), ChessBoardCell::Black(ChessPiece::Rook)),
+        ];
+        for ((row, col), cell) in pieces {
+            board.place_at(BoardPos { row, col }, cell).unwrap();
+        }
+        let mv = |from: (usize, usize), to: (usize, usize)| {
+            BoardMove::new(
+                BoardPos {
+                    row: from.0,
+                    col: from.1,
+                },
+                BoardPos {
+                    row: to.0,
+                    col: to.1,
+                },
+            )
+        };
+        assert_eq!(
+            decode_move(&board, encode((4, 0), (7, 0))),
+            mv((7, 4), (7, 6))
+        );
+        assert_eq!(
+            decode_move(&board, encode((4, 0), (0, 0))),
+            mv((7, 4), (7, 2))
+        );
+        assert_eq!(
+            decode_move(&board, encode((4, 7), (0, 7))),
+            mv((0, 4), (0, 2))
+        );
+        // a king which moved before does not castle any more
+        board
+            .place_at(
+                BoardPos { row: 7, col: 4 },
+                ChessBoardCell::White(ChessPiece::King(true)),
+            )
+            .unwrap();
+        assert_eq!(
+            decode_move(&board, encode((4, 0), (7, 0))),
+            mv((7, 4), (7, 7))
+        );
+    }
+}
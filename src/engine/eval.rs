@@ -0,0 +1,124 @@
+use crate::board::{ChessBoard, ChessBoardCell, ChessPiece, Side};
+
+pub const PAWN: i32 = 100;
+pub const KNIGHT: i32 = 320;
+pub const BISHOP: i32 = 330;
+pub const ROOK: i32 = 500;
+pub const QUEEN: i32 = 900;
+pub const KING: i32 = 20000;
+
+// Piece-square tables are laid out the same way as the board cells, row 0 being the black
+// back rank, and are written from the point of view of white
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+      0,  0,  0,  0,  0,  0,  0,  0,
+      5, 10, 10, 10, 10, 10, 10,  5,
+     -5,  0,  0,  0,  0,  0,  0, -5,
+     -5,  0,  0,  0,  0,  0,  0, -5,
+     -5,  0,  0,  0,  0,  0,  0, -5,
+     -5,  0,  0,  0,  0,  0,  0, -5,
+     -5,  0,  0,  0,  0,  0,  0, -5,
+      0,  0,  0,  5,  5,  0,  0,  0,
+];
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+pub fn piece_value(piece: ChessPiece) -> i32 {
+    match piece {
+        ChessPiece::Pawn(_) => PAWN,
+        ChessPiece::Knight => KNIGHT,
+        ChessPiece::Bishop => BISHOP,
+        ChessPiece::Rook => ROOK,
+        ChessPiece::Queen => QUEEN,
+        ChessPiece::King(_) => KING,
+    }
+}
+
+fn square_value(piece: ChessPiece, index: usize) -> i32 {
+    match piece {
+        ChessPiece::Pawn(_) => PAWN_TABLE[index],
+        ChessPiece::Knight => KNIGHT_TABLE[index],
+        ChessPiece::Bishop => BISHOP_TABLE[index],
+        ChessPiece::Rook => ROOK_TABLE[index],
+        ChessPiece::Queen => QUEEN_TABLE[index],
+        ChessPiece::King(_) => KING_TABLE[index],
+    }
+}
+
+/// Material and piece placement score in centipawns, positive values favour white
+pub fn evaluate(board: &ChessBoard) -> i32 {
+    let mut score = 0;
+    for (index, cell) in board.cells().iter().enumerate() {
+        match *cell {
+            ChessBoardCell::White(p) => score += piece_value(p) + square_value(p, index),
+            ChessBoardCell::Black(p) => {
+                // mirror the row so that the tables work for black as well
+                let mirrored = (7 - index / 8) * 8 + index % 8;
+                score -= piece_value(p) + square_value(p, mirrored)
+            }
+            ChessBoardCell::Empty => (),
+        }
+    }
+    score
+}
+
+/// Same as [`evaluate`] but from the point of view of `side`
+pub fn evaluate_for(board: &ChessBoard, side: Side) -> i32 {
+    match side {
+        Side::White => evaluate(board),
+        Side::Black => -evaluate(board),
+    }
+}
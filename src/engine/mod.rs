@@ -0,0 +1,434 @@
+use crate::board::{BoardMove, BoardMoveResult, ChessBoard, ChessBoardCell, ChessPiece, Side};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub mod analysis;
+pub mod background;
+pub mod book;
+pub mod eval;
+pub mod tablebase;
+pub mod uci;
+
+/// Score of a position in which the king can be captured, the game has no check detection so
+/// this is what a checkmate looks like to the search
+pub const MATE: i32 = 1_000_000;
+const INFINITY: i32 = 10_000_000;
+/// How many nodes are searched between checking the clock and the stop flag
+const CHECK_INTERVAL: u64 = 1024;
+pub const MAX_DEPTH: u8 = 64;
+/// Assumed number of moves left in the game when nobody says
+pub const MOVES_TO_GO: u32 = 30;
+
+/// Predefined strengths of the computer opponent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Level {
+    pub fn limits(self) -> SearchLimits {
+        match self {
+            Level::Easy => SearchLimits {
+                depth: 1,
+                movetime: None,
+            },
+            Level::Medium => SearchLimits {
+                depth: 3,
+                movetime: Some(Duration::from_secs(1)),
+            },
+            Level::Hard => SearchLimits {
+                depth: MAX_DEPTH,
+                movetime: Some(Duration::from_secs(3)),
+            },
+        }
+    }
+    pub fn next(self) -> Self {
+        match self {
+            Level::Easy => Level::Medium,
+            Level::Medium => Level::Hard,
+            Level::Hard => Level::Easy,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Easy => "Easy",
+            Level::Medium => "Medium",
+            Level::Hard => "Hard",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Level::Easy),
+            "medium" => Ok(Level::Medium),
+            "hard" => Ok(Level::Hard),
+            _ => anyhow::bail!("unknown level '{s}'"),
+        }
+    }
+}
+
+/// When the search has to stop, whichever limit is hit first wins
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchLimits {
+    pub depth: u8,
+    pub movetime: Option<Duration>,
+}
+
+impl SearchLimits {
+    /// Search for a fixed amount of time with no depth limit
+    pub fn movetime(time: Duration) -> Self {
+        Self {
+            depth: MAX_DEPTH,
+            movetime: Some(time),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchResult {
+    pub best: BoardMove,
+    /// centipawns from the point of view of the side to move
+    pub score: i32,
+    pub depth: u8,
+    pub nodes: u64,
+}
+
+/// Searches for the best move of `side` using iterative deepening alpha-beta
+/// # Returns
+/// `None` if `side` has no moves at all
+pub fn search(board: &ChessBoard, side: Side, limits: &SearchLimits) -> Option<SearchResult> {
+    Searcher::new(limits, Arc::new(AtomicBool::new(false))).run(board, side, limits.depth, |_| ())
+}
+
+/// Same as [`search`] but can be cut short from another thread by setting `stop`. The result of
+/// every finished iteration is passed to `report`.
+pub fn search_with_stop(
+    board: &ChessBoard,
+    side: Side,
+    limits: &SearchLimits,
+    stop: Arc<AtomicBool>,
+    report: impl FnMut(&SearchResult),
+) -> Option<SearchResult> {
+    Searcher::new(limits, stop).run(board, side, limits.depth, report)
+}
+
+/// How long to think about a move with `time` left on the clock
+pub fn time_budget(time: Duration, increment: Duration, moves_to_go: u32) -> Duration {
+    // never plan to use more than half of what is left on the clock
+    let budget = (time / moves_to_go.max(1) + increment / 2).min(time / 2);
+    budget.max(Duration::from_millis(1))
+}
+
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() >= MATE - MAX_DEPTH as i32
+}
+
+struct Searcher {
+    deadline: Option<Instant>,
+    stop: Arc<AtomicBool>,
+    nodes: u64,
+    aborted: bool,
+    /// the first iteration always runs to completion so there is a move to play
+    can_abort: bool,
+}
+
+impl Searcher {
+    fn new(limits: &SearchLimits, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            deadline: limits.movetime.map(|t| Instant::now() + t),
+            stop,
+            nodes: 0,
+            aborted: false,
+            can_abort: false,
+        }
+    }
+
+    fn run(
+        &mut self,
+        board: &ChessBoard,
+        side: Side,
+        max_depth: u8,
+        mut report: impl FnMut(&SearchResult),
+    ) -> Option<SearchResult> {
+        let mut root = board
+            .moves_for(side)
+            .into_iter()
+            .map(|m| (m, -INFINITY))
+            .collect::<Vec<_>>();
+        if root.is_empty() {
+            return None;
+        }
+        order_moves(board, &mut root);
+        let mut best: Option<SearchResult> = None;
+        for depth in 1..=max_depth.max(1) {
+            let mut alpha = -INFINITY;
+            for entry in root.iter_mut() {
+                let mut next = board.clone();
+                let Some(res) = next.move_piece(entry.0) else {
+                    continue;
+                };
+                let score = if captures_king(&res) {
+                    MATE
+                } else {
+                    -self.negamax(&next, side.opposite(), depth - 1, 1, -INFINITY, -alpha)
+                };
+                if self.aborted {
+                    break;
+                }
+                entry.1 = score;
+                alpha = alpha.max(score);
+            }
+            if self.aborted {
+                break;
+            }
+            // best moves of this iteration are searched first in the next one
+            root.sort_by_key(|e| std::cmp::Reverse(e.1));
+            let result = SearchResult {
+                best: root[0].0,
+                score: root[0].1,
+                depth,
+                nodes: self.nodes,
+            };
+            report(&result);
+            best = Some(result);
+            if is_mate_score(root[0].1) {
+                break;
+            }
+            self.can_abort = true;
+        }
+        best
+    }
+
+    fn negamax(
+        &mut self,
+        board: &ChessBoard,
+        side: Side,
+        depth: u8,
+        ply: u8,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiesce(board, side, ply, alpha, beta);
+        }
+        let mut moves = board
+            .moves_for(side)
+            .into_iter()
+            .map(|m| (m, 0))
+            .collect::<Vec<_>>();
+        if moves.is_empty() {
+            return 0;
+        }
+        order_moves(board, &mut moves);
+        for (m, _) in moves {
+            let mut next = board.clone();
+            let Some(res) = next.move_piece(m) else {
+                continue;
+            };
+            let score = if captures_king(&res) {
+                MATE - ply as i32
+            } else {
+                -self.negamax(&next, side.opposite(), depth - 1, ply + 1, -beta, -alpha)
+            };
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    /// Only looks at captures so that the evaluation is not taken in the middle of an exchange
+    fn quiesce(
+        &mut self,
+        board: &ChessBoard,
+        side: Side,
+        ply: u8,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        let stand_pat = eval::evaluate_for(board, side);
+        if stand_pat >= beta || ply >= MAX_DEPTH {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+        let mut captures = board
+            .moves_for(side)
+            .into_iter()
+            .filter(|m| {
+                board
+                    .at(m.to())
+                    .is_some_and(|c| *c != ChessBoardCell::Empty)
+            })
+            .map(|m| (m, 0))
+            .collect::<Vec<_>>();
+        order_moves(board, &mut captures);
+        for (m, _) in captures {
+            let mut next = board.clone();
+            let Some(res) = next.move_piece(m) else {
+                continue;
+            };
+            let score = if captures_king(&res) {
+                MATE - ply as i32
+            } else {
+                -self.quiesce(&next, side.opposite(), ply + 1, -beta, -alpha)
+            };
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    fn should_stop(&mut self) -> bool {
+        self.nodes += 1;
+        if self.can_abort && self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            let out_of_time = self.deadline.is_some_and(|d| Instant::now() >= d);
+            if out_of_time || self.stop.load(Ordering::Relaxed) {
+                self.aborted = true;
+            }
+        }
+        self.aborted
+    }
+}
+
+fn captures_king(res: &BoardMoveResult) -> bool {
+    res.pieces_deleted
+        .iter()
+        .any(|c| matches!(c.take_piece(), Some(ChessPiece::King(_))))
+}
+
+/// Most valuable victim, least valuable attacker. Quiet moves keep their relative order.
+fn order_moves(board: &ChessBoard, moves: &mut [(BoardMove, i32)]) {
+    moves.sort_by_cached_key(|(m, _)| {
+        let victim = board
+            .at(m.to())
+            .and_then(|c| c.take_piece())
+            .map(eval::piece_value)
+            .unwrap_or(0);
+        let attacker = board
+            .at(m.from())
+            .and_then(|c| c.take_piece())
+            .map(eval::piece_value)
+            .unwrap_or(0);
+        if victim > 0 {
+            -(victim * 10 - attacker / 10)
+        } else {
+            0
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::BoardPos;
+
+    /// A board with just `pieces` on it, given as row and column
+    fn board(pieces: &[((usize, usize), ChessBoardCell)]) -> ChessBoard {
+        let mut board = ChessBoard::new_empty();
+        for &((row, col), cell) in pieces {
+            board.place_at(BoardPos { row, col }, cell).unwrap();
+        }
+        board
+    }
+
+    fn mv(from: (usize, usize), to: (usize, usize)) -> BoardMove {
+        BoardMove::new(
+            BoardPos {
+                row: from.0,
+                col: from.1,
+            },
+            BoardPos {
+                row: to.0,
+                col: to.1,
+            },
+        )
+    }
+
+    fn depth(depth: u8) -> SearchLimits {
+        SearchLimits {
+            depth,
+            movetime: None,
+        }
+    }
+
+    #[test]
+    fn hanging_queens_are_taken() {
+        let board = board(&[
+            ((7, 6), ChessBoardCell::White(ChessPiece::King(true))),
+            ((7, 3), ChessBoardCell::White(ChessPiece::Rook)),
+            ((3, 3), ChessBoardCell::Black(ChessPiece::Queen)),
+            ((0, 6), ChessBoardCell::Black(ChessPiece::King(true))),
+        ]);
+        let res = search(&board, Side::White, &depth(3)).unwrap();
+        assert_eq!(res.best, mv((7, 3), (3, 3)));
+        assert!(res.score > 0);
+    }
+
+    #[test]
+    fn kings_are_taken_before_anything_else() {
+        let board = board(&[
+            ((7, 7), ChessBoardCell::White(ChessPiece::King(true))),
+            ((7, 0), ChessBoardCell::White(ChessPiece::Rook)),
+            // the queen hangs as well, but taking the king ends the game
+            ((5, 2), ChessBoardCell::White(ChessPiece::Bishop)),
+            ((0, 7), ChessBoardCell::Black(ChessPiece::Queen)),
+            ((0, 0), ChessBoardCell::Black(ChessPiece::King(true))),
+        ]);
+        let res = search(&board, Side::White, &depth(4)).unwrap();
+        assert_eq!(res.best, mv((7, 0), (0, 0)));
+        assert_eq!(res.score, MATE);
+        // there is no point in looking deeper
+        assert_eq!(res.depth, 1);
+    }
+
+    #[test]
+    fn stopped_searches_play_their_last_finished_iteration() {
+        let board = ChessBoard::new_full();
+        let stop = Arc::new(AtomicBool::new(true));
+        let mut reported = 0;
+        let res = search_with_stop(&board, Side::White, &depth(MAX_DEPTH), stop, |_| {
+            reported += 1
+        })
+        .unwrap();
+        // the first iteration always finishes so that there is a move, the flag is only looked at
+        // every few nodes, which lets the short second one finish as well
+        assert!(res.depth <= 2);
+        assert_eq!(reported, res.depth);
+        assert!(board.moves_for(Side::White).contains(&res.best));
+
+        // and one stopped from another thread ends well before its depth
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopper = stop.clone();
+        let start = Instant::now();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            stopper.store(true, Ordering::Relaxed);
+        });
+        let res = search_with_stop(&board, Side::White, &depth(MAX_DEPTH), stop, |_| ()).unwrap();
+        thread.join().unwrap();
+        assert!(res.depth < MAX_DEPTH);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
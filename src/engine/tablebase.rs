@@ -0,0 +1,278 @@
+//! Endgame tablebases in the Syzygy format, probed for perfect play once few pieces are left
+
+use crate::board::{BoardMove, ChessBoard, ChessBoardCell, ChessPiece, Side};
+use anyhow::{bail, Result};
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess};
+use shakmaty_syzygy::{AmbiguousWdl, MaybeRounded};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Result with perfect play for the side to move. The game has no fifty move rule, so the wins
+/// and losses the rule would turn into draws still count as such.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbOutcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+impl TbOutcome {
+    fn flip(self) -> Self {
+        match self {
+            TbOutcome::Win => TbOutcome::Loss,
+            TbOutcome::Draw => TbOutcome::Draw,
+            TbOutcome::Loss => TbOutcome::Win,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Probe {
+    pub outcome: TbOutcome,
+    /// plies until the next capture or pawn move on the way to the outcome
+    pub dtz: u32,
+    /// some tables keep the distance halved, it may then be one ply longer
+    pub rounded: bool,
+}
+
+impl Probe {
+    /// Like `White wins in 12` or `Draw`, where the count is the distance to the next capture or
+    /// pawn move
+    pub fn describe(&self, side: Side) -> String {
+        let winner = match (self.outcome, side) {
+            (TbOutcome::Draw, _) => return "Draw".to_owned(),
+            (TbOutcome::Win, Side::White) | (TbOutcome::Loss, Side::Black) => "White",
+            _ => "Black",
+        };
+        if self.rounded {
+            format!("{winner} wins in {} or {}", self.dtz, self.dtz + 1)
+        } else {
+            format!("{winner} wins in {}", self.dtz)
+        }
+    }
+}
+
+/// What the tables say about a position, with the best move unless the side to move has none
+pub type TablebaseHit = (Option<BoardMove>, Probe);
+
+pub struct Tablebase {
+    tables: shakmaty_syzygy::Tablebase<Chess>,
+}
+
+impl Tablebase {
+    /// Adds every `.rtbw` and `.rtbz` file of the directory
+    pub fn open(dir: &Path) -> Result<Self> {
+        let mut tables = shakmaty_syzygy::Tablebase::new();
+        if tables.add_directory(dir)? == 0 {
+            bail!("no tablebase files in {}", dir.display());
+        }
+        Ok(Self { tables })
+    }
+    /// The most pieces, kings included, a position may have to be found in the tables
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+    /// # Returns
+    /// `None` when the position has too many pieces, the tables for it are missing, a king can
+    /// be taken or castling is still possible
+    pub fn probe(&self, board: &ChessBoard, side: Side) -> Option<Probe> {
+        let pos = self.position(board, side)?;
+        // takes the halfmove clock of the position into account, which the board keeps at 0
+        let wdl = self.tables.probe_wdl(&pos).ok()?;
+        let (dtz, rounded) = match self.tables.probe_dtz(&pos).ok()? {
+            MaybeRounded::Precise(dtz) => (dtz, false),
+            MaybeRounded::Rounded(dtz) => (dtz, true),
+        };
+        // whether the fifty move rule would still allow the result makes no difference here
+        let outcome = match wdl {
+            AmbiguousWdl::Win | AmbiguousWdl::MaybeWin | AmbiguousWdl::CursedWin => TbOutcome::Win,
+            AmbiguousWdl::Draw => TbOutcome::Draw,
+            AmbiguousWdl::Loss | AmbiguousWdl::MaybeLoss | AmbiguousWdl::BlessedLoss => {
+                TbOutcome::Loss
+            }
+        };
+        Some(Probe {
+            outcome,
+            dtz: dtz.0.unsigned_abs(),
+            rounded,
+        })
+    }
+    /// Wins as fast as possible and holds out as long as possible when losing, measured by the
+    /// distance to the next capture or pawn move
+    pub fn best_move(&self, board: &ChessBoard, side: Side) -> Option<(BoardMove, Probe)> {
+        self.position(board, side)?;
+        let mut best: Option<(BoardMove, Probe)> = None;
+        for m in board.moves_for(side) {
+            let pawn = matches!(
+                board.at(m.from()).and_then(|c| c.take_piece()),
+                Some(ChessPiece::Pawn(_))
+            );
+            let mut after = board.clone();
+            let Some(res) = after.move_piece(m) else {
+                continue;
+            };
+            // moves which leave the king to be taken do not make valid positions
+            let Some(reply) = self.probe(&after, side.opposite()) else {
+                continue;
+            };
+            let zeroing = pawn || !res.pieces_deleted.is_empty();
+            let probe = Probe {
+                outcome: reply.outcome.flip(),
+                dtz: if zeroing { 1 } else { reply.dtz + 1 },
+                rounded: !zeroing && reply.rounded,
+            };
+            if best.is_none_or(|(_, b)| rank(probe) > rank(b)) {
+                best = Some((m, probe));
+            }
+        }
+        best
+    }
+    /// The best move and the outcome, or only the outcome when the side to move has no move
+    pub fn lookup(&self, board: &ChessBoard, side: Side) -> Option<TablebaseHit> {
+        self.best_move(board, side)
+            .map(|(m, probe)| (Some(m), probe))
+            .or_else(|| self.probe(board, side).map(|probe| (None, probe)))
+    }
+    fn position(&self, board: &ChessBoard, side: Side) -> Option<Chess> {
+        let pieces = board
+            .cells()
+            .iter()
+            .filter(|c| **c != ChessBoardCell::Empty)
+            .count();
+        if pieces > self.max_pieces() {
+            return None;
+        }
+        let fen = board.to_fen(side).parse::<Fen>().ok()?;
+        fen.into_position(CastlingMode::Standard).ok()
+    }
+}
+
+/// Higher is better for the side making the move. A rounded distance counts as the longer one
+/// for a win and as the shorter one for a loss, so that no move is taken for better than it
+/// surely is.
+fn rank(probe: Probe) -> (i8, i64) {
+    match probe.outcome {
+        TbOutcome::Win => (1, -(probe.dtz as i64 + probe.rounded as i64)),
+        TbOutcome::Draw => (0, 0),
+        TbOutcome::Loss => (-1, probe.dtz as i64),
+    }
+}
+
+/// A lookup made on a thread of its own, since probing reads the table files
+pub struct BackgroundProbe {
+    latest: Arc<Mutex<Option<TablebaseHit>>>,
+}
+
+impl BackgroundProbe {
+    pub fn start(tablebase: Arc<Tablebase>, board: ChessBoard, side: Side) -> Self {
+        let latest = Arc::new(Mutex::new(None));
+        let thread_latest = latest.clone();
+        std::thread::spawn(move || {
+            let hit = tablebase.lookup(&board, side);
+            *thread_latest.lock().unwrap() = hit;
+        });
+        Self { latest }
+    }
+    /// `None` until the lookup is done, and for positions the tables do not know
+    pub fn latest(&self) -> Option<TablebaseHit> {
+        *self.latest.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Where the 3-piece tables are put for the tests, which skip themselves without them
+    fn fixture() -> Option<Tablebase> {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/syzygy");
+        if !["KQvK.rtbw", "KQvK.rtbz", "KNvK.rtbw", "KNvK.rtbz"]
+            .iter()
+            .all(|f| dir.join(f).is_file())
+        {
+            eprintln!("no 3-piece tables in {}, skipping", dir.display());
+            return None;
+        }
+        Some(Tablebase::open(&dir).unwrap())
+    }
+
+    fn board(fen: &str) -> (ChessBoard, Side) {
+        ChessBoard::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn directory_without_tables_is_an_error() {
+        assert!(Tablebase::open(&std::env::temp_dir().join("pawn_hearts_no_tables")).is_err());
+    }
+
+    #[test]
+    fn queen_wins_for_either_side_to_move() {
+        let Some(tb) = fixture() else {
+            return;
+        };
+        let (b, side) = board("8/8/8/4k3/8/8/8/3QK3 w - - 0 1");
+        let probe = tb.probe(&b, side).unwrap();
+        assert_eq!(probe.outcome, TbOutcome::Win);
+        assert!(probe.dtz > 0);
+        assert!(probe.describe(side).starts_with("White wins in"));
+        let probe = tb.probe(&b, side.opposite()).unwrap();
+        assert_eq!(probe.outcome, TbOutcome::Loss);
+        assert!(probe.describe(side.opposite()).starts_with("White wins in"));
+    }
+
+    #[test]
+    fn lone_knight_draws() {
+        let Some(tb) = fixture() else {
+            return;
+        };
+        let (b, side) = board("8/8/8/4k3/8/8/8/4KN2 w - - 0 1");
+        assert_eq!(tb.probe(&b, side).unwrap().outcome, TbOutcome::Draw);
+        assert_eq!(tb.probe(&b, side).unwrap().describe(side), "Draw");
+    }
+
+    #[test]
+    fn best_move_keeps_the_win() {
+        let Some(tb) = fixture() else {
+            return;
+        };
+        let (b, side) = board("8/8/8/4k3/8/8/8/3QK3 w - - 0 1");
+        let before = tb.probe(&b, side).unwrap();
+        let (m, probe) = tb.best_move(&b, side).unwrap();
+        assert_eq!(probe.outcome, TbOutcome::Win);
+        let mut after = b.clone();
+        after.move_piece(m).unwrap();
+        let reply = tb.probe(&after, side.opposite()).unwrap();
+        assert_eq!(reply.outcome, TbOutcome::Loss);
+        // nothing is taken, so the distance goes down by the move
+        assert_eq!(reply.dtz + 1, probe.dtz);
+        assert!(probe.dtz <= before.dtz + before.rounded as u32);
+    }
+
+    #[test]
+    fn positions_beyond_the_tables_are_not_probed() {
+        let Some(tb) = fixture() else {
+            return;
+        };
+        let (b, side) = (ChessBoard::new_full(), Side::White);
+        assert_eq!(tb.probe(&b, side), None);
+        assert_eq!(tb.lookup(&b, side), None);
+    }
+
+    #[test]
+    fn background_probe_finds_the_same() {
+        let Some(tb) = fixture() else {
+            return;
+        };
+        let tb = Arc::new(tb);
+        let (b, side) = board("8/8/8/4k3/8/8/8/3QK3 w - - 0 1");
+        let expected = tb.lookup(&b, side);
+        let probe = BackgroundProbe::start(tb, b, side);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while probe.latest().is_none() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(probe.latest(), expected);
+    }
+}
@@ -0,0 +1,237 @@
+//! Headless mode which speaks UCI on the standard input and output, so that the engine can be
+//! used from chess GUIs like Cute Chess
+
+use super::book::Book;
+use super::tablebase::Tablebase;
+use super::{SearchLimits, SearchResult, MATE, MAX_DEPTH, MOVES_TO_GO};
+use crate::board::{BoardMove, ChessBoard, Side};
+use anyhow::{anyhow, Result};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+struct RunningSearch {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl RunningSearch {
+    /// Stops the search and waits for it to print its best move
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // a panicking search has nothing left to report
+        let _ = self.handle.join();
+    }
+}
+
+/// What the last `go` is busy with
+enum Thinking {
+    Search(RunningSearch),
+    /// a book or tablebase move found for `go infinite` or `go ponder`, which may only be
+    /// answered once the GUI says `stop` or `ponderhit`
+    Held(BoardMove, ChessBoard),
+}
+
+fn stop_search(search: &mut Option<Thinking>) {
+    match search.take() {
+        Some(Thinking::Search(s)) => s.stop(),
+        Some(Thinking::Held(m, board)) => println!("bestmove {}", m.to_uci(&board)),
+        None => (),
+    }
+}
+
+/// # Arguments
+/// * `book` - opening book to play from, the GUI can replace it with the `BookFile` option
+/// * `tablebase` - endgame tables to play from, the GUI can replace them with `SyzygyPath`
+pub fn run(mut book: Option<Arc<Book>>, mut tablebase: Option<Arc<Tablebase>>) -> Result<()> {
+    let mut board = ChessBoard::new_full();
+    let mut side = Side::White;
+    let mut search: Option<Thinking> = None;
+    let mut own_book = true;
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                println!("id name Pawn Hearts {}", env!("CARGO_PKG_VERSION"));
+                println!("id author JakubCygaro");
+                println!("option name OwnBook type check default true");
+                println!("option name BookFile type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("ucinewgame") => {
+                stop_search(&mut search);
+                board = ChessBoard::new_full();
+                side = Side::White;
+            }
+            Some("position") => {
+                stop_search(&mut search);
+                match parse_position(tokens.collect()) {
+                    Ok((b, s)) => (board, side) = (b, s),
+                    Err(e) => println!("info string {e}"),
+                }
+            }
+            Some("setoption") => match parse_option(tokens.collect())
+                .as_ref()
+                .map(|(n, v)| (n.as_str(), v.as_str()))
+            {
+                Some(("OwnBook", value)) => own_book = value == "true",
+                Some(("BookFile", "<empty>" | "")) => book = None,
+                Some(("BookFile", path)) => match Book::open(path.as_ref()) {
+                    Ok(b) => book = Some(Arc::new(b)),
+                    Err(e) => println!("info string could not read book '{path}': {e}"),
+                },
+                Some(("SyzygyPath", "<empty>" | "")) => tablebase = None,
+                Some(("SyzygyPath", dir)) => match Tablebase::open(dir.as_ref()) {
+                    Ok(t) => tablebase = Some(Arc::new(t)),
+                    Err(e) => println!("info string could not read tablebases '{dir}': {e}"),
+                },
+                _ => (),
+            },
+            Some("go") => {
+                stop_search(&mut search);
+                let params = tokens.collect::<Vec<_>>();
+                let book_move = book
+                    .as_ref()
+                    .filter(|_| own_book)
+                    .and_then(|b| b.pick(&board, side));
+                let known = match book_move {
+                    Some(m) => {
+                        println!("info string book move");
+                        Some(m)
+                    }
+                    None => tablebase
+                        .as_ref()
+                        .and_then(|t| t.best_move(&board, side))
+                        .map(|(m, probe)| {
+                            println!("info string tablebase {}", probe.describe(side));
+                            m
+                        }),
+                };
+                if let Some(m) = known {
+                    search = Some(Thinking::Held(m, board.clone()));
+                    if !params.iter().any(|p| matches!(*p, "infinite" | "ponder")) {
+                        stop_search(&mut search);
+                    }
+                    continue;
+                }
+                let limits = parse_go(params, side);
+                search = Some(Thinking::Search(start_search(board.clone(), side, limits)));
+            }
+            Some("stop") => stop_search(&mut search),
+            // a search goes on until it is done, only a move which is already known waits
+            Some("ponderhit") if matches!(search, Some(Thinking::Held(..))) => {
+                stop_search(&mut search)
+            }
+            Some("quit") => break,
+            _ => (),
+        }
+    }
+    stop_search(&mut search);
+    Ok(())
+}
+
+fn start_search(board: ChessBoard, side: Side, limits: SearchLimits) -> RunningSearch {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    let handle = std::thread::spawn(move || {
+        let start = Instant::now();
+        let res = super::search_with_stop(&board, side, &limits, thread_stop, |res| {
+            println!("{}", info_line(&board, res, start.elapsed()));
+        });
+        match res {
+            Some(res) => println!("bestmove {}", res.best.to_uci(&board)),
+            // the null move, there is nothing to play
+            None => println!("bestmove 0000"),
+        }
+    });
+    RunningSearch { stop, handle }
+}
+
+fn info_line(board: &ChessBoard, res: &SearchResult, elapsed: Duration) -> String {
+    let score = if super::is_mate_score(res.score) {
+        // the search sees a mate as the capture of the king one move later
+        let moves = ((MATE - res.score.abs()) / 2).max(1);
+        format!("mate {}", moves * res.score.signum())
+    } else {
+        format!("cp {}", res.score)
+    };
+    format!(
+        "info depth {} score {score} nodes {} time {} pv {}",
+        res.depth,
+        res.nodes,
+        elapsed.as_millis(),
+        res.best.to_uci(board)
+    )
+}
+
+/// Reads `name <name> [value <value>]`, both of which may contain spaces
+fn parse_option(tokens: Vec<&str>) -> Option<(String, String)> {
+    let line = tokens.join(" ");
+    let rest = line.strip_prefix("name ")?;
+    let (name, value) = rest.split_once(" value ").unwrap_or((rest, ""));
+    Some((name.trim().to_owned(), value.trim().to_owned()))
+}
+
+/// Reads `startpos|fen <fen> [moves <move>...]`
+fn parse_position(tokens: Vec<&str>) -> Result<(ChessBoard, Side)> {
+    let moves_at = tokens.iter().position(|t| *t == "moves");
+    let (setup, moves) = match moves_at {
+        Some(at) => (&tokens[..at], &tokens[at + 1..]),
+        None => (&tokens[..], &[][..]),
+    };
+    let (mut board, mut side) = match setup {
+        ["startpos"] => (ChessBoard::new_full(), Side::White),
+        ["fen", fen @ ..] => ChessBoard::from_fen(&fen.join(" "))?,
+        _ => return Err(anyhow!("invalid position command")),
+    };
+    for uci in moves {
+        let m = BoardMove::from_uci(uci).ok_or(anyhow!("unreadable move '{uci}'"))?;
+        board.move_piece(m).ok_or(anyhow!("invalid move '{uci}'"))?;
+        side = side.opposite();
+    }
+    Ok((board, side))
+}
+
+/// Turns the parameters of `go` into search limits, splitting the remaining clock time evenly
+/// over the moves left when no fixed time or depth is given
+fn parse_go(tokens: Vec<&str>, side: Side) -> SearchLimits {
+    let mut limits = SearchLimits {
+        depth: MAX_DEPTH,
+        movetime: None,
+    };
+    let (mut time, mut increment, mut moves_to_go) = (None, 0, MOVES_TO_GO);
+    let mut tokens = tokens.into_iter();
+    while let Some(name) = tokens.next() {
+        let mut value = || tokens.next().and_then(|v| v.parse::<u64>().ok());
+        match (name, side) {
+            ("depth", _) => {
+                if let Some(d) = value() {
+                    limits.depth = d.clamp(1, MAX_DEPTH as u64) as u8;
+                }
+            }
+            ("movetime", _) => limits.movetime = value().map(Duration::from_millis),
+            ("wtime", Side::White) | ("btime", Side::Black) => time = value(),
+            ("winc", Side::White) | ("binc", Side::Black) => increment = value().unwrap_or(0),
+            ("movestogo", _) => {
+                moves_to_go = value().map(|m| m.max(1) as u32).unwrap_or(MOVES_TO_GO);
+            }
+            ("wtime" | "btime" | "winc" | "binc", _) => {
+                value();
+            }
+            _ => (),
+        }
+    }
+    if let (None, Some(time)) = (limits.movetime, time) {
+        limits.movetime = Some(super::time_budget(
+            Duration::from_millis(time),
+            Duration::from_millis(increment),
+            moves_to_go,
+        ));
+    }
+    limits
+}
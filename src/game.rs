@@ -1,28 +1,49 @@
-use crate::board::{ChessBoardCell, ChessPiece};
+use crate::board::pgn::GameResult;
+use crate::board::{ChessBoardCell, ChessPiece, Side};
+use crate::clock::{self, ChessClock, TimeControl};
+use crate::engine::analysis::{self, GameReview, Judgement, ReviewJob};
+use crate::engine::background::{self, BackgroundSearch};
+use crate::engine::book::Book;
+use crate::engine::tablebase::{BackgroundProbe, Tablebase, TbOutcome};
+use crate::engine::{Level, SearchLimits, MATE, MAX_DEPTH};
 use crate::gui::{self, FontWrap};
+use crate::network::background::BackgroundConnection;
+use crate::network::bot::Bot;
 use crate::network::client::Client;
+use crate::network::discovery::{Announcement, Finder};
+use crate::network::handshake::{self, Features, HandshakeError, Hello};
 use crate::network::host::Host;
-use crate::network::{Connection, MessageQueue};
+use crate::network::lobby::GameId;
+use crate::network::relay::Relay;
+use crate::network::secure::Passphrase;
+use crate::network::uci::{UciConfig, UciEngine};
+use crate::network::{self, Connection, MessageQueue, Proposal};
+use crate::play::{self, Outcome};
+use crate::puzzle::{self, Attempt, Puzzle, Trainer};
 use crate::resources::meu_loader::MeurglisResourceLoader;
 
 use super::board::{self, BoardPos, MoveBuilder};
 use super::helpers;
 use super::resources::*;
 use raylib::{
-    ffi::{MouseButton, TraceLogLevel},
+    ffi::{KeyboardKey, MouseButton, TraceLogLevel},
     math::{Rectangle, Vector2},
-    prelude::{self as ray, color::Color, RaylibDraw},
+    prelude::{self as ray, color::Color, RaylibDraw, RaylibDrawHandle},
     RaylibHandle, RaylibThread,
 };
 use std::net::SocketAddr;
-use std::ops::Not;
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{path::PathBuf, str::FromStr};
 
 use super::{board::BoardMove, network::Message};
 ///
 ///Margin between the board and window borders
 const MARGIN: f32 = 0.1;
+/// Part of the window's width the panel with the chat and the clocks takes when there is one
+const PANEL_SHARE: f32 = 0.3;
+/// The setup screen lists no more games on the local network than this
+const MAX_LAN_GAMES: usize = 8;
 const OKRIMC: Color = Color {
     r: 133,
     g: 42,
@@ -41,7 +62,25 @@ const BACKC: Color = Color {
     b: 246,
     a: 255,
 };
+const HINTC: Color = Color {
+    r: 60,
+    g: 160,
+    b: 60,
+    a: 120,
+};
 const HEARTBEAT_T: Duration = Duration::from_mins(2);
+/// Used for the hints and the evaluation bar, deeper iterations keep refining the evaluation
+const ANALYSIS_LIMITS: SearchLimits = SearchLimits {
+    depth: MAX_DEPTH,
+    movetime: Some(Duration::from_secs(10)),
+};
+/// Pause before the opponent's move in a puzzle, so that the player sees it being made
+const PUZZLE_REPLY_T: Duration = Duration::from_millis(600);
+/// Every position of a reviewed game is searched to the same depth so that their scores compare
+const REVIEW_LIMITS: SearchLimits = SearchLimits {
+    depth: 4,
+    movetime: Some(Duration::from_secs(2)),
+};
 
 #[derive(Debug)]
 pub struct Selection {
@@ -49,9 +88,116 @@ pub struct Selection {
     taken_from: board::BoardPos,
 }
 #[derive(Debug)]
-pub struct RunArgs {
-    pub address: String,
-    pub is_host: bool,
+pub enum RunArgs {
+    Network {
+        address: String,
+        is_host: bool,
+        /// either side is fine when `None`
+        side: Option<Side>,
+    },
+    /// Play through a relay server, opening a game there or joining the one with the id
+    Relay {
+        server: String,
+        game: Option<GameId>,
+        side: Option<Side>,
+    },
+    /// Follow someone else's game on the host
+    Watch { address: String },
+    /// Play against the built-in computer opponent
+    Computer { limits: SearchLimits, side: Side },
+    /// Play against an external UCI engine
+    Engine { config: UciConfig, side: Side },
+    /// Solve tactics puzzles
+    Puzzles { puzzles: Vec<Puzzle> },
+    /// Both sides take turns on the same board
+    Local { auto_flip: bool },
+}
+
+/// Settings which apply regardless of who the opponent is
+#[derive(Default)]
+pub struct GameOptions {
+    /// Opening book used by the computer opponent and shown with the `B` key
+    pub book: Option<Arc<Book>>,
+    /// Endgame tables used by the computer opponent and the analysis
+    pub tablebase: Option<Arc<Tablebase>>,
+    /// Shown to the opponent in network games, the user name when not given
+    pub name: Option<String>,
+    /// Clocks for network games, the host's setting wins over the client's
+    pub time_control: Option<TimeControl>,
+    /// Network games are encrypted with it, the peer needs the same one
+    pub passphrase: Option<String>,
+}
+
+/// Post-game analysis of the moves played
+struct Review {
+    /// running until the analysis is done
+    job: Option<ReviewJob>,
+    result: Option<GameReview>,
+    outcome: GameResult,
+    /// the position shown is the one after this many moves
+    ply: usize,
+    /// describes the last move of the shown position
+    caption: String,
+    message: Option<String>,
+}
+
+/// What the buttons of a network game ask for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Control {
+    Resign,
+    Offer(Proposal),
+    /// whether the player agrees to the opponent's offer
+    Answer(bool),
+}
+
+/// Messages exchanged with the opponent of a network game
+struct Chat {
+    lines: Vec<ChatLine>,
+    /// the message being typed, sent with Enter
+    input: String,
+    /// the entry has the keyboard, so typing does not trigger the shortcuts
+    typing: bool,
+    /// the timestamps count from here
+    started: Instant,
+}
+
+struct ChatLine {
+    /// since the chat started
+    at: Duration,
+    author: String,
+    text: String,
+}
+
+impl Chat {
+    fn new() -> Self {
+        Self {
+            lines: vec![],
+            input: String::new(),
+            typing: false,
+            started: Instant::now(),
+        }
+    }
+    fn push(&mut self, author: &str, text: String) {
+        self.lines.push(ChatLine {
+            at: self.started.elapsed(),
+            author: author.to_owned(),
+            text,
+        });
+    }
+}
+
+impl std::fmt::Display for ChatLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.at.as_secs();
+        write!(
+            f,
+            "{:02}:{:02} {}: {}",
+            secs / 60,
+            secs % 60,
+            self.author,
+            self.text
+        )
+    }
 }
 
 pub struct Game {
@@ -64,7 +210,10 @@ pub struct Game {
     board_data: board::BoardRenderData,
     selected_piece: Option<Selection>,
     reversed: bool,
+    /// the host of a network game has the last word on what happened in it
     is_host: bool,
+    /// the pieces the player moves
+    side: Side,
     conn: Option<Box<dyn Connection>>,
     send_queue: MessageQueue,
     state: State,
@@ -72,6 +221,45 @@ pub struct Game {
     error_msg: Option<String>,
     last_network_t: Instant,
     next_heartbeat_t: Instant,
+    bot_level: Level,
+    book: Option<Arc<Book>>,
+    show_book: bool,
+    /// hints and the evaluation bar, only available against computer opponents
+    assists: bool,
+    /// the analysed position in FEN and the search running on it
+    analysis: Option<(String, BackgroundSearch)>,
+    tablebase: Option<Arc<Tablebase>>,
+    /// the last position probed in FEN, its side to move and the lookup in the tables
+    tablebase_probe: Option<(String, Side, BackgroundProbe)>,
+    show_hint: bool,
+    /// every move played since the start of the game
+    history: Vec<BoardMove>,
+    review: Option<Review>,
+    trainer: Option<Trainer>,
+    /// when the opponent makes their move in a puzzle
+    reply_t: Instant,
+    /// both sides play on this machine, `side` tells whose turn it is
+    hot_seat: bool,
+    /// turn the board towards whoever moves next in a hot seat game
+    auto_flip: bool,
+    /// the player's name in network games
+    name: String,
+    /// watching a network game, with white at the bottom and nothing to play
+    spectator: bool,
+    /// only in network games whose peer supports it, drawn to the right of the board
+    chat: Option<Chat>,
+    /// proposed when setting up a network game
+    time_control: Option<TimeControl>,
+    /// asked for when setting up a network game, either side is fine when `None`
+    side_choice: Option<Side>,
+    /// network games played with a time control, the host's is the one which counts
+    clock: Option<ChessClock>,
+    /// network games are encrypted with it unless it is empty
+    passphrase: String,
+    /// the setup screen's input edits the passphrase rather than the address
+    editing_passphrase: bool,
+    /// games announced on the local network, `None` when they cannot be listened for
+    lan: Option<Finder>,
 }
 #[derive(PartialEq, Clone, Debug)]
 enum State {
@@ -79,11 +267,20 @@ enum State {
     MovePending(BoardMove),
     WaitReply(BoardMove),
     WaitMove,
+    /// waiting for the opponent to answer an offer, the game goes on if they do not agree
+    Asking(Proposal),
+    /// the opponent's offer waits for the player's answer
+    Answering(Proposal),
     Won,
     Lost,
+    Drawn,
     SetupConnection,
     ConnectingHost,
     ConnectingClient,
+    /// following the game as a spectator
+    Watching,
+    Review,
+    PuzzleOver,
     FatalError,
 }
 
@@ -98,7 +295,7 @@ impl Game {
     pub fn should_close(&self) -> bool {
         self.window_handle.window_should_close()
     }
-    pub fn init(width: i32, height: i32, run_args: Option<RunArgs>) -> Self {
+    pub fn init(width: i32, height: i32, run_args: Option<RunArgs>, options: GameOptions) -> Self {
         let (mut window_handle, mut window_thread) = ray::init()
             .width(width)
             .height(height)
@@ -127,22 +324,103 @@ impl Game {
             )
         };
 
-        let is_host = run_args.as_ref().map(|ra| ra.is_host);
-
-        let conn: Option<Box<dyn Connection>> = match run_args {
-            Some(ra) => {
-                let c: Box<dyn Connection> = if ra.is_host {
-                    Box::new(Host::new(&ra.address).unwrap())
-                } else {
-                    Box::new(Client::new(&ra.address).unwrap())
+        let assists = matches!(
+            run_args,
+            Some(RunArgs::Computer { .. } | RunArgs::Engine { .. })
+        );
+        let mut trainer = None;
+        let (mut hot_seat, mut auto_flip) = (false, false);
+        let name = options.name.unwrap_or_else(handshake::default_name);
+        let spectator = matches!(run_args, Some(RunArgs::Watch { .. }));
+        let passphrase = options.passphrase.clone();
+        let mut error_msg = None;
+        let (conn, state): (Option<Box<dyn Connection>>, State) = match run_args {
+            Some(RunArgs::Network {
+                address,
+                is_host: true,
+                side,
+            }) => {
+                let hello = Hello::new(&name)
+                    .with_time_control(options.time_control)
+                    .with_side(side);
+                (
+                    Some(connect(passphrase, move |p| {
+                        Ok(Host::new(&address, hello)?.with_passphrase(p))
+                    })),
+                    State::ConnectingHost,
+                )
+            }
+            Some(RunArgs::Network {
+                address,
+                is_host: false,
+                side,
+            }) => {
+                let hello = Hello::new(&name)
+                    .with_time_control(options.time_control)
+                    .with_side(side);
+                (
+                    Some(connect(passphrase, move |p| {
+                        Ok(Client::new(&address, hello)?.with_passphrase(p))
+                    })),
+                    State::ConnectingClient,
+                )
+            }
+            Some(RunArgs::Relay { server, game, side }) => {
+                let hello = Hello::new(&name)
+                    .with_time_control(options.time_control)
+                    .with_side(side);
+                let state = match game {
+                    Some(_) => State::ConnectingClient,
+                    None => State::ConnectingHost,
                 };
-                Some(c)
+                let relay = connect(passphrase, move |p| {
+                    let relay = match game {
+                        Some(id) => Relay::join(&server, hello, id)?,
+                        None => Relay::open(&server, hello)?,
+                    };
+                    Ok(relay.with_passphrase(p))
+                });
+                (Some(relay), state)
+            }
+            Some(RunArgs::Watch { address }) => {
+                let hello = Hello::spectator(&name);
+                (
+                    Some(connect(passphrase, move |p| {
+                        Ok(Client::new(&address, hello)?.with_passphrase(p))
+                    })),
+                    State::ConnectingClient,
+                )
+            }
+            Some(RunArgs::Computer { limits, side }) => (
+                Some(Box::new(
+                    Bot::new(side.opposite(), limits)
+                        .with_book(options.book.clone())
+                        .with_tablebase(options.tablebase.clone()),
+                )),
+                local_opponent_state(side),
+            ),
+            Some(RunArgs::Engine { config, side }) => match UciEngine::new(config, side.opposite())
+            {
+                Ok(engine) => (Some(Box::new(engine)), local_opponent_state(side)),
+                // the setup screen says why, so that another opponent can be picked
+                Err(e) => {
+                    error_msg = Some(format!("{e:#}"));
+                    (None, State::SetupConnection)
+                }
+            },
+            Some(RunArgs::Puzzles { puzzles }) => {
+                trainer = Some(Trainer::new(puzzles, PathBuf::from(puzzle::STATS_FILE)));
+                (None, State::WaitMove)
             }
-            None => None,
+            Some(RunArgs::Local { auto_flip: flip }) => {
+                (hot_seat, auto_flip) = (true, flip);
+                (None, State::Move)
+            }
+            None => (None, State::SetupConnection),
         };
         let (min_width, min_height) = (width, height);
         window_handle.set_window_min_size(min_width, min_height);
-        Self {
+        let mut game = Self {
             board: board::ChessBoard::new_full(),
             window_handle,
             window_thread,
@@ -153,30 +431,60 @@ impl Game {
             selected_piece: None,
             reversed: false,
             is_host: true,
+            side: Side::White,
             conn,
-            state: is_host
-                .map(|ih| {
-                    if ih {
-                        State::ConnectingHost
-                    } else {
-                        State::ConnectingClient
-                    }
-                })
-                .unwrap_or(State::SetupConnection),
+            state,
             send_queue: MessageQueue::new(),
             input_text: String::from(""),
-            error_msg: None,
+            error_msg,
             last_network_t: Instant::now(),
             next_heartbeat_t: Instant::now(),
-        }
+            bot_level: Level::Medium,
+            book: options.book,
+            show_book: false,
+            assists,
+            analysis: None,
+            tablebase: options.tablebase,
+            tablebase_probe: None,
+            show_hint: false,
+            history: vec![],
+            review: None,
+            trainer,
+            reply_t: Instant::now(),
+            hot_seat,
+            auto_flip,
+            name,
+            spectator,
+            chat: None,
+            time_control: options.time_control,
+            side_choice: None,
+            clock: None,
+            passphrase: options.passphrase.unwrap_or_default(),
+            editing_passphrase: false,
+            lan: Finder::new()
+                .inspect_err(|e| eprintln!("cannot look for games on the network: {e}"))
+                .ok(),
+        };
+        game.next_puzzle();
+        game
     }
     pub fn update(&mut self) {
         if self.window_handle.is_window_resized() {
             self.resize();
         }
+        let typing = self.chat.as_ref().is_some_and(|c| c.typing);
+        if !typing && self.window_handle.is_key_pressed(KeyboardKey::KEY_B) {
+            self.show_book = !self.show_book;
+        }
+        if !typing && self.window_handle.is_key_pressed(KeyboardKey::KEY_H) {
+            self.show_hint = !self.show_hint;
+        }
         let mut msgs: Vec<Message> = vec![];
         if self.conn.is_some()
-            && !matches!(self.state, State::Won | State::Lost | State::FatalError)
+            && !matches!(
+                self.state,
+                State::Won | State::Lost | State::Drawn | State::FatalError
+            )
             && !self
                 .conn
                 .as_deref()
@@ -191,11 +499,33 @@ impl Game {
                         msgs.push(msg)
                     }
                 }
-                Err(e) => {
-                    self.error_msg = Some("Connection failure".to_owned());
-                    eprintln!("{e}");
-                    self.state = State::FatalError;
-                }
+                Err(e) => match e.downcast_ref::<HandshakeError>() {
+                    // the host goes on listening for a client it can play with
+                    Some(e) if self.state == State::ConnectingHost => {
+                        self.error_msg = Some(e.to_string());
+                    }
+                    Some(e) => {
+                        self.error_msg = Some(e.to_string());
+                        self.conn = None;
+                        self.state = State::SetupConnection;
+                    }
+                    // nothing was played yet, so the setup screen can have another go
+                    None if matches!(
+                        self.state,
+                        State::ConnectingClient | State::ConnectingHost
+                    ) =>
+                    {
+                        self.error_msg = Some(e.to_string());
+                        self.conn = None;
+                        self.spectator = false;
+                        self.state = State::SetupConnection;
+                    }
+                    None => {
+                        self.error_msg = Some("Connection failure".to_owned());
+                        eprintln!("{e}");
+                        self.state = State::FatalError;
+                    }
+                },
             }
             const TIMEOUT: Duration = Duration::from_mins(3);
             if self.last_network_t - Instant::now() > TIMEOUT {
@@ -223,39 +553,54 @@ impl Game {
                     self.state.clone()
                 }
             }
+            State::ConnectingClient
+                if self.spectator && self.conn.as_ref().unwrap().is_connected() =>
+            {
+                State::Watching
+            }
             State::ConnectingClient if self.conn.as_ref().unwrap().is_connected() => {
                 self.is_host = false;
-                self.reversed = true;
+                self.take_side(Side::Black);
                 self.next_heartbeat_t = Instant::now() + HEARTBEAT_T;
-                State::WaitMove
+                self.open_chat();
+                self.start_clock();
+                self.turn_state()
             }
             State::ConnectingHost if self.conn.as_ref().unwrap().is_connected() => {
+                self.error_msg = None;
+                self.take_side(Side::White);
                 self.next_heartbeat_t = Instant::now() + HEARTBEAT_T;
-                State::Move
+                self.open_chat();
+                self.start_clock();
+                self.turn_state()
             }
-            State::Won | State::Lost => {
-                let conn = self.conn.as_mut().unwrap();
-                if !conn.is_shutdown() {
+            State::Won | State::Lost | State::Drawn => {
+                if let Some(clock) = self.clock.as_mut() {
+                    clock.stop();
+                }
+                if let Some(conn) = self.conn.as_mut().filter(|c| !c.is_shutdown()) {
+                    // the last move and `GameDone` still have to reach the peer
+                    while let Some(m) = self.send_queue.pop_front() {
+                        conn.send(m);
+                    }
                     conn.shutdown();
                 }
                 self.state.clone()
             }
-            _ => self.state.clone(),
+            _ => self.check_flag().unwrap_or_else(|| self.state.clone()),
         };
         self.update_mouse();
+        self.update_analysis();
+        self.update_review();
+        self.update_tablebase();
+        self.update_puzzle();
+        self.update_chat();
 
         let mut msgs = vec![];
         while let Some(m) = self.send_queue.pop_front() {
             msgs.push(m)
         }
-        if self.conn.is_some()
-            && !self
-                .conn
-                .as_deref()
-                .map(Connection::is_shutdown)
-                .unwrap_or(true)
-        {
-            let conn = self.conn.as_mut().unwrap();
+        if let Some(conn) = self.conn.as_mut().filter(|c| !c.is_shutdown()) {
             for m in msgs {
                 conn.send(m);
             }
@@ -274,7 +619,44 @@ impl Game {
         }
     }
     fn handle_message(&mut self, msg: Message) -> Option<State> {
-        if self.is_host {
+        if let Message::Chat(text) = msg {
+            let author = self
+                .conn
+                .as_deref()
+                .and_then(Connection::peer)
+                .map_or("Opponent", |peer| &peer.name);
+            if let Some(chat) = self.chat.as_mut() {
+                chat.push(author, text);
+            }
+            return None;
+        }
+        if let Message::Position(plies, key) = msg {
+            self.check_position(plies as usize, key);
+            return None;
+        }
+        if let Message::Clock(white, black) = msg {
+            let running = Side::to_move_after(self.history.len());
+            if let Some(clock) = self.clock.as_mut() {
+                clock.set(white, black, running);
+            }
+            return None;
+        }
+        // the opponent moved instead of answering
+        if let (Message::Moved(_), State::Asking(_)) = (&msg, &self.state) {
+            self.state = self.turn_state();
+        }
+        if self.spectator {
+            self.handle_message_spectator(msg)
+        } else if matches!(
+            msg,
+            Message::Resign()
+                | Message::Offer(..)
+                | Message::Agree(_)
+                | Message::Decline()
+                | Message::Takeback(_)
+        ) {
+            self.handle_message_control(msg)
+        } else if self.is_host {
             self.handle_message_host(msg)
         } else {
             self.handle_message_client(msg)
@@ -283,15 +665,33 @@ impl Game {
     fn handle_message_host(&mut self, msg: Message) -> Option<State> {
         match (msg, &self.state) {
             (Message::Moved(m), State::WaitMove) => {
+                // the client's time ran out while its move was on the way
+                if let Some(end) = self.check_flag() {
+                    return Some(end);
+                }
+                if !play::accepts(&self.board, self.history.len(), m) {
+                    self.send_queue.push_back(Message::Rejected());
+                    return None;
+                }
                 self.send_queue.push_back(Message::Accepted());
-                self.statefull_move_piece(m)
-                    .inspect(|_| self.send_queue.push_back(Message::GameDone()))
+                let end = self.statefull_move_piece(m);
+                self.confirm_position();
+                self.press_clock();
+                end.inspect(|_| self.send_queue.push_back(Message::GameDone()))
                     .or(Some(State::Move))
             }
             (Message::Moved(_), _) => {
                 self.send_queue.push_back(Message::Rejected());
                 Some(State::Move)
             }
+            (Message::SyncRequest(), _) => {
+                self.send_queue
+                    .push_back(Message::Sync(self.history.clone()));
+                if let Some(clock) = &self.clock {
+                    self.send_queue.push_back(network::clock_message(clock));
+                }
+                None
+            }
             (_, _) => None,
         }
     }
@@ -301,6 +701,8 @@ impl Game {
                 .statefull_move_piece(m)
                 .inspect(|_| self.send_queue.push_back(Message::GameDone()))
                 .or(Some(State::Move)),
+            // the host did not take the move, the turn is still ours
+            (Message::Rejected(), State::WaitReply(_)) => Some(State::Move),
             (Message::Rejected(), _) => Some(State::WaitMove),
             (Message::Accepted(), State::WaitReply(m)) => self
                 .statefull_move_piece(*m)
@@ -310,10 +712,185 @@ impl Game {
                 self.statefull_move_piece(*m).or(Some(State::WaitMove))
             }
             (Message::GameDone(), _) => None,
+            (Message::Sync(moves), _) => Some(self.resync(moves)),
+            // the host's word, like when a flag fell
+            (Message::Ended(result), _) if self.side_to_move().is_some() => {
+                Some(self.result_state(result))
+            }
+            _ => None,
+        }
+    }
+
+    /// Both sides' moves come from the host once they are final, a won game is one white won
+    fn handle_message_spectator(&mut self, msg: Message) -> Option<State> {
+        match msg {
+            Message::Moved(m) => self.statefull_move_piece(m),
+            Message::Sync(moves) => {
+                self.start_clock();
+                Some(self.resync(moves))
+            }
+            Message::Ended(result) => Some(self.result_state(result)),
             _ => None,
         }
     }
 
+    /// Resignations and offers, which both ends handle alike except that the host has the last
+    /// word on takebacks
+    fn handle_message_control(&mut self, msg: Message) -> Option<State> {
+        let playing = self.side_to_move().is_some();
+        match (msg, &self.state) {
+            (Message::Resign(), _) if playing => Some(self.conclude(State::Won)),
+            (Message::Offer(proposal, plies), State::Move | State::WaitMove)
+                if plies as usize == self.history.len() =>
+            {
+                Some(State::Answering(proposal))
+            }
+            // a move crossed it or the client's move is still on its way
+            (Message::Offer(..), _) => {
+                self.send_queue.push_back(Message::Decline());
+                None
+            }
+            (Message::Agree(Proposal::Draw), State::Asking(Proposal::Draw)) => {
+                Some(self.conclude(State::Drawn))
+            }
+            // the host's word, whatever the client thought was going on
+            (Message::Agree(Proposal::Draw), _) if !self.is_host && playing => Some(State::Drawn),
+            (Message::Agree(Proposal::Takeback), State::Asking(Proposal::Takeback))
+                if self.is_host =>
+            {
+                Some(match self.takeback_target(self.side) {
+                    Some(plies) => self.take_back(plies),
+                    None => self.turn_state(),
+                })
+            }
+            (Message::Takeback(plies), _)
+                if !self.is_host && plies as usize <= self.history.len() =>
+            {
+                Some(self.take_back(plies as usize))
+            }
+            (Message::Decline(), State::Asking(_)) => Some(self.turn_state()),
+            _ => None,
+        }
+    }
+    /// What the buttons below the board ask for
+    fn control(&mut self, control: Control) {
+        match (control, self.state.clone()) {
+            (Control::Resign, _) => {
+                self.send_queue.push_back(Message::Resign());
+                self.state = self.conclude(State::Lost);
+            }
+            (Control::Offer(proposal), State::Move | State::WaitMove) => {
+                let plies = self.history.len() as u16;
+                self.send_queue.push_back(Message::Offer(proposal, plies));
+                self.state = State::Asking(proposal);
+            }
+            (Control::Answer(false), State::Answering(_)) => {
+                self.send_queue.push_back(Message::Decline());
+                self.state = self.turn_state();
+            }
+            (Control::Answer(true), State::Answering(Proposal::Draw)) => {
+                self.send_queue.push_back(Message::Agree(Proposal::Draw));
+                self.state = self.conclude(State::Drawn);
+            }
+            (Control::Answer(true), State::Answering(Proposal::Takeback)) if self.is_host => {
+                let opponent = self.side.opposite();
+                self.state = match self.takeback_target(opponent) {
+                    Some(plies) => self.take_back(plies),
+                    None => {
+                        self.send_queue.push_back(Message::Decline());
+                        self.turn_state()
+                    }
+                };
+            }
+            // nothing moves until the host's takeback arrives
+            (Control::Answer(true), State::Answering(Proposal::Takeback)) => {
+                self.send_queue
+                    .push_back(Message::Agree(Proposal::Takeback));
+                self.state = State::WaitMove;
+            }
+            _ => (),
+        }
+    }
+    /// Whether there is an opponent to resign to, offer a draw or ask for a takeback
+    fn has_controls(&self) -> bool {
+        !self.spectator && self.conn.as_deref().and_then(Connection::peer).is_some()
+    }
+    /// How many moves are left once the last one of `side` is taken back, so that it is their
+    /// turn again
+    fn takeback_target(&self, side: Side) -> Option<usize> {
+        play::takeback_target(self.history.len(), side)
+    }
+    /// Goes back to the first `plies` moves, the host tells the client to do the same
+    fn take_back(&mut self, plies: usize) -> State {
+        let state = self.resync(self.history[..plies].to_vec());
+        let clock = self.clock.as_ref();
+        self.send_queue
+            .extend(play::take_back(self.is_host, plies, clock));
+        self.confirm_position();
+        state
+    }
+    /// Ends a game in which no king was taken, the host tells spectators how
+    fn conclude(&mut self, state: State) -> State {
+        let outcome = match state {
+            State::Won => Outcome::Won,
+            State::Lost => Outcome::Lost,
+            _ => Outcome::Drawn,
+        };
+        let result = outcome.result(self.side);
+        self.send_queue.extend(play::conclude(self.is_host, result));
+        state
+    }
+    /// How a result the host announced turns out for this end, spectators see it from white's
+    /// side
+    fn result_state(&self, result: GameResult) -> State {
+        match Outcome::of(result, self.side) {
+            Outcome::Won => State::Won,
+            Outcome::Lost => State::Lost,
+            Outcome::Drawn => State::Drawn,
+        }
+    }
+    /// The host tells the client where the game stands after every final move, so that a client
+    /// which went astray notices
+    fn confirm_position(&mut self) {
+        if self.is_host && self.has_controls() {
+            let plies = self.history.len();
+            self.send_queue
+                .push_back(play::position(&self.board, plies));
+        }
+    }
+    /// Asks the host for the whole game when the client's position is not the host's
+    fn check_position(&mut self, plies: usize, key: u64) {
+        let mut board = self.board.clone();
+        // the board is missing the dragged piece
+        if let Some(s) = &self.selected_piece {
+            let _ = board.place_at(s.taken_from, s.piece);
+        }
+        let own = self.history.len();
+        self.send_queue
+            .extend(play::check_position(&board, own, plies, key));
+    }
+    /// The host stops the clock of the side which just moved and tells the client the times
+    fn press_clock(&mut self) {
+        if let Some(clock) = self
+            .clock
+            .as_mut()
+            .filter(|_| self.is_host && !self.spectator)
+        {
+            self.send_queue.push_back(play::press_clock(clock));
+        }
+    }
+    /// The host ends the game once the side to move is out of time
+    fn check_flag(&mut self) -> Option<State> {
+        let clock = self
+            .clock
+            .as_ref()
+            .filter(|_| self.is_host && !self.spectator)?;
+        let result = play::flag_fell(clock, &self.board, self.side_to_move()?)?;
+        self.send_queue.push_back(network::clock_message(clock));
+        let state = self.result_state(result);
+        Some(self.conclude(state))
+    }
+
     fn update_mouse(&mut self) {
         if self
             .window_handle
@@ -347,13 +924,13 @@ impl Game {
                 board::ChessBoardCell::Empty => {
                     self.selected_piece = None;
                 }
-                board::ChessBoardCell::White(_) if self.is_host => {
+                board::ChessBoardCell::White(_) if self.side == Side::White => {
                     self.selected_piece = Some(Selection {
                         piece: self.board.take_from(pos).unwrap(),
                         taken_from: pos,
                     });
                 }
-                board::ChessBoardCell::Black(_) if self.is_host.not() => {
+                board::ChessBoardCell::Black(_) if self.side == Side::Black => {
                     self.selected_piece = Some(Selection {
                         piece: self.board.take_from(pos).unwrap(),
                         taken_from: pos,
@@ -372,7 +949,28 @@ impl Game {
                 .place_at(selection.taken_from, selection.piece)
                 .unwrap();
 
-            if !self.is_host {
+            if let Some(trainer) = self.trainer.as_mut() {
+                let mut scratch = self.board.clone();
+                // moves which are not valid at all are just taken back
+                if scratch.move_piece(m).is_none() {
+                    return;
+                }
+                match trainer.attempt(m, m.promotion(&self.board)) {
+                    Attempt::Correct => {
+                        self.board = scratch;
+                        self.reply_t = Instant::now() + PUZZLE_REPLY_T;
+                        self.state = State::WaitMove;
+                    }
+                    Attempt::Solved => {
+                        self.board = scratch;
+                        self.state = State::PuzzleOver;
+                    }
+                    Attempt::Wrong(_) => self.state = State::PuzzleOver,
+                }
+                return;
+            }
+
+            if !self.is_host && !self.hot_seat {
                 // clone the board so that any changes occur only for the copy and dont modify the
                 // state of the real board (host does not care and performs their moves on the true
                 // board anyways)
@@ -381,8 +979,11 @@ impl Game {
                     self.state = State::MovePending(m);
                 }
             } else if let Some(result) = self.board.move_piece(m) {
+                self.history.push(m);
                 self.send_queue.push_back(Message::Moved(m));
-                match is_lost_or_won(self.is_host, &result.pieces_deleted) {
+                self.confirm_position();
+                self.press_clock();
+                match is_lost_or_won(self.side, &result.pieces_deleted) {
                     Some(EndCheck::Victory) => {
                         self.send_queue.push_back(Message::GameDone());
                         self.state = State::Won
@@ -391,12 +992,229 @@ impl Game {
                         self.send_queue.push_back(Message::GameDone());
                         self.state = State::Lost
                     }
+                    _ if self.hot_seat => {
+                        self.side = self.side.opposite();
+                        if self.auto_flip {
+                            self.reversed = self.side == Side::Black;
+                        }
+                    }
                     _ => self.state = State::WaitMove,
                 }
             }
         }
     }
 
+    /// Restarts the analysis whenever the position changes, a hint is only shown for the position
+    /// it was asked for
+    fn update_analysis(&mut self) {
+        // the board is missing the dragged piece
+        if self.selected_piece.is_some() {
+            return;
+        }
+        let side = match self.side_to_move() {
+            Some(side) if self.assists_on() => side,
+            _ => {
+                self.analysis = None;
+                return;
+            }
+        };
+        let fen = self.board.to_fen(side);
+        if self.analysis.as_ref().is_some_and(|(f, _)| *f == fen) {
+            return;
+        }
+        self.show_hint = false;
+        let search = BackgroundSearch::start(self.board.clone(), side, ANALYSIS_LIMITS);
+        self.analysis = Some((fen, search));
+    }
+
+    /// Looks the analysed or reviewed position up in the tables whenever it changes
+    fn update_tablebase(&mut self) {
+        let Some(tablebase) = self.tablebase.as_ref() else {
+            return;
+        };
+        if self.selected_piece.is_some() {
+            return;
+        }
+        let side = match (&self.state, &self.review) {
+            (State::Review, Some(review)) if review.result.is_some() => {
+                Some(if review.ply.is_multiple_of(2) {
+                    Side::White
+                } else {
+                    Side::Black
+                })
+            }
+            _ => self.side_to_move().filter(|_| self.assists_on()),
+        };
+        let Some(side) = side else {
+            self.tablebase_probe = None;
+            return;
+        };
+        let fen = self.board.to_fen(side);
+        if self
+            .tablebase_probe
+            .as_ref()
+            .is_some_and(|(f, _, _)| *f == fen)
+        {
+            return;
+        }
+        let probe = BackgroundProbe::start(tablebase.clone(), self.board.clone(), side);
+        self.tablebase_probe = Some((fen, side, probe));
+    }
+
+    /// Sets up the next puzzle with the player's side at the bottom
+    fn next_puzzle(&mut self) {
+        let Some(trainer) = self.trainer.as_mut() else {
+            return;
+        };
+        self.board = trainer.next_puzzle();
+        let player = trainer.puzzle().player();
+        self.side = player;
+        self.reversed = player == Side::Black;
+        self.reply_t = Instant::now() + PUZZLE_REPLY_T;
+        self.state = State::WaitMove;
+    }
+
+    /// Plays the opponent's moves of a puzzle
+    fn update_puzzle(&mut self) {
+        let Some(trainer) = self.trainer.as_mut() else {
+            return;
+        };
+        match self.state {
+            State::WaitMove if Instant::now() >= self.reply_t => {
+                if let Some(m) = trainer.reply() {
+                    self.board.move_piece(m);
+                }
+                self.state = State::Move;
+            }
+            State::PuzzleOver if self.window_handle.is_key_pressed(KeyboardKey::KEY_N) => {
+                self.next_puzzle();
+            }
+            _ => (),
+        }
+    }
+
+    fn start_review(&mut self) {
+        let outcome = match self.state {
+            State::Won => GameResult::won_by(self.side),
+            State::Lost => GameResult::won_by(self.side.opposite()),
+            State::Drawn => GameResult::Draw,
+            _ => GameResult::Unknown,
+        };
+        self.review = Some(Review {
+            job: Some(ReviewJob::start(self.history.clone(), REVIEW_LIMITS)),
+            result: None,
+            outcome,
+            ply: self.history.len(),
+            caption: String::new(),
+            message: None,
+        });
+        self.state = State::Review;
+    }
+
+    /// Stepping through the reviewed game with the arrow keys or by clicking the graph
+    fn update_review(&mut self) {
+        let (State::Review, Some(review)) = (&self.state, self.review.as_mut()) else {
+            return;
+        };
+        let mut changed = false;
+        if let Some(res) = review.job.as_ref().and_then(ReviewJob::poll) {
+            review.job = None;
+            match res {
+                Some(r) => review.result = Some(r),
+                None => review.message = Some("Analysis failed".to_owned()),
+            }
+            changed = true;
+        }
+        let Some(result) = &review.result else {
+            return;
+        };
+        let last = result.moves.len();
+        let mut ply = review.ply;
+        let handle = &self.window_handle;
+        if handle.is_key_pressed(KeyboardKey::KEY_LEFT) {
+            ply = ply.saturating_sub(1);
+        }
+        if handle.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+            ply = (ply + 1).min(last);
+        }
+        if handle.is_key_pressed(KeyboardKey::KEY_HOME) {
+            ply = 0;
+        }
+        if handle.is_key_pressed(KeyboardKey::KEY_END) {
+            ply = last;
+        }
+        if handle.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
+            let graph = graph_rect(&self.board_data);
+            if let Some(point) = helpers::check_point_on_rect(&graph, handle.get_mouse_position()) {
+                ply = ((point.x / graph.width) * last as f32).round() as usize;
+            }
+        }
+        if handle.is_key_pressed(KeyboardKey::KEY_E) {
+            review.message = Some(match export_review(result, review.outcome) {
+                Ok(path) => format!("Saved to {}", path.display()),
+                Err(e) => {
+                    eprintln!("{e}");
+                    "Could not save the PGN".to_owned()
+                }
+            });
+        }
+        if ply != review.ply || changed {
+            review.ply = ply;
+            review.caption = describe_ply(result, ply);
+            self.board = result.board_at(ply);
+        }
+    }
+
+    /// Makes room for the chat next to the board when the peer can take part in it
+    fn open_chat(&mut self) {
+        let supported = self
+            .conn
+            .as_deref()
+            .and_then(Connection::peer)
+            .is_some_and(|peer| peer.features.contains(Features::CHAT));
+        if supported && self.chat.is_none() {
+            self.chat = Some(Chat::new());
+            self.update_board_data();
+        }
+    }
+    /// Starts the clocks when both ends agreed on a time control
+    fn start_clock(&mut self) {
+        let time_control = self
+            .conn
+            .as_deref()
+            .and_then(Connection::peer)
+            .and_then(|peer| peer.time_control);
+        if let Some(tc) = time_control.filter(|_| self.clock.is_none()) {
+            let running = Side::to_move_after(self.history.len());
+            self.clock = Some(ChessClock::new(tc, running));
+            self.update_board_data();
+        }
+    }
+    /// Enter starts typing a message and sends it once it is done
+    fn update_chat(&mut self) {
+        let open = self.conn.as_deref().is_some_and(|c| !c.is_shutdown());
+        let Some(chat) = self.chat.as_mut() else {
+            return;
+        };
+        // nobody is listening once the game is over
+        if !open {
+            chat.typing = false;
+            return;
+        }
+        if !self.window_handle.is_key_pressed(KeyboardKey::KEY_ENTER) {
+            return;
+        }
+        chat.typing = !chat.typing;
+        if chat.typing {
+            return;
+        }
+        let text = network::clean_chat(&std::mem::take(&mut chat.input));
+        if !text.is_empty() {
+            chat.push(&self.name, text.clone());
+            self.send_queue.push_back(Message::Chat(text));
+        }
+    }
+
     fn resize(&mut self) {
         self.width = self.window_handle.get_screen_width();
         self.height = self.window_handle.get_screen_height();
@@ -404,12 +1222,18 @@ impl Game {
     }
 
     pub fn update_board_data(&mut self) {
+        // the board is centred on what the panel leaves of the window
+        let width = if self.chat.is_some() || self.clock.is_some() {
+            self.width as f32 * (1. - PANEL_SHARE)
+        } else {
+            self.width as f32
+        };
         let center = Vector2 {
-            x: self.width as f32 / 2.,
+            x: width / 2.,
             y: self.height as f32 / 2.,
         };
 
-        let (start, size) = if self.width >= self.height {
+        let (start, size) = if width >= self.height as f32 {
             let size = self.height as f32 - self.height as f32 * MARGIN;
             (
                 Vector2 {
@@ -419,7 +1243,7 @@ impl Game {
                 size,
             )
         } else {
-            let size = self.width as f32 - self.width as f32 * MARGIN;
+            let size = width - width * MARGIN;
             (
                 Vector2 {
                     x: center.x - size / 2.,
@@ -442,13 +1266,51 @@ impl Game {
     }
     pub fn on_network_event(&mut self, _ev: NetworkEvent) {}
 
+    /// Replaces the game with the host's after the connection was lost, play goes on from
+    /// wherever its moves lead
+    fn resync(&mut self, moves: Vec<BoardMove>) -> State {
+        self.selected_piece = None;
+        self.board = board::ChessBoard::new_full();
+        self.history.clear();
+        for m in moves {
+            if let Some(end) = self.statefull_move_piece(m) {
+                return end;
+            }
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            clock.restart(Side::to_move_after(self.history.len()));
+        }
+        if self.spectator {
+            return State::Watching;
+        }
+        self.turn_state()
+    }
+    /// Whose turn it is after the moves so far
+    fn turn_state(&self) -> State {
+        if Side::to_move_after(self.history.len()) == self.side {
+            State::Move
+        } else {
+            State::WaitMove
+        }
+    }
+    /// The side the handshake gave the player, computer opponents leave it at `fallback`
+    fn take_side(&mut self, fallback: Side) {
+        self.side = self
+            .conn
+            .as_deref()
+            .and_then(Connection::peer)
+            .map_or(fallback, |peer| peer.side.opposite());
+        self.reversed = self.side == Side::Black;
+    }
+
     /// Takes into consideration wether the move casuses a loss or victory
     /// # Returns
     /// `Some(State::Won | State::Lost)` if the move caused a game ending condition, otherwise
     /// returns None
     fn statefull_move_piece(&mut self, m: BoardMove) -> Option<State> {
         if let Some(res) = self.board.move_piece(m) {
-            match is_lost_or_won(self.is_host, &res.pieces_deleted) {
+            self.history.push(m);
+            match is_lost_or_won(self.side, &res.pieces_deleted) {
                 Some(EndCheck::Victory) => Some(State::Won),
                 Some(EndCheck::Loss) => Some(State::Lost),
                 _ => None,
@@ -486,6 +1348,22 @@ impl Game {
                     "Connecting to host",
                     fontw,
                 );
+                // dropping the connection stops its thread, even in the middle of connecting
+                let (cancel, _) = gui::button(
+                    &mut draw_handle,
+                    Vector2 {
+                        x: (self.width as f32 / 2.),
+                        y: (self.height as f32 / 2.) + 96.,
+                    },
+                    "Cancel",
+                    fontw,
+                );
+                if cancel {
+                    self.conn = None;
+                    self.spectator = false;
+                    self.error_msg = None;
+                    self.state = State::SetupConnection;
+                }
             }
             State::ConnectingHost if !self.conn.as_ref().unwrap().is_connected() => {
                 let mut draw_handle = self.window_handle.begin_drawing(&self.window_thread);
@@ -509,6 +1387,46 @@ impl Game {
                     "Waiting for client",
                     fontw,
                 );
+                // the joining player picks the game from the relay's list by it
+                if let Some(id) = self.conn.as_ref().unwrap().relay_game() {
+                    gui::text(
+                        &mut draw_handle,
+                        Vector2 {
+                            x: (self.width as f32 / 2.),
+                            y: (self.height as f32 / 2.) - 48.,
+                        },
+                        &format!("The relay lists the game as {id}"),
+                        fontw,
+                    );
+                }
+                // a client which could not be played with
+                if let Some(msg) = &self.error_msg {
+                    gui::text(
+                        &mut draw_handle,
+                        Vector2 {
+                            x: (self.width as f32 / 2.),
+                            y: (self.height as f32 / 2.) + 48.,
+                        },
+                        msg,
+                        fontw,
+                    );
+                }
+                // dropping the connection stops its thread, even in the middle of connecting
+                let (cancel, _) = gui::button(
+                    &mut draw_handle,
+                    Vector2 {
+                        x: (self.width as f32 / 2.),
+                        y: (self.height as f32 / 2.) + 96.,
+                    },
+                    "Cancel",
+                    fontw,
+                );
+                if cancel {
+                    self.conn = None;
+                    self.spectator = false;
+                    self.error_msg = None;
+                    self.state = State::SetupConnection;
+                }
             }
             State::FatalError => {
                 self.draw_fatal_error();
@@ -571,18 +1489,30 @@ impl Game {
             OKRIMC,
         );
 
+        // high enough for all of the buttons to fit below
         let input_pos = Vector2 {
             x: (self.width as f32 / 2.),
-            y: (self.height as f32 / 2.),
+            y: (self.height as f32 / 3.),
+        };
+        let (input, input_sz) = if self.editing_passphrase {
+            gui::secret_input(
+                &mut draw_handle,
+                input_pos,
+                &mut self.passphrase,
+                Some("<PASSPHRASE>"),
+                Some(64),
+                fontw,
+            )
+        } else {
+            gui::text_input(
+                &mut draw_handle,
+                input_pos,
+                &mut self.input_text,
+                Some("<IP ADDRESS>"),
+                Some(24),
+                fontw,
+            )
         };
-        let (input, input_sz) = gui::text_input(
-            &mut draw_handle,
-            input_pos,
-            &mut self.input_text,
-            Some("<IP ADDRESS>"),
-            Some(24),
-            fontw,
-        );
         let (client, client_sz) = gui::button(
             &mut draw_handle,
             Vector2 {
@@ -601,19 +1531,195 @@ impl Game {
             "Host",
             fontw,
         );
-        if !input && self.error_msg.is_some() {
+        let (watch, watch_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y + (input_sz.y * 1.5) + (client_sz.y * 1.5) + (host_sz.y * 1.5),
+            },
+            "Watch",
+            fontw,
+        );
+        let (computer, computer_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y
+                    + (input_sz.y * 1.5)
+                    + (client_sz.y * 1.5)
+                    + (host_sz.y * 1.5)
+                    + (watch_sz.y * 1.5),
+            },
+            "Computer",
+            fontw,
+        );
+        let level_label = format!("Level: {}", self.bot_level.name());
+        let (level, level_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y
+                    + (input_sz.y * 1.5)
+                    + (client_sz.y * 1.5)
+                    + (host_sz.y * 1.5)
+                    + (watch_sz.y * 1.5)
+                    + (computer_sz.y * 1.5),
+            },
+            &level_label,
+            fontw,
+        );
+        let (local, local_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y
+                    + (input_sz.y * 1.5)
+                    + (client_sz.y * 1.5)
+                    + (host_sz.y * 1.5)
+                    + (watch_sz.y * 1.5)
+                    + (computer_sz.y * 1.5)
+                    + (level_sz.y * 1.5),
+            },
+            "Local",
+            fontw,
+        );
+        let flip_label = format!("Flip board: {}", if self.auto_flip { "On" } else { "Off" });
+        let (flip, flip_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y
+                    + (input_sz.y * 1.5)
+                    + (client_sz.y * 1.5)
+                    + (host_sz.y * 1.5)
+                    + (watch_sz.y * 1.5)
+                    + (computer_sz.y * 1.5)
+                    + (level_sz.y * 1.5)
+                    + (local_sz.y * 1.5),
+            },
+            &flip_label,
+            fontw,
+        );
+        let clock_label = match self.time_control {
+            Some(tc) => format!("Clock: {tc}"),
+            None => "Clock: Off".to_owned(),
+        };
+        let (clock, clock_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y
+                    + (input_sz.y * 1.5)
+                    + (client_sz.y * 1.5)
+                    + (host_sz.y * 1.5)
+                    + (watch_sz.y * 1.5)
+                    + (computer_sz.y * 1.5)
+                    + (level_sz.y * 1.5)
+                    + (local_sz.y * 1.5)
+                    + (flip_sz.y * 1.5),
+            },
+            &clock_label,
+            fontw,
+        );
+        let side_label = match self.side_choice {
+            Some(side) => format!("Side: {}", side_name(side)),
+            None => "Side: Random".to_owned(),
+        };
+        let (side, side_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y
+                    + (input_sz.y * 1.5)
+                    + (client_sz.y * 1.5)
+                    + (host_sz.y * 1.5)
+                    + (watch_sz.y * 1.5)
+                    + (computer_sz.y * 1.5)
+                    + (level_sz.y * 1.5)
+                    + (local_sz.y * 1.5)
+                    + (flip_sz.y * 1.5)
+                    + (clock_sz.y * 1.5),
+            },
+            &side_label,
+            fontw,
+        );
+        let passphrase_label = match (self.editing_passphrase, self.passphrase.is_empty()) {
+            (true, _) => "Passphrase: Done",
+            (false, true) => "Passphrase: Off",
+            (false, false) => "Passphrase: Set",
+        };
+        let (passphrase, passphrase_sz) = gui::button(
+            &mut draw_handle,
+            Vector2 {
+                x: input_pos.x,
+                y: input_pos.y
+                    + (input_sz.y * 1.5)
+                    + (client_sz.y * 1.5)
+                    + (host_sz.y * 1.5)
+                    + (watch_sz.y * 1.5)
+                    + (computer_sz.y * 1.5)
+                    + (level_sz.y * 1.5)
+                    + (local_sz.y * 1.5)
+                    + (flip_sz.y * 1.5)
+                    + (clock_sz.y * 1.5)
+                    + (side_sz.y * 1.5),
+            },
+            passphrase_label,
+            fontw,
+        );
+        if let (false, Some(msg)) = (input, &self.error_msg) {
             gui::text(
                 &mut draw_handle,
                 Vector2 {
                     x: input_pos.x,
-                    y: input_pos.y + (client_sz.y * 1.5) + (host_sz.y * 1.5),
+                    y: input_pos.y
+                        + (input_sz.y * 1.5)
+                        + (client_sz.y * 1.5)
+                        + (host_sz.y * 1.5)
+                        + (watch_sz.y * 1.5)
+                        + (computer_sz.y * 1.5)
+                        + (level_sz.y * 1.5)
+                        + (local_sz.y * 1.5)
+                        + (flip_sz.y * 1.5)
+                        + (clock_sz.y * 1.5)
+                        + (side_sz.y * 1.5)
+                        + (passphrase_sz.y * 1.5),
                 },
-                self.error_msg.as_ref().unwrap(),
+                msg,
                 fontw,
             );
         } else {
             self.error_msg = None;
         }
+        if let Some(Err(e)) = self.lan.as_mut().map(Finder::poll) {
+            eprintln!("stopped looking for games on the network: {e}");
+            self.lan = None;
+        }
+        let mut joined = None;
+        if let Some(lan) = self.lan.as_ref() {
+            // to the right of the buttons, with a smaller font to fit
+            let fontw = FontWrap::wrap(font.as_ref(), 16., 6.);
+            let mut pos = Vector2 {
+                x: self.width as f32 * 0.85,
+                y: input_pos.y,
+            };
+            let (_, header_sz) = gui::text(&mut draw_handle, pos, "On your network", fontw);
+            pos.y += header_sz.y * 1.5;
+            let games = &lan.games()[..lan.games().len().min(MAX_LAN_GAMES)];
+            for game in games {
+                let (clicked, sz) =
+                    gui::button(&mut draw_handle, pos, &lan_label(&game.announcement), fontw);
+                if clicked {
+                    joined = Some(game.addr);
+                }
+                pos.y += sz.y * 1.5;
+            }
+            if games.is_empty() {
+                gui::text(&mut draw_handle, pos, "no games yet", fontw);
+            } else if games.iter().any(|g| g.announcement.protected) {
+                gui::text(&mut draw_handle, pos, "* passphrase needed", fontw);
+            }
+        }
         let fontw = FontWrap::wrap(font.as_ref(), 48., 12.);
         gui::text(
             &mut draw_handle,
@@ -624,16 +1730,83 @@ impl Game {
             "Pawn Hearts",
             fontw,
         );
+        if level {
+            self.bot_level = self.bot_level.next();
+        }
+        if flip {
+            self.auto_flip = !self.auto_flip;
+        }
+        if passphrase {
+            self.editing_passphrase = !self.editing_passphrase;
+        }
+        if side {
+            self.side_choice = match self.side_choice {
+                None => Some(Side::White),
+                Some(Side::White) => Some(Side::Black),
+                Some(Side::Black) => None,
+            };
+        }
+        if clock {
+            // off once the last preset was offered
+            self.time_control = match self.time_control {
+                None => Some(clock::PRESETS[0]),
+                Some(tc) => clock::PRESETS
+                    .iter()
+                    .skip_while(|preset| **preset != tc)
+                    .nth(1)
+                    .copied(),
+            };
+        }
+        if local {
+            self.hot_seat = true;
+            self.state = State::Move;
+            return;
+        }
+        if computer {
+            let limits = self.bot_level.limits();
+            self.conn = Some(Box::new(
+                Bot::new(Side::Black, limits)
+                    .with_book(self.book.clone())
+                    .with_tablebase(self.tablebase.clone()),
+            ));
+            self.assists = true;
+            self.state = local_opponent_state(Side::White);
+            return;
+        }
+        // joining a game from the list is connecting to its address
+        if let Some(addr) = joined {
+            self.input_text = addr.to_string();
+        }
+        let client = client || joined.is_some();
+        let passphrase = (!self.passphrase.is_empty()).then(|| self.passphrase.clone());
         match (client, host, SocketAddr::from_str(self.input_text.as_str())) {
+            (false, false, Ok(addr)) if watch => {
+                let hello = Hello::spectator(&self.name);
+                self.spectator = true;
+                self.state = State::ConnectingClient;
+                self.conn = Some(connect(passphrase, move |p| {
+                    Ok(Client::new(&addr.to_string(), hello)?.with_passphrase(p))
+                }))
+            }
             (true, false, Ok(addr)) => {
                 self.state = State::ConnectingClient;
-                self.conn = Some(Box::new(Client::new(&addr.to_string()).unwrap()))
+                let hello = Hello::new(&self.name)
+                    .with_time_control(self.time_control)
+                    .with_side(self.side_choice);
+                self.conn = Some(connect(passphrase, move |p| {
+                    Ok(Client::new(&addr.to_string(), hello)?.with_passphrase(p))
+                }))
             }
             (false, true, Ok(addr)) => {
                 self.state = State::ConnectingHost;
-                self.conn = Some(Box::new(Host::new(&addr.to_string()).unwrap()))
+                let hello = Hello::new(&self.name)
+                    .with_time_control(self.time_control)
+                    .with_side(self.side_choice);
+                self.conn = Some(connect(passphrase, move |p| {
+                    Ok(Host::new(&addr.to_string(), hello)?.with_passphrase(p))
+                }))
             }
-            (false, false, _) => (),
+            (false, false, Err(_)) if !watch => (),
             (_, _, Err(e)) => {
                 let e = format!("Error while parsing SocketAddr: {e}");
                 draw_handle.trace_log(TraceLogLevel::LOG_ERROR, &e);
@@ -643,6 +1816,78 @@ impl Game {
         };
     }
     fn draw_board(&mut self) {
+        let side_to_move = self.side_to_move();
+        let show_book = self.show_book && self.assists_on();
+        let search = self.analysis.as_ref().map(|(_, s)| s);
+        let tablebase = self
+            .tablebase_probe
+            .as_ref()
+            .and_then(|(_, side, probe)| probe.latest().map(|(m, p)| (m, p, *side)));
+        // the tables know better than the search
+        let hint = tablebase
+            .and_then(|(m, _, _)| m)
+            .or_else(|| search.and_then(BackgroundSearch::latest).map(|r| r.best))
+            .filter(|_| self.show_hint && self.assists_on() && self.state == State::Move)
+            .map(|m| [self.cell_rect(m.from()), self.cell_rect(m.to())]);
+        let hint = hint.or_else(|| match self.trainer.as_ref()?.outcome {
+            Some(Attempt::Wrong(expected)) => Some([
+                self.cell_rect(expected.from()),
+                self.cell_rect(expected.to()),
+            ]),
+            _ => None,
+        });
+        let reviewed_score = self
+            .review
+            .as_ref()
+            .and_then(|r| r.result.as_ref().map(|res| res.evals[r.ply]));
+        let tablebase_score = tablebase.map(|(_, probe, side)| {
+            let score = match probe.outcome {
+                TbOutcome::Win => MATE,
+                TbOutcome::Draw => 0,
+                TbOutcome::Loss => -MATE,
+            };
+            if side == Side::White {
+                score
+            } else {
+                -score
+            }
+        });
+        let white_score = tablebase_score
+            .or_else(|| search.and_then(BackgroundSearch::white_score))
+            .or(reviewed_score);
+        let opponent = self
+            .conn
+            .as_deref()
+            .and_then(Connection::peer)
+            .filter(|_| self.state != State::Review)
+            .map(|peer| {
+                if self.spectator {
+                    format!("Watching the game of {}", peer.name)
+                } else {
+                    format!("Playing against {}", peer.name)
+                }
+            });
+        let interrupted = self
+            .conn
+            .as_deref()
+            .is_some_and(|c| c.peer().is_some() && !c.is_connected())
+            && self.side_to_move().is_some();
+        let controls = self.has_controls()
+            && matches!(self.state, State::Move | State::WaitMove)
+            && !interrupted;
+        let can_take_back = self.takeback_target(self.side).is_some();
+        let opponent_name = self
+            .conn
+            .as_deref()
+            .and_then(Connection::peer)
+            .map_or("The opponent", |peer| &peer.name);
+        let question = match self.state {
+            State::Answering(Proposal::Draw) => Some(format!("{opponent_name} offers a draw")),
+            State::Answering(Proposal::Takeback) => {
+                Some(format!("{opponent_name} asks to take back a move"))
+            }
+            _ => None,
+        };
         let mut draw_handle = self.window_handle.begin_drawing(&self.window_thread);
         draw_handle.clear_background(OKRIMC);
 
@@ -695,6 +1940,60 @@ impl Game {
                 }
             }
         }
+        for rect in hint.into_iter().flatten() {
+            draw_handle.draw_rectangle_rec(rect, HINTC);
+        }
+        if let Some(score) = white_score {
+            let board = self.board_data.rect;
+            let bar = Rectangle {
+                x: board.x / 2. - board.x / 6.,
+                y: board.y,
+                width: board.x / 3.,
+                height: board.height,
+            };
+            let white = bar.height * background::white_share(score);
+            // white's part grows from white's side of the board
+            let white_y = if self.reversed {
+                bar.y
+            } else {
+                bar.y + bar.height - white
+            };
+            draw_handle.draw_rectangle_rec(bar, Color::DARKGRAY);
+            draw_handle.draw_rectangle_rec(
+                Rectangle {
+                    y: white_y,
+                    height: white,
+                    ..bar
+                },
+                Color::WHITESMOKE,
+            );
+        }
+        let tablebase_line =
+            tablebase.map(|(_, probe, side)| format!("Tablebase: {}", probe.describe(side)));
+        if interrupted {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            let pos = Vector2 {
+                x: self.width as f32 / 2.,
+                y: self.height as f32 - self.board_data.start.y / 2.,
+            };
+            let line = if self.is_host && !self.spectator {
+                "Connection lost, waiting for the opponent to come back"
+            } else {
+                "Connection lost, reconnecting"
+            };
+            gui::text(&mut draw_handle, pos, line, fontw);
+        }
+        // the review has its own captions which the line joins
+        if let (Some(line), false) = (&tablebase_line, self.state == State::Review) {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            let pos = Vector2 {
+                x: self.width as f32 / 2.,
+                y: self.height as f32 - self.board_data.start.y / 2.,
+            };
+            gui::text(&mut draw_handle, pos, line, fontw);
+        }
         if let Some(selection) = &self.selected_piece {
             let mouse = draw_handle.get_mouse_position();
             let cell_sz = self.board_data.cell_size;
@@ -730,7 +2029,71 @@ impl Game {
                 Color::WHITE,
             )
         }
-        if matches!(self.state, State::Won | State::Lost) {
+        if let (true, Some(book), Some(side)) = (show_book, &self.book, side_to_move) {
+            let moves = book.moves(&self.board, side);
+            let total = moves.iter().map(|(_, w)| *w as u32).sum::<u32>().max(1);
+            let line = if moves.is_empty() {
+                "Out of book".to_owned()
+            } else {
+                moves
+                    .iter()
+                    .take(5)
+                    .map(|(m, w)| format!("{} {}%", m.to_uci(&self.board), *w as u32 * 100 / total))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            };
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            let pos = Vector2 {
+                x: self.width as f32 / 2.,
+                y: self.board_data.start.y / 2.,
+            };
+            gui::text(&mut draw_handle, pos, &line, fontw);
+        } else if let Some(line) = &opponent {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            let pos = Vector2 {
+                x: self.width as f32 / 2.,
+                y: self.board_data.start.y / 2.,
+            };
+            gui::text(&mut draw_handle, pos, line, fontw);
+        }
+        let board = self.board_data.rect;
+        let x = board.x + board.width + board.y;
+        let mut panel = Rectangle {
+            x,
+            width: self.width as f32 - x - board.y,
+            ..board
+        };
+        if let Some(clock) = &self.clock {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 32., 4.);
+            // each clock is on the side of its pieces
+            let bottom = if self.reversed {
+                Side::Black
+            } else {
+                Side::White
+            };
+            let clock_h = fontw.line_height() * 1.5;
+            let top_rect = Rectangle {
+                height: clock_h,
+                ..panel
+            };
+            let bottom_rect = Rectangle {
+                y: panel.y + panel.height - clock_h,
+                ..top_rect
+            };
+            draw_clock(&mut draw_handle, clock, bottom.opposite(), top_rect, fontw);
+            draw_clock(&mut draw_handle, clock, bottom, bottom_rect, fontw);
+            panel.y += clock_h * 1.25;
+            panel.height -= clock_h * 2.5;
+        }
+        if let Some(chat) = self.chat.as_mut() {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 18., 2.);
+            draw_chat(&mut draw_handle, chat, panel, fontw);
+        }
+        if matches!(self.state, State::Won | State::Lost | State::Drawn) {
             let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
             let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
             let pos = Vector2 {
@@ -742,12 +2105,163 @@ impl Game {
                 y: (self.height as f32 / 8.),
             };
             draw_handle.draw_rectangle_v(pos - (sz / 2.), sz, Color::GRAY);
+            // the player who moved last in a hot seat game is the one `side` stands for
+            let mover = side_name(self.side);
+            let other = side_name(self.side.opposite());
             let msg = match self.state {
-                State::Won => "You won",
-                State::Lost => "You lost",
+                State::Won if self.hot_seat => format!("{mover} won"),
+                State::Lost if self.hot_seat => format!("{other} won"),
+                // spectators see the game from white's side
+                State::Won if self.spectator => "White won".to_owned(),
+                State::Lost if self.spectator => "Black won".to_owned(),
+                State::Won => "You won".to_owned(),
+                State::Lost => "You lost".to_owned(),
+                State::Drawn => "Draw".to_owned(),
                 _ => unreachable!(),
             };
-            gui::text(&mut draw_handle, pos, msg, fontw);
+            gui::text(&mut draw_handle, pos, &msg, fontw);
+            let (analyse, _) = gui::button(
+                &mut draw_handle,
+                Vector2 {
+                    x: pos.x,
+                    y: pos.y + sz.y,
+                },
+                "Analyse",
+                fontw,
+            );
+            if analyse {
+                drop(draw_handle);
+                self.start_review();
+                return;
+            }
+        }
+        let mut control = None;
+        let board = self.board_data.rect;
+        let bottom = board.y + board.height + board.y / 2.;
+        if controls {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            let buttons = [
+                (Control::Resign, "Resign"),
+                (Control::Offer(Proposal::Draw), "Offer draw"),
+                (Control::Offer(Proposal::Takeback), "Takeback"),
+            ];
+            for (n, (c, label)) in buttons.into_iter().enumerate() {
+                if c == Control::Offer(Proposal::Takeback) && !can_take_back {
+                    continue;
+                }
+                let pos = Vector2 {
+                    x: board.x + board.width * (1 + 2 * n) as f32 / 6.,
+                    y: bottom,
+                };
+                if gui::button(&mut draw_handle, pos, label, fontw).0 {
+                    control = Some(c);
+                }
+            }
+        }
+        if matches!(self.state, State::Asking(_)) && !interrupted {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            let pos = Vector2 {
+                x: board.x + board.width / 2.,
+                y: bottom,
+            };
+            gui::text(&mut draw_handle, pos, "Waiting for an answer", fontw);
+        }
+        if let Some(question) = &question {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            let pos = Vector2 {
+                x: board.x + board.width / 2.,
+                y: board.y + board.height / 2.,
+            };
+            let sz = Vector2 {
+                x: board.width * 0.8,
+                y: board.height / 4.,
+            };
+            draw_handle.draw_rectangle_v(pos - (sz / 2.), sz, Color::GRAY);
+            gui::text(
+                &mut draw_handle,
+                Vector2 {
+                    y: pos.y - sz.y / 4.,
+                    ..pos
+                },
+                question,
+                fontw,
+            );
+            let answers = [(true, "Accept"), (false, "Decline")];
+            for (n, (accept, label)) in answers.into_iter().enumerate() {
+                let button = Vector2 {
+                    x: pos.x - sz.x / 4. + n as f32 * sz.x / 2.,
+                    y: pos.y + sz.y / 4.,
+                };
+                if gui::button(&mut draw_handle, button, label, fontw).0 {
+                    control = Some(Control::Answer(accept));
+                }
+            }
+        }
+        if let Some(control) = control {
+            drop(draw_handle);
+            self.control(control);
+            return;
+        }
+        let mut next_puzzle = false;
+        if let Some(trainer) = &self.trainer {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            next_puzzle = draw_puzzle(
+                &mut draw_handle,
+                trainer,
+                &self.board,
+                &self.board_data,
+                fontw,
+            );
+        }
+        if next_puzzle {
+            drop(draw_handle);
+            self.next_puzzle();
+            return;
+        }
+        if let (State::Review, Some(review)) = (&self.state, &self.review) {
+            let font = self.loader.get_font_no_load("LinLibertine_R.otf").unwrap();
+            let fontw = FontWrap::wrap(font.as_ref(), 24., 12.);
+            draw_review(
+                &mut draw_handle,
+                review,
+                &self.board_data,
+                fontw,
+                tablebase_line.as_deref(),
+            );
+        }
+    }
+    /// Where a cell is drawn on the screen
+    fn cell_rect(&self, pos: BoardPos) -> Rectangle {
+        let (row, col) = if self.reversed {
+            (7 - pos.row, 7 - pos.col)
+        } else {
+            (pos.row, pos.col)
+        };
+        Rectangle {
+            x: self.board_data.start.x + col as f32 * self.board_data.cell_size,
+            y: self.board_data.start.y + row as f32 * self.board_data.cell_size,
+            width: self.board_data.cell_size,
+            height: self.board_data.cell_size,
+        }
+    }
+    /// Whether the book, hints and the tables may be shown: never to a player facing a person
+    /// over the network, even if assists were on for an earlier game
+    fn assists_on(&self) -> bool {
+        self.assists && self.conn.as_ref().is_none_or(|c| c.peer().is_none())
+    }
+    /// `None` while no game is being played
+    fn side_to_move(&self) -> Option<Side> {
+        match self.state {
+            State::Move | State::MovePending(_) | State::WaitReply(_) => Some(self.side),
+            State::WaitMove => Some(self.side.opposite()),
+            State::Watching | State::Asking(_) | State::Answering(_) => {
+                Some(Side::to_move_after(self.history.len()))
+            }
+            _ => None,
         }
     }
     fn board_pos(&self) -> Option<BoardPos> {
@@ -769,17 +2283,291 @@ impl Game {
     }
 }
 
+/// Puzzle details above the board, the player's results below it and how the puzzle went
+/// once it is over
+/// # Returns
+/// whether the player asked for the next puzzle
+fn draw_puzzle(
+    draw_handle: &mut RaylibDrawHandle,
+    trainer: &Trainer,
+    board: &board::ChessBoard,
+    board_data: &board::BoardRenderData,
+    fontw: FontWrap,
+) -> bool {
+    let rect = board_data.rect;
+    let puzzle = trainer.puzzle();
+    let info = format!(
+        "Puzzle {} ({})  {}",
+        puzzle.id,
+        puzzle.rating,
+        puzzle.themes.join(", ")
+    );
+    let top = Vector2 {
+        x: rect.x + rect.width / 2.,
+        y: rect.y / 2.,
+    };
+    gui::text(draw_handle, top, &info, fontw);
+    let stats = &trainer.stats;
+    let results = format!(
+        "Rating {}   Streak {} (best {})   Solved {}/{}",
+        stats.rating,
+        stats.streak,
+        stats.best_streak,
+        stats.solved,
+        stats.solved + stats.failed
+    );
+    let bottom = Vector2 {
+        x: top.x,
+        y: rect.y + rect.height + rect.y / 2.,
+    };
+    gui::text(draw_handle, bottom, &results, fontw);
+
+    let msg = match trainer.outcome {
+        Some(Attempt::Solved) => "Solved!".to_owned(),
+        Some(Attempt::Wrong(expected)) => {
+            format!("Wrong, {} was the answer", expected.to_san(board))
+        }
+        _ => return false,
+    };
+    let pos = Vector2 {
+        x: rect.x + rect.width / 2.,
+        y: rect.y + rect.height / 2.,
+    };
+    let (_, sz) = gui::text(draw_handle, pos, &msg, fontw);
+    let (next, _) = gui::button(
+        draw_handle,
+        Vector2 {
+            x: pos.x,
+            y: pos.y + sz.y * 1.5,
+        },
+        "Next",
+        fontw,
+    );
+    next
+}
+
+/// The messages so far, with the entry below them while the player is typing
+fn draw_chat(
+    draw_handle: &mut RaylibDrawHandle,
+    chat: &mut Chat,
+    rect: Rectangle,
+    fontw: FontWrap,
+) {
+    if rect.width <= 0. {
+        return;
+    }
+    draw_handle.draw_rectangle_rec(rect, BACKC);
+    let entry_h = fontw.line_height() * 2.;
+    let lines = chat
+        .lines
+        .iter()
+        .map(ChatLine::to_string)
+        .collect::<Vec<_>>();
+    let history = Rectangle {
+        height: rect.height - entry_h,
+        ..rect
+    };
+    gui::text_box(draw_handle, history, &lines, fontw);
+    let entry = Vector2 {
+        x: rect.x + rect.width / 2.,
+        y: rect.y + rect.height - entry_h / 2.,
+    };
+    if chat.typing {
+        gui::text_input(
+            draw_handle,
+            entry,
+            &mut chat.input,
+            Some("Enter to send"),
+            Some(network::MAX_CHAT as u32),
+            fontw,
+        );
+    } else {
+        gui::text(draw_handle, entry, "Enter to chat", fontw);
+    }
+}
+
+/// What the setup screen lists a game on the local network as, short enough for its column
+fn lan_label(announcement: &Announcement) -> String {
+    let lock = if announcement.protected { "*" } else { "" };
+    let name = announcement.name.chars().take(10).collect::<String>();
+    let clock = announcement
+        .time_control
+        .map_or("untimed".to_owned(), |tc| tc.to_string());
+    match announcement.variant.as_str() {
+        "standard" => format!("{lock}{name} {clock}"),
+        variant => format!("{lock}{name} {clock} {variant}"),
+    }
+}
+
+/// Time left of `side`, framed while it runs
+fn draw_clock(
+    draw_handle: &mut RaylibDrawHandle,
+    clock: &ChessClock,
+    side: Side,
+    rect: Rectangle,
+    fontw: FontWrap,
+) {
+    if rect.width <= 0. {
+        return;
+    }
+    let left = clock.left(side);
+    let back = if left.is_zero() {
+        ERRRIMC
+    } else {
+        Color::WHITE
+    };
+    draw_handle.draw_rectangle_rec(rect, back);
+    if clock.running() == Some(side) {
+        draw_handle.draw_rectangle_lines_ex(rect, 3., OKRIMC);
+    }
+    let center = Vector2 {
+        x: rect.x + rect.width / 2.,
+        y: rect.y + rect.height / 2.,
+    };
+    gui::text(draw_handle, center, &clock::format(left), fontw);
+}
+
+/// The lower quarter of the board, where the evaluation graph of a review is drawn
+fn graph_rect(board_data: &board::BoardRenderData) -> Rectangle {
+    let board = board_data.rect;
+    Rectangle {
+        y: board.y + board.height * 0.75,
+        height: board.height * 0.25,
+        ..board
+    }
+}
+
+fn draw_review(
+    draw_handle: &mut RaylibDrawHandle,
+    review: &Review,
+    board_data: &board::BoardRenderData,
+    fontw: FontWrap,
+    tablebase: Option<&str>,
+) {
+    let board = board_data.rect;
+    let top = Vector2 {
+        x: board.x + board.width / 2.,
+        y: board.y / 2.,
+    };
+    let bottom = Vector2 {
+        x: top.x,
+        y: board.y + board.height + board.y / 2.,
+    };
+    let caption = match &review.job {
+        Some(job) => {
+            let (done, total) = job.progress();
+            format!("Analysing {done}/{total}")
+        }
+        None => review.caption.clone(),
+    };
+    let caption = match tablebase {
+        Some(line) => format!("{caption}   {line}"),
+        None => caption,
+    };
+    gui::text(draw_handle, top, &caption, fontw);
+    let help = "Left/Right: step through the game   E: save annotated PGN";
+    let footer = review.message.as_deref().unwrap_or(help);
+    gui::text(draw_handle, bottom, footer, fontw);
+
+    let Some(result) = &review.result else {
+        return;
+    };
+    let graph = graph_rect(board_data);
+    draw_handle.draw_rectangle_rec(graph, Color::new(0, 0, 0, 150));
+    let middle = graph.y + graph.height / 2.;
+    draw_handle.draw_line_ex(
+        Vector2::new(graph.x, middle),
+        Vector2::new(graph.x + graph.width, middle),
+        1.,
+        Color::GRAY,
+    );
+    let step = graph.width / (result.evals.len() - 1).max(1) as f32;
+    let point = |ply: usize| {
+        Vector2::new(
+            graph.x + ply as f32 * step,
+            graph.y + graph.height * (1. - background::white_share(result.evals[ply])),
+        )
+    };
+    for ply in 1..result.evals.len() {
+        draw_handle.draw_line_ex(point(ply - 1), point(ply), 2., Color::WHITESMOKE);
+    }
+    for (ply, m) in result.moves.iter().enumerate() {
+        let color = match m.judgement {
+            Some(Judgement::Inaccuracy) => Color::YELLOW,
+            Some(Judgement::Mistake) => Color::ORANGE,
+            Some(Judgement::Blunder) => Color::RED,
+            None => continue,
+        };
+        draw_handle.draw_circle_v(point(ply + 1), 4., color);
+    }
+    let x = graph.x + review.ply as f32 * step;
+    draw_handle.draw_line_ex(
+        Vector2::new(x, graph.y),
+        Vector2::new(x, graph.y + graph.height),
+        2.,
+        OKRIMC,
+    );
+}
+
+/// The last move of the position after `ply` moves, its evaluation and what was better
+fn describe_ply(result: &GameReview, ply: usize) -> String {
+    let eval = analysis::format_score(result.evals[ply]);
+    let Some(review) = ply.checked_sub(1).and_then(|p| result.moves.get(p)) else {
+        return format!("Starting position  {eval}");
+    };
+    let before = result.board_at(ply - 1);
+    let number = match review.side {
+        Side::White => format!("{}.", ply.div_ceil(2)),
+        Side::Black => format!("{}...", ply.div_ceil(2)),
+    };
+    let symbol = review.judgement.map(Judgement::symbol).unwrap_or_default();
+    let mut ret = format!("{number} {}{symbol}  {eval}", review.played.to_san(&before));
+    if let (Some(judgement), Some(best)) = (review.judgement, review.best) {
+        ret += &format!("  {}, {} was best", judgement.name(), best.to_san(&before));
+    }
+    ret
+}
+
+/// Writes the annotated game next to the executable's working directory
+fn export_review(result: &GameReview, outcome: GameResult) -> anyhow::Result<PathBuf> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = PathBuf::from(format!("pawn_hearts_{secs}.pgn"));
+    std::fs::write(&path, result.to_pgn(outcome).to_pgn()?)?;
+    Ok(path)
+}
+
+/// Connects on a thread of its own, so that the window keeps being drawn. The passphrase is
+/// stretched there as well, as that takes a moment.
+fn connect<C, F>(passphrase: Option<String>, open: F) -> Box<dyn Connection>
+where
+    C: Connection + Send + 'static,
+    F: FnOnce(Option<Passphrase>) -> anyhow::Result<C> + Send + 'static,
+{
+    Box::new(BackgroundConnection::start(move || {
+        let conn = open(passphrase.as_deref().map(Passphrase::new))?;
+        Ok(Box::new(conn) as Box<dyn Connection + Send>)
+    }))
+}
+
+/// Local opponents take the role of whichever end of the connection the player does not, so a
+/// player on the white side is the host
+fn local_opponent_state(side: Side) -> State {
+    match side {
+        Side::White => State::ConnectingHost,
+        Side::Black => State::ConnectingClient,
+    }
+}
+
 enum EndCheck {
     Loss,
     Victory,
 }
-fn is_lost_or_won(is_host: bool, deleted: &Vec<ChessBoardCell>) -> Option<EndCheck> {
+fn is_lost_or_won(side: Side, deleted: &Vec<ChessBoardCell>) -> Option<EndCheck> {
+    let white = side == Side::White;
     for cell in deleted {
         match *cell {
-            ChessBoardCell::Black(ChessPiece::King(_)) if is_host => {
-                return Some(EndCheck::Victory)
-            }
-            ChessBoardCell::White(ChessPiece::King(_)) if is_host => return Some(EndCheck::Loss),
+            ChessBoardCell::Black(ChessPiece::King(_)) if white => return Some(EndCheck::Victory),
+            ChessBoardCell::White(ChessPiece::King(_)) if white => return Some(EndCheck::Loss),
             ChessBoardCell::Black(ChessPiece::King(_)) => return Some(EndCheck::Loss),
             ChessBoardCell::White(ChessPiece::King(_)) => return Some(EndCheck::Victory),
             _ => continue,
@@ -787,3 +2575,10 @@ fn is_lost_or_won(is_host: bool, deleted: &Vec<ChessBoardCell>) -> Option<EndChe
     }
     None
 }
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::White => "White",
+        Side::Black => "Black",
+    }
+}
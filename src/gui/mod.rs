@@ -22,6 +22,10 @@ impl<'a> FontWrap<'a> {
             sp: spacing,
         }
     }
+    /// Height of a line of text
+    pub fn line_height(&self) -> f32 {
+        measure_text_ex(self.font, "Ag", self.sz, self.sp).y
+    }
 }
 
 pub fn measure_text_ex(
@@ -87,6 +91,39 @@ pub fn text_input(
     limit: Option<u32>,
     font: FontWrap,
 ) -> (bool, rmath::Vector2) {
+    let ret = edit(hndl, text, limit);
+    let text = if text.is_empty() {
+        if_empty.unwrap_or("<Empty>")
+    } else {
+        text.as_str()
+    };
+    (ret, draw_input(hndl, pos, text, font))
+}
+
+/// Like [`text_input`], but the text is shown as one star per character
+pub fn secret_input(
+    hndl: &mut RaylibDrawHandle,
+    pos: rmath::Vector2,
+    text: &mut String,
+    if_empty: Option<&str>,
+    limit: Option<u32>,
+    font: FontWrap,
+) -> (bool, rmath::Vector2) {
+    let ret = edit(hndl, text, limit);
+    let stars;
+    let text = if text.is_empty() {
+        if_empty.unwrap_or("<Empty>")
+    } else {
+        stars = "*".repeat(text.chars().count());
+        stars.as_str()
+    };
+    (ret, draw_input(hndl, pos, text, font))
+}
+
+/// Applies the keys typed since the last frame
+/// # Returns
+/// whether any were
+fn edit(hndl: &mut RaylibDrawHandle, text: &mut String, limit: Option<u32>) -> bool {
     let mut ret = false;
     if let Some(c) = hndl.get_key_pressed() {
         ret = true;
@@ -99,11 +136,15 @@ pub fn text_input(
             _ => text.push(c),
         }
     }
-    let text = if text.is_empty() {
-        if_empty.unwrap_or("<Empty>")
-    } else {
-        text.as_str()
-    };
+    ret
+}
+
+fn draw_input(
+    hndl: &mut RaylibDrawHandle,
+    pos: rmath::Vector2,
+    text: &str,
+    font: FontWrap,
+) -> rmath::Vector2 {
     let tsz = measure_text_ex(font.font, text, font.sz, font.sp);
     let mut area_sz = rmath::Vector2 {
         x: tsz.x + 2.0 * font.sp,
@@ -128,7 +169,7 @@ pub fn text_input(
         font.sp,
         Color::BLACK,
     );
-    (ret, area_sz)
+    area_sz
 }
 
 pub fn text(
@@ -152,3 +193,44 @@ pub fn text(
     );
     (true, area_sz)
 }
+
+/// Left aligned lines wrapped at the width of `rect`, when they do not all fit the last ones are
+/// the ones shown
+pub fn text_box(
+    hndl: &mut RaylibDrawHandle,
+    rect: rmath::Rectangle,
+    lines: &[String],
+    font: FontWrap,
+) {
+    let fits = |s: &str| measure_text_ex(font.font, s, font.sz, font.sp).x <= rect.width;
+    let mut rows = vec![];
+    for line in lines {
+        let mut row = String::new();
+        for word in line.split_whitespace() {
+            if row.is_empty() {
+                row = word.to_owned();
+            } else if fits(&format!("{row} {word}")) {
+                row = format!("{row} {word}");
+            } else {
+                // a word too long for a row of its own overflows it
+                rows.push(std::mem::replace(&mut row, word.to_owned()));
+            }
+        }
+        rows.push(row);
+    }
+    let row_h = font.line_height();
+    let shown = (rect.height / row_h).max(0.) as usize;
+    for (n, row) in rows[rows.len().saturating_sub(shown)..].iter().enumerate() {
+        hndl.draw_text_ex(
+            font.font,
+            row,
+            rmath::Vector2 {
+                x: rect.x,
+                y: rect.y + n as f32 * row_h,
+            },
+            font.sz,
+            font.sp,
+            Color::BLACK,
+        );
+    }
+}
@@ -0,0 +1,12 @@
+//! Everything of the game which does without a window: the board, the engine, clocks, the network
+//! protocol and the terminal client. The game adds the window on top of it and the relay server
+//! uses its lobby and framing.
+
+pub mod board;
+pub mod clock;
+pub mod engine;
+pub mod network;
+pub mod play;
+pub mod puzzle;
+pub mod tournament;
+pub mod tui;
@@ -1,34 +1,312 @@
+use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
 
-use self::game::{Game, RunArgs};
-pub mod board;
+use self::board::Side;
+use self::engine::book::Book;
+use self::engine::tablebase::Tablebase;
+use self::engine::{Level, SearchLimits};
+use self::game::{Game, GameOptions, RunArgs};
+use self::network::lobby::GameId;
+use self::network::uci::UciConfig;
+use self::tournament::player::PlayerConfig;
+use self::tournament::MatchConfig;
+use pawn_hearts::{board, clock, engine, network, play, puzzle, tournament, tui};
 pub mod data;
 pub mod game;
 mod gui;
 pub mod helpers;
-pub mod network;
 pub mod resources;
 
 const WIDTH: i32 = 800;
 const HEIGHT: i32 = 800;
+const USAGE: &str = "usage:
+    pawn_hearts <address> <is_host> [white|black|random]
+    pawn_hearts --bot <easy|medium|hard|milliseconds> [white|black]
+    pawn_hearts --engine <path> [milliseconds] [white|black] [--option <name>=<value>]...
+    pawn_hearts --uci
+    pawn_hearts --tui <address> <is_host> [white|black|random]
+    pawn_hearts [--tui] --relay <server> [<game id>] [white|black|random]
+    pawn_hearts --list <server>
+    pawn_hearts --watch <address>
+    pawn_hearts --puzzles <file.csv>
+    pawn_hearts --local [flip]
+    pawn_hearts --match <player> <player> [--games <n>] [--openings <file.epd|file.pgn>]
+                [--pgn <file>]
+options:
+    --book <path>   Polyglot opening book for the computer opponent
+    --syzygy <dir>  Syzygy endgame tablebases for the computer opponent and analysis
+    --name <name>   What the opponent sees you as in network games
+    --tc <seconds>[+<increment>][d<delay>]
+                    Clocks for network games and matches, with a Fischer increment or a
+                    Bronstein delay
+    --passphrase <text>
+                    Encrypts network games, the peer has to use the same one";
 
 fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
-    let args = if args.len() > 1 {
-        if args.len() != 3 {
-            eprintln!("improper argument count");
+    let mut args = std::env::args().collect::<Vec<_>>();
+    let options = match parse_options(&mut args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            exit(-1)
+        }
+    };
+    if args.get(1).is_some_and(|a| a == "--uci") {
+        // headless, no window gets opened
+        if let Err(e) = engine::uci::run(options.book, options.tablebase) {
+            eprintln!("{e}");
             exit(-1)
         }
-        let address = args[1].clone();
-        let is_host = args[2].parse::<bool>().unwrap();
-        Some(RunArgs { address, is_host })
+        return;
+    }
+    if args.get(1).is_some_and(|a| a == "--match") {
+        // headless too, the results are printed as the games finish
+        let res = parse_match_args(&args[2..], options).and_then(tournament::run);
+        if let Err(e) = res {
+            eprintln!("{e}");
+            exit(-1)
+        }
+        return;
+    }
+    if let [_, flag, server] = args.as_slice() {
+        if flag == "--list" {
+            // headless, the games waiting on the relay server are printed
+            match network::relay::list_games(server) {
+                Ok(games) if games.is_empty() => println!("no open games"),
+                Ok(games) => {
+                    for game in games {
+                        println!("{:>6}  {}  ({})", game.id, game.name, game.details);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    exit(-1)
+                }
+            }
+            return;
+        }
+    }
+    if let [_, flag, rest @ ..] = args.as_slice() {
+        if flag == "--tui" {
+            // headless as well, the board is printed to the terminal
+            let res = parse_tui_args(rest).and_then(|(endpoint, side)| {
+                let name = options
+                    .name
+                    .unwrap_or_else(network::handshake::default_name);
+                tui::run(
+                    endpoint,
+                    &name,
+                    side,
+                    options.time_control,
+                    options.passphrase.as_deref(),
+                )
+            });
+            if let Err(e) = res {
+                eprintln!("{e}\n{USAGE}");
+                exit(-1)
+            }
+            return;
+        }
+    }
+    let args = if args.len() > 1 {
+        match parse_args(&args[1..]) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                eprintln!("{e}\n{USAGE}");
+                exit(-1)
+            }
+        }
     } else {
         None
     };
-    let mut game = Game::init(WIDTH, HEIGHT, args);
+    let mut game = Game::init(WIDTH, HEIGHT, args, options);
     game.update_board_data();
     while !game.should_close() {
         game.update();
         game.draw();
     }
 }
+
+/// Removes the options which may appear in any mode from the arguments
+fn parse_options(args: &mut Vec<String>) -> anyhow::Result<GameOptions> {
+    let mut options = GameOptions::default();
+    while let Some(at) = args.iter().position(|a| a == "--book") {
+        let path = args
+            .get(at + 1)
+            .ok_or(anyhow::anyhow!("--book expects a path"))?;
+        let book = Book::open(path.as_ref())
+            .map_err(|e| anyhow::anyhow!("could not read book '{path}': {e}"))?;
+        options.book = Some(Arc::new(book));
+        args.drain(at..at + 2);
+    }
+    while let Some(at) = args.iter().position(|a| a == "--syzygy") {
+        let dir = args
+            .get(at + 1)
+            .ok_or(anyhow::anyhow!("--syzygy expects a directory"))?;
+        let tablebase = Tablebase::open(dir.as_ref())
+            .map_err(|e| anyhow::anyhow!("could not read tablebases in '{dir}': {e}"))?;
+        options.tablebase = Some(Arc::new(tablebase));
+        args.drain(at..at + 2);
+    }
+    while let Some(at) = args.iter().position(|a| a == "--name") {
+        let name = args
+            .get(at + 1)
+            .ok_or(anyhow::anyhow!("--name expects a name"))?;
+        options.name = Some(name.clone());
+        args.drain(at..at + 2);
+    }
+    while let Some(at) = args.iter().position(|a| a == "--tc") {
+        let tc = args
+            .get(at + 1)
+            .ok_or(anyhow::anyhow!("--tc expects a time control"))?;
+        options.time_control = Some(tc.parse()?);
+        args.drain(at..at + 2);
+    }
+    while let Some(at) = args.iter().position(|a| a == "--passphrase") {
+        let passphrase = args
+            .get(at + 1)
+            .ok_or(anyhow::anyhow!("--passphrase expects a passphrase"))?;
+        options.passphrase = Some(passphrase.clone());
+        args.drain(at..at + 2);
+    }
+    Ok(options)
+}
+
+fn parse_args(args: &[String]) -> anyhow::Result<RunArgs> {
+    match args {
+        [flag, strength, rest @ ..] if flag == "--bot" && rest.len() <= 1 => {
+            // either a named level or a time budget per move
+            let limits = match strength.parse::<u64>() {
+                Ok(ms) => SearchLimits::movetime(Duration::from_millis(ms)),
+                Err(_) => strength.parse::<Level>()?.limits(),
+            };
+            let side = match rest.first() {
+                Some(s) => s.parse::<Side>()?,
+                None => Side::White,
+            };
+            Ok(RunArgs::Computer { limits, side })
+        }
+        [flag, path, rest @ ..] if flag == "--engine" => {
+            let mut config = UciConfig {
+                path: PathBuf::from(path),
+                options: vec![],
+                movetime: Duration::from_secs(1),
+            };
+            let mut side = Side::White;
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                if arg == "--option" {
+                    let (name, value) = rest
+                        .next()
+                        .and_then(|o| o.split_once('='))
+                        .ok_or(anyhow::anyhow!("--option expects <name>=<value>"))?;
+                    config.options.push((name.to_owned(), value.to_owned()));
+                } else if let Ok(ms) = arg.parse::<u64>() {
+                    config.movetime = Duration::from_millis(ms);
+                } else {
+                    side = arg.parse::<Side>()?;
+                }
+            }
+            Ok(RunArgs::Engine { config, side })
+        }
+        [flag, rest @ ..] if flag == "--local" => match rest {
+            [] => Ok(RunArgs::Local { auto_flip: false }),
+            [flip] if flip == "flip" => Ok(RunArgs::Local { auto_flip: true }),
+            _ => anyhow::bail!("--local only takes 'flip'"),
+        },
+        [flag, address] if flag == "--watch" => Ok(RunArgs::Watch {
+            address: address.clone(),
+        }),
+        [flag, path] if flag == "--puzzles" => Ok(RunArgs::Puzzles {
+            puzzles: puzzle::load(path.as_ref())?,
+        }),
+        [flag, rest @ ..] if flag == "--relay" => {
+            let (server, game, side) = parse_relay_args(rest)?;
+            Ok(RunArgs::Relay {
+                server: server.to_owned(),
+                game,
+                side,
+            })
+        }
+        [address, is_host, side @ ..] if side.len() <= 1 => Ok(RunArgs::Network {
+            address: address.clone(),
+            is_host: is_host.parse::<bool>()?,
+            side: parse_side_choice(side.first())?,
+        }),
+        _ => anyhow::bail!("improper argument count"),
+    }
+}
+
+fn parse_tui_args(args: &[String]) -> anyhow::Result<(tui::Endpoint<'_>, Option<Side>)> {
+    match args {
+        [flag, rest @ ..] if flag == "--relay" => {
+            let (server, game, side) = parse_relay_args(rest)?;
+            Ok((tui::Endpoint::Relay { server, game }, side))
+        }
+        [address, is_host, side @ ..] if side.len() <= 1 => Ok((
+            tui::Endpoint::Direct {
+                address,
+                is_host: is_host.parse::<bool>()?,
+            },
+            parse_side_choice(side.first())?,
+        )),
+        _ => anyhow::bail!("improper argument count"),
+    }
+}
+
+/// The server, then the id of the game to join unless a new one is opened, then the side
+fn parse_relay_args(args: &[String]) -> anyhow::Result<(&str, Option<GameId>, Option<Side>)> {
+    let (server, rest) = args
+        .split_first()
+        .ok_or(anyhow::anyhow!("--relay expects a server"))?;
+    let (game, rest) = match rest.first().map(|id| id.parse::<GameId>()) {
+        Some(Ok(id)) => (Some(id), &rest[1..]),
+        _ => (None, rest),
+    };
+    if rest.len() > 1 {
+        anyhow::bail!("improper argument count");
+    }
+    Ok((server, game, parse_side_choice(rest.first())?))
+}
+
+/// `white`, `black` or `random`, which leaves it to the coin toss of the handshake
+fn parse_side_choice(arg: Option<&String>) -> anyhow::Result<Option<Side>> {
+    match arg.map(String::as_str) {
+        None | Some("random") => Ok(None),
+        Some(side) => Ok(Some(side.parse()?)),
+    }
+}
+
+fn parse_match_args(args: &[String], options: GameOptions) -> anyhow::Result<MatchConfig> {
+    let [first, second, rest @ ..] = args else {
+        anyhow::bail!("--match expects two players\n{USAGE}");
+    };
+    let movetime = Duration::from_secs(1);
+    let mut config = MatchConfig {
+        players: [
+            PlayerConfig::parse(first, movetime),
+            PlayerConfig::parse(second, movetime),
+        ],
+        games: 2,
+        time_control: options.time_control,
+        openings: vec![],
+        pgn: None,
+        book: options.book,
+        tablebase: options.tablebase,
+    };
+    let mut rest = rest.iter();
+    while let Some(flag) = rest.next() {
+        let value = rest
+            .next()
+            .ok_or(anyhow::anyhow!("{flag} expects a value\n{USAGE}"))?;
+        match flag.as_str() {
+            "--games" => config.games = value.parse()?,
+            "--openings" => config.openings = tournament::openings::load(value.as_ref())?,
+            "--pgn" => config.pgn = Some(PathBuf::from(value)),
+            _ => anyhow::bail!("unknown match option '{flag}'\n{USAGE}"),
+        }
+    }
+    Ok(config)
+}
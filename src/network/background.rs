@@ -0,0 +1,272 @@
+//! Runs a connection on a thread of its own, so that the window is neither held up by connecting
+//! nor limits how often the network is polled. The game talks to it through channels: messages
+//! to send and a shutdown go one way, received messages, the state of the connection and errors
+//! come back, and [`Connection::poll`] only picks up what arrived.
+
+use super::handshake::{HandshakeError, Peer};
+use super::lobby::GameId;
+use super::{Connection, Message, MessageQueue};
+use anyhow::Result;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Longest pause between polls of the connection, messages to send cut it short
+const POLL_T: Duration = Duration::from_millis(2);
+/// How long a shutdown waits for the last messages to be written
+const CLOSE_T: Duration = Duration::from_secs(1);
+
+/// The connection could not be made at all, like when the host cannot be reached or the address
+/// is taken already
+#[derive(Debug)]
+pub struct OpenError(pub anyhow::Error);
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not connect: {}", self.0)
+    }
+}
+
+impl std::error::Error for OpenError {}
+
+enum Command {
+    Send(Message),
+    Shutdown,
+}
+
+enum Event {
+    Received(Message),
+    /// whether the peer can be talked to, who it is and what a relay server lists the game as
+    State {
+        connected: bool,
+        peer: Option<Peer>,
+        game: Option<GameId>,
+    },
+    Failed(anyhow::Error),
+}
+
+/// Cancels the connection when dropped, without waiting for the thread to finish
+pub struct BackgroundConnection {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    /// nothing is ever sent on it, the thread drops its end once it is done
+    done: Receiver<()>,
+    recv: MessageQueue,
+    connected: bool,
+    peer: Option<Peer>,
+    game: Option<GameId>,
+    shutdown: bool,
+}
+
+impl BackgroundConnection {
+    /// Makes the connection on the new thread, so that resolving the address, connecting and
+    /// stretching a passphrase do not block the caller
+    pub fn start<F>(open: F) -> Self
+    where
+        F: FnOnce() -> Result<Box<dyn Connection + Send>> + Send + 'static,
+    {
+        let (commands, commands_rx) = mpsc::channel();
+        let (events_tx, events) = mpsc::channel();
+        let (done_tx, done) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            run(open, commands_rx, events_tx);
+            drop(done_tx);
+        });
+        Self {
+            commands,
+            events,
+            done,
+            recv: MessageQueue::new(),
+            connected: false,
+            peer: None,
+            game: None,
+            shutdown: false,
+        }
+    }
+}
+
+fn run<F>(open: F, commands: Receiver<Command>, events: Sender<Event>)
+where
+    F: FnOnce() -> Result<Box<dyn Connection + Send>>,
+{
+    let mut conn = match open() {
+        Ok(conn) => conn,
+        Err(e) => {
+            let _ = events.send(Event::Failed(OpenError(e).into()));
+            return;
+        }
+    };
+    let (mut connected, mut peer, mut game) = (false, None, None);
+    let mut command = Err(RecvTimeoutError::Timeout);
+    loop {
+        // everything the game sent so far goes out with this poll
+        loop {
+            match command {
+                Ok(Command::Send(msg)) => conn.send(msg),
+                // the game is gone when its end of the channel is, which cancels the connection
+                Ok(Command::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                    conn.shutdown();
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+            command = match commands.try_recv() {
+                Ok(cmd) => Ok(cmd),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+            };
+        }
+        let res = conn.poll();
+        while let Some(msg) = conn.recv() {
+            if events.send(Event::Received(msg)).is_err() {
+                return;
+            }
+        }
+        if conn.is_connected() != connected
+            || conn.peer() != peer.as_ref()
+            || conn.relay_game() != game
+        {
+            connected = conn.is_connected();
+            peer = conn.peer().cloned();
+            game = conn.relay_game();
+            let state = Event::State {
+                connected,
+                peer: peer.clone(),
+                game,
+            };
+            if events.send(state).is_err() {
+                return;
+            }
+        }
+        if let Err(e) = res {
+            // a host goes on listening after turning a client away, anything else is the end
+            let fatal = !e.is::<HandshakeError>();
+            if events.send(Event::Failed(e)).is_err() || fatal {
+                return;
+            }
+        }
+        command = commands.recv_timeout(POLL_T);
+    }
+}
+
+impl Connection for BackgroundConnection {
+    /// Takes in what the thread reported since the last call
+    /// # Returns
+    /// the first error reported, later ones are left for the next call
+    fn poll(&mut self) -> Result<()> {
+        loop {
+            match self.events.try_recv() {
+                Ok(Event::Received(msg)) => self.recv.push_back(msg),
+                Ok(Event::State {
+                    connected,
+                    peer,
+                    game,
+                }) => {
+                    self.connected = connected;
+                    self.peer = peer;
+                    self.game = game;
+                }
+                Ok(Event::Failed(e)) => return Err(e),
+                // the thread ends after reporting why, so there is nothing new
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+    fn send(&mut self, msg: Message) {
+        let _ = self.commands.send(Command::Send(msg));
+    }
+    fn recv(&mut self) -> Option<Message> {
+        self.recv.pop_front()
+    }
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+    /// Waits a moment for the thread to write what is still queued, as the game may be about to
+    /// exit
+    fn shutdown(&mut self) {
+        if !self.shutdown {
+            let _ = self.commands.send(Command::Shutdown);
+            let _ = self.done.recv_timeout(CLOSE_T);
+            self.shutdown = true;
+        }
+    }
+    fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+    fn peer(&self) -> Option<&Peer> {
+        self.peer.as_ref()
+    }
+    fn relay_game(&self) -> Option<GameId> {
+        self.game
+    }
+}
+
+impl Drop for BackgroundConnection {
+    fn drop(&mut self) {
+        if !self.shutdown {
+            let _ = self.commands.send(Command::Shutdown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    /// Takes a while to shut down and tells when it did
+    struct Slow {
+        closed: Arc<AtomicBool>,
+    }
+
+    impl Connection for Slow {
+        fn is_shutdown(&self) -> bool {
+            self.closed.load(Ordering::SeqCst)
+        }
+        fn shutdown(&mut self) {
+            thread::sleep(Duration::from_millis(100));
+            self.closed.store(true, Ordering::SeqCst);
+        }
+        fn is_connected(&self) -> bool {
+            true
+        }
+        fn poll(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn send(&mut self, _msg: Message) {}
+        fn recv(&mut self) -> Option<Message> {
+            None
+        }
+    }
+
+    fn start() -> (BackgroundConnection, Arc<AtomicBool>) {
+        let closed = Arc::new(AtomicBool::new(false));
+        let conn = Slow {
+            closed: closed.clone(),
+        };
+        let background = BackgroundConnection::start(move || Ok(Box::new(conn) as Box<_>));
+        (background, closed)
+    }
+
+    #[test]
+    fn shutdown_waits_for_the_connection() {
+        let (mut background, closed) = start();
+        background.shutdown();
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn dropping_cancels_the_connection() {
+        let (background, closed) = start();
+        let t = Instant::now();
+        drop(background);
+        assert!(t.elapsed() < Duration::from_millis(100));
+        while !closed.load(Ordering::SeqCst) {
+            assert!(t.elapsed() < CLOSE_T, "the connection was not shut down");
+            thread::sleep(POLL_T);
+        }
+    }
+}
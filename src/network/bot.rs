@@ -0,0 +1,130 @@
+use super::{Connection, Message, MessageQueue};
+use crate::board::{BoardMove, ChessBoard, Side};
+use crate::engine::book::Book;
+use crate::engine::tablebase::Tablebase;
+use crate::engine::{self, SearchLimits};
+use anyhow::{anyhow, Result};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+
+/// Computer opponent pretending to be the other end of a connection, so that the game does not
+/// need to know it is not playing against a human.
+///
+/// When the bot plays white it behaves like a [`super::host::Host`] and accepts or rejects the
+/// moves it receives, otherwise it behaves like a [`super::client::Client`].
+pub struct Bot {
+    board: ChessBoard,
+    side: Side,
+    to_move: Side,
+    limits: SearchLimits,
+    book: Option<Arc<Book>>,
+    tablebase: Option<Arc<Tablebase>>,
+    recv: MessageQueue,
+    /// the move found by the tables or the search
+    search: Option<Receiver<Option<BoardMove>>>,
+    shutdown: bool,
+}
+
+impl Bot {
+    pub fn new(side: Side, limits: SearchLimits) -> Self {
+        Self {
+            board: ChessBoard::new_full(),
+            side,
+            to_move: Side::White,
+            limits,
+            book: None,
+            tablebase: None,
+            recv: MessageQueue::new(),
+            search: None,
+            shutdown: false,
+        }
+    }
+    /// Book moves are played without searching for as long as the position is in the book
+    pub fn with_book(mut self, book: Option<Arc<Book>>) -> Self {
+        self.book = book;
+        self
+    }
+    /// Endgames the tables cover are played perfectly instead of searched
+    pub fn with_tablebase(mut self, tablebase: Option<Arc<Tablebase>>) -> Self {
+        self.tablebase = tablebase;
+        self
+    }
+    fn play(&mut self, m: BoardMove) -> Result<()> {
+        self.board
+            .move_piece(m)
+            .ok_or(anyhow!("bot found an invalid move"))?;
+        self.to_move = self.side.opposite();
+        self.recv.push_back(Message::Moved(m));
+        Ok(())
+    }
+    /// Probes the tables and searches on a separate thread so that the window does not freeze
+    /// while the bot thinks
+    fn start_search(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        let board = self.board.clone();
+        let side = self.side;
+        let limits = self.limits;
+        let tablebase = self.tablebase.clone();
+        std::thread::spawn(move || {
+            let known = tablebase.and_then(|t| t.best_move(&board, side).map(|(m, _)| m));
+            let found = known.or_else(|| engine::search(&board, side, &limits).map(|r| r.best));
+            // the receiver is gone if the game was closed in the meantime
+            let _ = tx.send(found);
+        });
+        self.search = Some(rx);
+    }
+}
+
+impl Connection for Bot {
+    fn poll(&mut self) -> Result<()> {
+        if self.shutdown {
+            return Ok(());
+        }
+        match self.search.as_ref().map(Receiver::try_recv) {
+            None if self.to_move == self.side => {
+                let book_move = self
+                    .book
+                    .as_ref()
+                    .and_then(|b| b.pick(&self.board, self.side));
+                match book_move {
+                    Some(m) => self.play(m)?,
+                    None => self.start_search(),
+                }
+            }
+            None | Some(Err(TryRecvError::Empty)) => (),
+            Some(Ok(Some(m))) => {
+                self.search = None;
+                self.play(m)?;
+            }
+            Some(Ok(None)) => return Err(anyhow!("bot has no moves left")),
+            Some(Err(TryRecvError::Disconnected)) => return Err(anyhow!("bot search failed")),
+        }
+        Ok(())
+    }
+    fn send(&mut self, msg: Message) {
+        match msg {
+            Message::Moved(m) if self.to_move != self.side => {
+                if self.board.move_piece(m).is_some() {
+                    self.to_move = self.side;
+                    self.recv.push_back(Message::Accepted());
+                } else {
+                    self.recv.push_back(Message::Rejected());
+                }
+            }
+            Message::Moved(_) => self.recv.push_back(Message::Rejected()),
+            _ => (),
+        }
+    }
+    fn recv(&mut self) -> Option<Message> {
+        self.recv.pop_front()
+    }
+    fn is_connected(&self) -> bool {
+        true
+    }
+    fn shutdown(&mut self) {
+        self.shutdown = true;
+    }
+    fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+}
@@ -1,89 +1,252 @@
-use super::{Message, MessageQueue, NetBuf, SessId};
-use anyhow::{anyhow, Result};
-use std::io::{ErrorKind, Read, Write};
-use std::net::{Shutdown, TcpStream};
+use super::handshake::{
+    Handshake, HandshakeError, Hello, Peer, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use super::secure::{Passphrase, Secret};
+use super::stream::{FramedStream, Frames};
+use super::websocket::{self, WsStream};
+use super::{Message, MessageQueue, SessId, RESUME_T};
+use anyhow::{anyhow, bail, Result};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Pause between attempts to get back to the host
+const RETRY_T: Duration = Duration::from_secs(1);
+/// Each attempt gives up after this long, so that the window keeps being drawn
+const CONNECT_T: Duration = Duration::from_millis(200);
+/// How long the first connection may take, which does not hold up the window
+const OPEN_T: Duration = Duration::from_secs(10);
+/// Hosts which do not answer the hello in time are given up on, they may be something else
+const ANSWER_T: Duration = Duration::from_secs(10);
 
 pub struct Client {
     state: ClientConnection,
     send: MessageQueue,
     recv: MessageQueue,
-    tcp: TcpStream,
+    tcp: Box<dyn Frames>,
     shutdown: bool,
     session_id: SessId,
-    buf: NetBuf,
+    /// what the client tells the host about itself
+    hello: Hello,
+    peer: Option<Peer>,
+    /// where the host was found, for reconnecting, `None` when a relay brought the host
+    addr: Option<SocketAddr>,
+    /// the host was given as a `ws://` address and is talked to through a WebSocket
+    url: Option<String>,
+    /// set while the connection is lost, the game is given up once it passes
+    resume_deadline: Option<Instant>,
+    next_attempt: Instant,
+    /// when the hello has to be answered by
+    answer_deadline: Instant,
+    /// the host has to prove it knows it, and the connection is encrypted with it, when set
+    passphrase: Option<Passphrase>,
+    /// the key stretched from the passphrase for the current handshake
+    secret: Option<Secret>,
 }
 
 pub enum ClientConnection {
     Begin,
-    SessionIdRead,
+    Welcome,
     Connected,
+    /// the connection dropped, a new one is tried every so often
+    Reconnecting,
 }
 
 impl Client {
-    pub fn new(address: &str) -> Result<Self> {
-        let tcp = TcpStream::connect(address)?;
-        tcp.set_nonblocking(true)?;
+    /// Hosts given as `ws://<address>` are joined through a WebSocket, like browsers do
+    pub fn new(address: &str, hello: Hello) -> Result<Self> {
+        let url = address.starts_with("ws://").then(|| address.to_owned());
+        let addr = match &url {
+            Some(url) => websocket::resolve(url)?,
+            None => address
+                .to_socket_addrs()?
+                .next()
+                .ok_or(anyhow!("{address} does not resolve"))?,
+        };
         Ok(Self {
             state: ClientConnection::Begin,
             send: MessageQueue::new(),
             recv: MessageQueue::new(),
-            tcp,
+            tcp: dial(addr, url.as_deref(), OPEN_T)?,
             shutdown: false,
             session_id: [0; 4],
-            buf: [0; 128],
+            hello,
+            peer: None,
+            addr: Some(addr),
+            url,
+            resume_deadline: None,
+            next_attempt: Instant::now(),
+            answer_deadline: Instant::now(),
+            passphrase: None,
+            secret: None,
         })
     }
-}
-
-impl super::Connection for Client {
-    // fn state(&self);
-    fn poll(&mut self) -> Result<()> {
-        self.state = match self.state {
-            ClientConnection::Begin => match self.tcp.write_all(&[0xDE, 0xAD, 0xBE, 0xEF, 0x02]) {
-                Err(e) => {
-                    if e.kind() == ErrorKind::WouldBlock {
-                        Ok(ClientConnection::Begin)
-                    } else {
-                        Err(anyhow!("{e}"))
-                    }
-                }
-                Ok(_) => Ok(ClientConnection::SessionIdRead),
-            },
-            ClientConnection::SessionIdRead => match self.tcp.read(&mut self.buf[..8]) {
-                Err(e) => {
-                    if e.kind() == ErrorKind::WouldBlock {
-                        Ok(ClientConnection::SessionIdRead)
-                    } else {
-                        Err(anyhow!("{e}"))
-                    }
-                }
-                Ok(recieved) => {
-                    if recieved != 8 {
-                        return Err(anyhow!("handshake length failure"));
-                    }
-                    if self.buf[..4] != [0xDE, 0xAD, 0xBE, 0xEF] {
-                        return Err(anyhow!("handshake header failure"));
+    /// Plays with the host the relay server paired the client with, on the connection to the
+    /// server. Losing it ends the game, the pairing cannot be resumed.
+    pub fn relayed(tcp: FramedStream, hello: Hello) -> Self {
+        Self {
+            state: ClientConnection::Begin,
+            send: MessageQueue::new(),
+            recv: MessageQueue::new(),
+            tcp: Box::new(tcp),
+            shutdown: false,
+            session_id: [0; 4],
+            hello,
+            peer: None,
+            addr: None,
+            url: None,
+            resume_deadline: None,
+            next_attempt: Instant::now(),
+            answer_deadline: Instant::now(),
+            passphrase: None,
+            secret: None,
+        }
+    }
+    pub fn with_passphrase(mut self, passphrase: Option<Passphrase>) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+    /// Waits for the host to welcome or refuse the hello
+    fn welcome(&mut self) -> Result<ClientConnection> {
+        let incompatible = |theirs| HandshakeError::Incompatible {
+            ours: PROTOCOL_VERSION,
+            theirs,
+        };
+        self.tcp.flush()?;
+        let mut frames = match self.tcp.recv_frames() {
+            Ok(frames) => frames.into_iter(),
+            // hosts of the unframed protocol hang up on a hello they do not understand, a host
+            // which welcomed us before surely does not
+            Err(e)
+                if self.hello.resume.is_none()
+                    && (self.tcp.is_closed()
+                        || e.downcast_ref::<std::io::Error>()
+                            .is_some_and(|e| e.kind() == ErrorKind::ConnectionReset)) =>
+            {
+                return Err(incompatible(None).into())
+            }
+            Err(e) => return Err(e),
+        };
+        let Some(frame) = frames.next() else {
+            if Instant::now() >= self.answer_deadline {
+                bail!("the host did not answer");
+            }
+            return Ok(ClientConnection::Welcome);
+        };
+        match Handshake::decode(&frame)? {
+            Handshake::Welcome(welcome)
+                if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&welcome.version) =>
+            {
+                if self.hello.resume.is_some() {
+                    if self.hello.resume != Some(welcome.session_id) {
+                        bail!("Handshake: the host resumed another session");
                     }
-                    let id: SessId = self.buf[4..8].try_into().unwrap();
-                    self.session_id = id;
-                    self.buf.fill(0);
-                    Ok(ClientConnection::Connected)
+                    // whatever did not get through is part of the game the host sends back
+                    self.send.clear();
+                    self.send.push_back(Message::SyncRequest());
+                    self.resume_deadline = None;
                 }
-            },
-            ClientConnection::Connected => {
-                if let Some(msgs) =
-                    super::recv_messages(&mut self.tcp, &mut self.buf, &self.session_id)?
-                {
-                    for msg in msgs {
-                        self.recv.push_back(msg);
+                match (&self.secret, &welcome.auth) {
+                    (None, _) => (),
+                    (Some(secret), Some(theirs)) if welcome.verify(secret, &self.hello) => {
+                        self.tcp.set_cipher(secret.session(&theirs.nonce, false));
                     }
+                    (Some(_), Some(_)) => return Err(HandshakeError::WrongPassphrase.into()),
+                    (Some(_), None) => return Err(HandshakeError::Unprotected.into()),
                 }
-                while let Some(mess) = self.send.pop_front() {
-                    super::send_message(&mut self.tcp, mess, &self.session_id).unwrap();
-                }
+                self.session_id = welcome.session_id;
+                self.peer = Some(Peer {
+                    name: welcome.name,
+                    version: welcome.version,
+                    features: welcome.features,
+                    time_control: welcome.time_control,
+                    side: welcome.side.opposite(),
+                });
+                // the host may have moved right after welcoming, which was sealed already
+                let frames = frames
+                    .map(|frame| self.tcp.open(&frame))
+                    .collect::<Result<Vec<_>>>()?;
+                self.recv
+                    .extend(super::read_messages(frames, &self.session_id)?);
                 Ok(ClientConnection::Connected)
             }
-        }?;
+            Handshake::Welcome(welcome) => Err(incompatible(Some(welcome.version)).into()),
+            Handshake::Refused(refused) if refused.version != PROTOCOL_VERSION => {
+                Err(incompatible(Some(refused.version)).into())
+            }
+            Handshake::Refused(refused) => Err(HandshakeError::Refused(refused.reason).into()),
+            Handshake::Hello(_) => bail!("Handshake: the host said hello"),
+        }
+    }
+    /// Tries a new connection once the pause since the last attempt is over
+    fn reconnect(&mut self) -> Result<ClientConnection> {
+        let now = Instant::now();
+        if now < self.next_attempt {
+            return Ok(ClientConnection::Reconnecting);
+        }
+        self.next_attempt = now + RETRY_T;
+        let Some(addr) = self.addr else {
+            bail!("no address to reconnect to");
+        };
+        self.tcp = dial(addr, self.url.as_deref(), CONNECT_T)?;
+        Ok(ClientConnection::Begin)
+    }
+    /// Once a game started a lost connection is not the end of it, the host is asked to resume
+    /// the session until the deadline
+    fn interrupted(&mut self, e: anyhow::Error) -> Result<ClientConnection> {
+        if self.peer.is_none() || self.addr.is_none() || e.is::<HandshakeError>() {
+            return Err(e);
+        }
+        if self.resume_deadline.is_none() {
+            eprintln!("lost the host: {e}");
+            self.resume_deadline = Some(Instant::now() + RESUME_T);
+            self.hello.resume = Some(self.session_id);
+        }
+        Ok(ClientConnection::Reconnecting)
+    }
+}
+
+/// A WebSocket when the host was given by its `ws://` address, raw frames otherwise
+fn dial(addr: SocketAddr, url: Option<&str>, timeout: Duration) -> Result<Box<dyn Frames>> {
+    if let Some(url) = url {
+        return Ok(Box::new(WsStream::connect(url, timeout)?));
+    }
+    let tcp = TcpStream::connect_timeout(&addr, timeout)?;
+    tcp.set_nonblocking(true)?;
+    Ok(Box::new(FramedStream::new(tcp)))
+}
+
+impl super::Connection for Client {
+    // fn state(&self);
+    fn poll(&mut self) -> Result<()> {
+        if self.resume_deadline.is_some_and(|t| Instant::now() >= t) {
+            bail!("could not get back to the host");
+        }
+        let next = match self.state {
+            ClientConnection::Begin => {
+                // a fresh nonce for every connection, so that no two sessions share keys
+                self.hello.auth = None;
+                self.secret = self.passphrase.as_ref().map(|p| self.hello.sign(p));
+                let hello = Handshake::Hello(self.hello.clone());
+                self.answer_deadline = Instant::now() + ANSWER_T;
+                self.tcp
+                    .send_frame(&hello.encode())
+                    .map(|_| ClientConnection::Welcome)
+            }
+            ClientConnection::Welcome => self.welcome(),
+            ClientConnection::Connected => super::exchange(
+                self.tcp.as_mut(),
+                &self.session_id,
+                &mut self.send,
+                &mut self.recv,
+            )
+            .map(|_| ClientConnection::Connected),
+            ClientConnection::Reconnecting => self.reconnect(),
+        };
+        self.state = match next {
+            Ok(state) => state,
+            Err(e) => self.interrupted(e)?,
+        };
         Ok(())
     }
     fn send(&mut self, msg: Message) {
@@ -97,13 +260,14 @@ impl super::Connection for Client {
     }
     fn shutdown(&mut self) {
         if !self.shutdown {
-            self.tcp
-                .shutdown(Shutdown::Both)
-                .expect("TcpStream shutdown failed");
+            super::close(self.tcp.as_mut(), &self.session_id, &mut self.send);
             self.shutdown = true;
         }
     }
     fn is_shutdown(&self) -> bool {
         self.shutdown
     }
+    fn peer(&self) -> Option<&Peer> {
+        self.peer.as_ref()
+    }
 }
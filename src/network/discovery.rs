@@ -0,0 +1,274 @@
+//! Finding games on the local network. A host waiting for a client announces its game with a UDP
+//! broadcast every second, and the setup screen lists the games it heard of lately so that they
+//! can be joined without typing an address. Announcements are single datagrams starting with the
+//! handshake's magic number, so that strangers on the port are told apart.
+
+use super::handshake::{
+    self, clean_name, get_string, get_time_control, put_string, put_time_control,
+};
+use super::MAGIC_N;
+use crate::clock::TimeControl;
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Where announcements are sent to and listened for
+pub const DISCOVERY_PORT: u16 = 47_474;
+/// Pause between the announcements of a host
+const ANNOUNCE_T: Duration = Duration::from_secs(1);
+/// Games which were not announced for this long are gone from the list
+const EXPIRE_T: Duration = Duration::from_secs(4);
+/// Handshake frames are kinds 1 to 3
+const ANNOUNCEMENT_KIND: u8 = 0x04;
+/// Enough for the longest announcement
+const MAX_DATAGRAM: usize = 1024;
+
+/// A game waiting for a client
+#[derive(Clone, Debug, PartialEq)]
+pub struct Announcement {
+    /// the protocol version the host speaks
+    pub version: u8,
+    /// the host's player
+    pub name: String,
+    /// the rules played by, only standard chess so far
+    pub variant: String,
+    pub time_control: Option<TimeControl>,
+    /// where the host listens, on the address the announcement came from
+    pub port: u16,
+    /// clients need the passphrase to join
+    pub protected: bool,
+}
+
+impl Announcement {
+    pub fn encode(&self) -> BytesMut {
+        let mut bytes = BytesMut::new();
+        bytes.put(MAGIC_N.as_slice());
+        bytes.put_u8(ANNOUNCEMENT_KIND);
+        bytes.put_u8(self.version);
+        bytes.put_u16(self.port);
+        put_string(&mut bytes, &self.name);
+        put_string(&mut bytes, &self.variant);
+        put_time_control(&mut bytes, self.time_control);
+        bytes.put_u8(self.protected as u8);
+        bytes
+    }
+    /// Fields added by later versions come after the ones known here and are ignored
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MAGIC_N.len() + 4
+            || bytes[..MAGIC_N.len()] != MAGIC_N
+            || bytes[MAGIC_N.len()] != ANNOUNCEMENT_KIND
+        {
+            bail!("Discovery: not an announcement");
+        }
+        bytes.advance(MAGIC_N.len() + 1);
+        let version = bytes.get_u8();
+        let port = bytes.get_u16();
+        let name = get_string(&mut bytes)?;
+        let variant = get_string(&mut bytes)?;
+        let time_control = get_time_control(&mut bytes)?;
+        let protected = bytes.has_remaining() && bytes.get_u8() != 0;
+        Ok(Self {
+            version,
+            name: clean_name(&name),
+            variant: clean_name(&variant),
+            time_control,
+            port,
+            protected,
+        })
+    }
+}
+
+/// Sends a host's announcements
+pub struct Beacon {
+    socket: UdpSocket,
+    target: SocketAddr,
+    next_t: Instant,
+}
+
+impl Beacon {
+    /// Hosts listening on the loopback address can only be joined from the same machine, so
+    /// their games are only announced there, with its broadcast address so that every window
+    /// listening gets them
+    pub fn new(listening: SocketAddr) -> Result<Self> {
+        let (bind, target) = if listening.ip().is_loopback() {
+            (Ipv4Addr::LOCALHOST, Ipv4Addr::new(127, 255, 255, 255))
+        } else {
+            (Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST)
+        };
+        let socket = UdpSocket::bind((bind, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            target: (target, DISCOVERY_PORT).into(),
+            next_t: Instant::now(),
+        })
+    }
+    /// Whether the next announcement is due, which restarts the pause when it is
+    pub fn is_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_t {
+            return false;
+        }
+        self.next_t = now + ANNOUNCE_T;
+        true
+    }
+    pub fn send(&self, announcement: &Announcement) -> Result<()> {
+        match self.socket.send_to(&announcement.encode(), self.target) {
+            Ok(_) => Ok(()),
+            // dropped like any datagram, the next one may get through
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// A game heard of on the local network
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanGame {
+    /// where to connect to
+    pub addr: SocketAddr,
+    pub announcement: Announcement,
+    seen: Instant,
+}
+
+/// Listens for announcements, several windows on one machine can do so at once
+pub struct Finder {
+    socket: UdpSocket,
+    games: Vec<LanGame>,
+}
+
+impl Finder {
+    pub fn new() -> Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_broadcast(true)?;
+        let addr: SocketAddr = (Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT).into();
+        socket.bind(&addr.into())?;
+        let socket: UdpSocket = socket.into();
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            games: vec![],
+        })
+    }
+    /// Reads every announcement which arrived and forgets the games which went quiet. Games
+    /// whose hosts speak a protocol this build does not are left out.
+    pub fn poll(&mut self) -> Result<()> {
+        let mut buf = [0; MAX_DATAGRAM];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let Ok(announcement) = Announcement::decode(&buf[..len]) else {
+                continue;
+            };
+            if handshake::negotiate(announcement.version).is_none() {
+                continue;
+            }
+            let addr = SocketAddr::new(from.ip(), announcement.port);
+            let game = LanGame {
+                addr,
+                announcement,
+                seen: Instant::now(),
+            };
+            match self.games.iter_mut().find(|g| g.addr == addr) {
+                Some(known) => *known = game,
+                None => self.games.push(game),
+            }
+        }
+        self.games.retain(|g| g.seen.elapsed() < EXPIRE_T);
+        Ok(())
+    }
+    /// In the order they were first heard of
+    pub fn games(&self) -> &[LanGame] {
+        &self.games
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::handshake::{LEGACY_VERSION, PROTOCOL_VERSION};
+
+    fn announcement() -> Announcement {
+        Announcement {
+            version: PROTOCOL_VERSION,
+            name: "alice".to_owned(),
+            variant: "standard".to_owned(),
+            time_control: Some(TimeControl::from_secs(300, 2, 0)),
+            port: 4321,
+            protected: true,
+        }
+    }
+
+    #[test]
+    fn announcements_survive_encoding() {
+        let game = announcement();
+        assert_eq!(Announcement::decode(&game.encode()).unwrap(), game);
+        let untimed = Announcement {
+            time_control: None,
+            protected: false,
+            ..announcement()
+        };
+        assert_eq!(Announcement::decode(&untimed.encode()).unwrap(), untimed);
+        // later versions may add fields
+        let mut longer = game.encode();
+        longer.put_u32(7);
+        assert_eq!(Announcement::decode(&longer).unwrap(), game);
+    }
+
+    #[test]
+    fn strangers_are_not_announcements() {
+        let game = announcement().encode();
+        let mut handshake = game.clone();
+        handshake[MAGIC_N.len()] = 0x01;
+        let foreign: [&[u8]; 6] = [
+            b"",
+            b"M-SEARCH * HTTP/1.1\r\n",
+            &MAGIC_N,
+            &handshake,
+            // cut short in the name and in the time control
+            &game[..MAGIC_N.len() + 4],
+            &game[..game.len() - 6],
+        ];
+        for datagram in foreign {
+            assert!(Announcement::decode(datagram).is_err(), "{datagram:?}");
+        }
+    }
+
+    #[test]
+    fn finders_list_games_until_they_go_quiet() {
+        let mut finder = Finder::new().unwrap();
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let target = (Ipv4Addr::LOCALHOST, DISCOVERY_PORT);
+        sender.send_to(b"not a game", target).unwrap();
+        let old = Announcement {
+            version: LEGACY_VERSION,
+            port: 1234,
+            ..announcement()
+        };
+        sender.send_to(&old.encode(), target).unwrap();
+        sender.send_to(&announcement().encode(), target).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while finder.games().is_empty() {
+            assert!(Instant::now() < deadline, "the game was not heard of");
+            finder.poll().unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // only the game in a protocol spoken here is listed
+        assert_eq!(finder.games().len(), 1);
+        assert_eq!(finder.games()[0].announcement, announcement());
+        assert_eq!(finder.games()[0].addr.port(), 4321);
+
+        finder.games[0].seen = Instant::now().checked_sub(EXPIRE_T).unwrap();
+        finder.poll().unwrap();
+        assert!(finder.games().is_empty());
+    }
+}
@@ -0,0 +1,632 @@
+//! The first frames of a connection, in which both ends introduce themselves. The client says
+//! hello with its protocol version, the optional features it supports and the player's name, and
+//! the host either welcomes it with what both have in common and the session id, or refuses it.
+//! A client which lost its connection says hello again with the session id to resume the game.
+//! Spectators say hello too, they get to see the game but not to play in it. When the game is
+//! played with clocks the client proposes a time control in its hello and the host's welcome has
+//! the one agreed on, which is the host's own if it set one. Sides are settled the same way, with
+//! a coin toss when neither player minds which one they get. When a passphrase is set the hello
+//! and the welcome each carry a nonce with a proof of knowing it, and everything after them is
+//! encrypted, see [`super::secure`].
+
+use super::secure::{Auth, Passphrase, Secret};
+use super::{SessId, MAGIC_N};
+use crate::board::Side;
+use crate::clock::TimeControl;
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::time::Duration;
+
+/// Spoken by this build
+pub const PROTOCOL_VERSION: u8 = 3;
+/// The oldest version this build still talks to, the first one with frames
+pub const MIN_PROTOCOL_VERSION: u8 = 3;
+/// Version 2 was a bare `DEADBEEF 02` without frames, which is only recognised to name it in the
+/// error
+pub const LEGACY_VERSION: u8 = 2;
+/// Longer names are cut short
+pub const MAX_NAME: usize = 32;
+
+/// Optional parts of the protocol, a connection uses the ones both ends support
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features(u8);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+    /// choosing the piece a pawn is promoted to
+    pub const PROMOTION: Self = Self(1);
+    pub const CLOCKS: Self = Self(1 << 1);
+    pub const CHAT: Self = Self(1 << 2);
+    pub const VARIANTS: Self = Self(1 << 3);
+    /// What this build offers
+    pub const SUPPORTED: Self = Self(Self::CHAT.0 | Self::CLOCKS.0);
+    const NAMES: [(Self, &'static str); 4] = [
+        (Self::PROMOTION, "promotion"),
+        (Self::CLOCKS, "clocks"),
+        (Self::CHAT, "chat"),
+        (Self::VARIANTS, "variants"),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// The features both sets have
+    pub fn common(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+    /// Bits of features this build does not know about are kept, they just never end up in
+    /// [`Features::common`] with our own set
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+}
+
+impl std::ops::BitOr for Features {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::fmt::Display for Features {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = Self::NAMES
+            .iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
+
+/// The version both ends speak, the older of the two
+/// # Returns
+/// `None` when that is older than what this build still supports
+pub fn negotiate(theirs: u8) -> Option<u8> {
+    let version = theirs.min(PROTOCOL_VERSION);
+    (version >= MIN_PROTOCOL_VERSION).then_some(version)
+}
+
+/// The player's name from the environment, for when none was given
+pub fn default_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "Player".to_owned())
+}
+
+/// What a client connects for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Role {
+    #[default]
+    Player, // 0x00
+    /// sees every move of the game, anything it sends is ignored
+    Spectator, // 0x01
+}
+
+/// How one end introduces itself, the client sends it and the host keeps its own to answer with
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub version: u8,
+    pub features: Features,
+    pub name: String,
+    /// the session of the game the client drops back into
+    pub resume: Option<SessId>,
+    pub role: Role,
+    /// the host's setting, or the client's proposal when the host has none
+    pub time_control: Option<TimeControl>,
+    /// the side the player wants to play, either is fine when `None`
+    pub side: Option<Side>,
+    /// proves that the client knows the passphrase
+    pub auth: Option<Auth>,
+}
+
+impl Hello {
+    /// This build's version and features, with the name cleaned up to be shown to the peer
+    pub fn new(name: &str) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: Features::SUPPORTED,
+            name: clean_name(name),
+            resume: None,
+            role: Role::Player,
+            time_control: None,
+            side: None,
+            auth: None,
+        }
+    }
+    pub fn spectator(name: &str) -> Self {
+        Self {
+            role: Role::Spectator,
+            ..Self::new(name)
+        }
+    }
+    pub fn with_time_control(mut self, time_control: Option<TimeControl>) -> Self {
+        self.time_control = time_control;
+        self
+    }
+    pub fn with_side(mut self, side: Option<Side>) -> Self {
+        self.side = side;
+        self
+    }
+    /// Proves that the client knows the passphrase with a fresh nonce, the proof covers
+    /// everything else the hello says
+    /// # Returns
+    /// the key of the handshake, which the welcome is checked with
+    pub fn sign(&mut self, passphrase: &Passphrase) -> Secret {
+        let nonce = rand::random();
+        let secret = passphrase.stretch(&nonce);
+        self.auth = Some(Auth {
+            nonce,
+            proof: [0; 32],
+        });
+        let proof = secret.proof(b"client", &[&self.unsigned()]);
+        self.auth = Some(Auth { nonce, proof });
+        secret
+    }
+    /// The key of the handshake when the hello proves that the client knows the passphrase
+    pub fn verify(&self, passphrase: &Passphrase) -> Option<Secret> {
+        let auth = self.auth.as_ref()?;
+        let secret = passphrase.stretch(&auth.nonce);
+        secret
+            .verify(b"client", &[&self.unsigned()], &auth.proof)
+            .then_some(secret)
+    }
+    /// The hello as it is sent, but with the proof zeroed
+    fn unsigned(&self) -> Bytes {
+        let mut hello = self.clone();
+        if let Some(auth) = hello.auth.as_mut() {
+            auth.proof = [0; 32];
+        }
+        Handshake::Hello(hello).encode()
+    }
+}
+
+/// What the host and the client agreed on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Welcome {
+    pub version: u8,
+    pub features: Features,
+    pub session_id: SessId,
+    /// the host's player
+    pub name: String,
+    /// the game is played without clocks when `None`
+    pub time_control: Option<TimeControl>,
+    /// the side the client plays
+    pub side: Side,
+    /// proves that the host knows the passphrase too, `None` when it has none
+    pub auth: Option<Auth>,
+}
+
+impl Welcome {
+    /// Proves that the host knows the passphrase with a nonce of its own, the proof covers the
+    /// client's hello too so that it cannot be replayed to another client
+    pub fn sign(&mut self, secret: &Secret, hello: &Hello) {
+        let nonce = rand::random();
+        self.auth = Some(Auth {
+            nonce,
+            proof: [0; 32],
+        });
+        let [hello, welcome] = self.transcript(hello);
+        let proof = secret.proof(b"host", &[&hello, &welcome]);
+        self.auth = Some(Auth { nonce, proof });
+    }
+    pub fn verify(&self, secret: &Secret, hello: &Hello) -> bool {
+        let [hello, welcome] = self.transcript(hello);
+        self.auth
+            .as_ref()
+            .is_some_and(|auth| secret.verify(b"host", &[&hello, &welcome], &auth.proof))
+    }
+    /// The hello as it was sent and the welcome with its proof zeroed
+    fn transcript(&self, hello: &Hello) -> [Bytes; 2] {
+        let mut welcome = self.clone();
+        if let Some(auth) = welcome.auth.as_mut() {
+            auth.proof = [0; 32];
+        }
+        [
+            Handshake::Hello(hello.clone()).encode(),
+            Handshake::Welcome(welcome).encode(),
+        ]
+    }
+}
+
+/// The host does not play with the client, it hangs up after sending this
+#[derive(Clone, Debug, PartialEq)]
+pub struct Refused {
+    /// the host's own version, so that the client can tell who is outdated
+    pub version: u8,
+    pub reason: String,
+}
+
+/// The other end of an established connection
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peer {
+    pub name: String,
+    /// the version both ends speak
+    pub version: u8,
+    /// the features both ends support
+    pub features: Features,
+    /// what both ends agreed on
+    pub time_control: Option<TimeControl>,
+    /// the side the peer plays
+    pub side: Side,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Handshake {
+    Hello(Hello),     // 0x01
+    Welcome(Welcome), // 0x02
+    Refused(Refused), // 0x03
+}
+
+impl Handshake {
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        bytes.put(MAGIC_N.as_slice());
+        match self {
+            Handshake::Hello(hello) => {
+                bytes.put_u8(0x01);
+                bytes.put_u8(hello.version);
+                bytes.put_u8(hello.features.bits());
+                put_string(&mut bytes, &hello.name);
+                match hello.resume {
+                    Some(session_id) => {
+                        bytes.put_u8(1);
+                        bytes.put(session_id.as_slice());
+                    }
+                    None => bytes.put_u8(0),
+                }
+                bytes.put_u8(match hello.role {
+                    Role::Player => 0x00,
+                    Role::Spectator => 0x01,
+                });
+                put_time_control(&mut bytes, hello.time_control);
+                bytes.put_u8(match hello.side {
+                    None => 0x00,
+                    Some(Side::White) => 0x01,
+                    Some(Side::Black) => 0x02,
+                });
+                put_auth(&mut bytes, hello.auth.as_ref());
+            }
+            Handshake::Welcome(welcome) => {
+                bytes.put_u8(0x02);
+                bytes.put_u8(welcome.version);
+                bytes.put_u8(welcome.features.bits());
+                bytes.put(welcome.session_id.as_slice());
+                put_string(&mut bytes, &welcome.name);
+                put_time_control(&mut bytes, welcome.time_control);
+                bytes.put_u8(match welcome.side {
+                    Side::White => 0x01,
+                    Side::Black => 0x02,
+                });
+                put_auth(&mut bytes, welcome.auth.as_ref());
+            }
+            Handshake::Refused(refused) => {
+                bytes.put_u8(0x03);
+                bytes.put_u8(refused.version);
+                put_string(&mut bytes, &refused.reason);
+            }
+        }
+        bytes.into()
+    }
+    /// Fields added by later versions come after the ones known here and are ignored
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MAGIC_N.len() + 2 || bytes[..MAGIC_N.len()] != MAGIC_N {
+            bail!("Handshake: not a handshake");
+        }
+        bytes.advance(MAGIC_N.len());
+        let kind = bytes.get_u8();
+        let version = bytes.get_u8();
+        match kind {
+            0x01 => {
+                let features = Features::from_bits(get_u8(&mut bytes)?);
+                let name = get_string(&mut bytes)?;
+                let resume = match get_u8(&mut bytes)? {
+                    0 => None,
+                    _ => Some(get_session_id(&mut bytes)?),
+                };
+                let role = match get_u8(&mut bytes)? {
+                    0x00 => Role::Player,
+                    0x01 => Role::Spectator,
+                    _ => bail!("Handshake: invalid role"),
+                };
+                let time_control = get_time_control(&mut bytes)?;
+                let side = match get_u8(&mut bytes)? {
+                    0x00 => None,
+                    0x01 => Some(Side::White),
+                    0x02 => Some(Side::Black),
+                    _ => bail!("Handshake: invalid side"),
+                };
+                Ok(Handshake::Hello(Hello {
+                    version,
+                    features,
+                    name: clean_name(&name),
+                    resume,
+                    role,
+                    time_control,
+                    side,
+                    auth: get_auth(&mut bytes)?,
+                }))
+            }
+            0x02 => {
+                let features = Features::from_bits(get_u8(&mut bytes)?);
+                let session_id = get_session_id(&mut bytes)?;
+                let name = get_string(&mut bytes)?;
+                Ok(Handshake::Welcome(Welcome {
+                    version,
+                    features,
+                    session_id,
+                    name: clean_name(&name),
+                    time_control: get_time_control(&mut bytes)?,
+                    side: match get_u8(&mut bytes)? {
+                        0x01 => Side::White,
+                        0x02 => Side::Black,
+                        _ => bail!("Handshake: invalid side"),
+                    },
+                    auth: get_auth(&mut bytes)?,
+                }))
+            }
+            0x03 => Ok(Handshake::Refused(Refused {
+                version,
+                reason: get_string(&mut bytes)?,
+            })),
+            _ => bail!("Handshake: invalid kind"),
+        }
+    }
+}
+
+/// Why a connection could not be set up, shown to the player rather than treated as a failure
+#[derive(Clone, Debug, PartialEq)]
+pub enum HandshakeError {
+    /// `theirs` is `None` when the peer did not even say which version it speaks
+    Incompatible { ours: u8, theirs: Option<u8> },
+    /// the host turned the client away for another reason
+    Refused(String),
+    /// the peer's proof does not match the passphrase set here
+    WrongPassphrase,
+    /// a passphrase is set here but the host does not use one
+    Unprotected,
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeError::Incompatible {
+                ours,
+                theirs: Some(theirs),
+            } => write!(
+                f,
+                "Incompatible version: the peer speaks protocol {theirs}, this build {ours}"
+            ),
+            HandshakeError::Incompatible { ours, theirs: None } => write!(
+                f,
+                "Incompatible version: the peer does not speak protocol {ours}"
+            ),
+            HandshakeError::Refused(reason) => write!(f, "Refused by the host: {reason}"),
+            HandshakeError::WrongPassphrase => {
+                write!(f, "Wrong passphrase: the peer uses another one")
+            }
+            HandshakeError::Unprotected => {
+                write!(f, "Not encrypted: the host does not use a passphrase")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+/// Names are shown on the other end, so control characters are dropped and the length is capped
+pub fn clean_name(name: &str) -> String {
+    let mut ret = String::new();
+    for c in name.trim().chars().filter(|c| !c.is_control()) {
+        if ret.len() + c.len_utf8() > MAX_NAME {
+            break;
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+pub fn put_string(bytes: &mut BytesMut, s: &str) {
+    // a character split by the cut is replaced when decoding
+    let len = s.len().min(u8::MAX as usize);
+    bytes.put_u8(len as u8);
+    bytes.put(&s.as_bytes()[..len]);
+}
+
+pub fn get_u8(bytes: &mut &[u8]) -> Result<u8> {
+    if !bytes.has_remaining() {
+        bail!("Handshake: truncated");
+    }
+    Ok(bytes.get_u8())
+}
+
+/// A flag and, if it is set, the base time, the increment and the delay in milliseconds
+pub fn put_time_control(bytes: &mut BytesMut, time_control: Option<TimeControl>) {
+    match time_control {
+        Some(tc) => {
+            bytes.put_u8(1);
+            for d in [tc.base, tc.increment, tc.delay] {
+                bytes.put_u32(d.as_millis().min(u32::MAX as u128) as u32);
+            }
+        }
+        None => bytes.put_u8(0),
+    }
+}
+
+pub fn get_time_control(bytes: &mut &[u8]) -> Result<Option<TimeControl>> {
+    if get_u8(bytes)? == 0 {
+        return Ok(None);
+    }
+    if bytes.remaining() < 12 {
+        bail!("Handshake: truncated time control");
+    }
+    let mut millis = || Duration::from_millis(bytes.get_u32() as u64);
+    let tc = TimeControl {
+        base: millis(),
+        increment: millis(),
+        delay: millis(),
+    };
+    if tc.base.is_zero() {
+        bail!("Handshake: time control without base time");
+    }
+    Ok(Some(tc))
+}
+
+/// A flag and, if it is set, the nonce followed by the proof
+fn put_auth(bytes: &mut BytesMut, auth: Option<&Auth>) {
+    match auth {
+        Some(auth) => {
+            bytes.put_u8(1);
+            bytes.put(auth.nonce.as_slice());
+            bytes.put(auth.proof.as_slice());
+        }
+        None => bytes.put_u8(0),
+    }
+}
+
+fn get_auth(bytes: &mut &[u8]) -> Result<Option<Auth>> {
+    if get_u8(bytes)? == 0 {
+        return Ok(None);
+    }
+    if bytes.remaining() < 48 {
+        bail!("Handshake: truncated passphrase proof");
+    }
+    let mut auth = Auth {
+        nonce: [0; 16],
+        proof: [0; 32],
+    };
+    bytes.copy_to_slice(&mut auth.nonce);
+    bytes.copy_to_slice(&mut auth.proof);
+    Ok(Some(auth))
+}
+
+fn get_session_id(bytes: &mut &[u8]) -> Result<SessId> {
+    if bytes.remaining() < 4 {
+        bail!("Handshake: truncated session id");
+    }
+    let mut session_id: SessId = [0; 4];
+    bytes.copy_to_slice(&mut session_id);
+    Ok(session_id)
+}
+
+pub fn get_string(bytes: &mut &[u8]) -> Result<String> {
+    let len = get_u8(bytes)? as usize;
+    if bytes.remaining() < len {
+        bail!("Handshake: truncated string");
+    }
+    let s = String::from_utf8_lossy(&bytes[..len]).into_owned();
+    bytes.advance(len);
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello() -> Hello {
+        Hello {
+            resume: Some([1, 2, 3, 4]),
+            role: Role::Spectator,
+            side: Some(Side::Black),
+            ..Hello::new("tester")
+        }
+        .with_time_control(Some(TimeControl::from_secs(300, 3, 0)))
+    }
+
+    #[test]
+    fn hello_round_trips() {
+        let hello = hello();
+        let decoded = Handshake::decode(&Handshake::Hello(hello.clone()).encode()).unwrap();
+        assert_eq!(decoded, Handshake::Hello(hello));
+    }
+
+    fn welcome() -> Welcome {
+        Welcome {
+            version: PROTOCOL_VERSION,
+            features: Features::SUPPORTED,
+            session_id: [9, 8, 7, 6],
+            name: "host".to_owned(),
+            time_control: None,
+            side: Side::White,
+            auth: None,
+        }
+    }
+
+    #[test]
+    fn welcome_round_trips() {
+        let welcome = welcome();
+        let decoded = Handshake::decode(&Handshake::Welcome(welcome.clone()).encode()).unwrap();
+        assert_eq!(decoded, Handshake::Welcome(welcome));
+    }
+
+    #[test]
+    fn truncated_hello_is_an_error() {
+        let bytes = Handshake::Hello(hello()).encode();
+        for len in MAGIC_N.len() + 2..bytes.len() {
+            assert!(
+                Handshake::decode(&bytes[..len]).is_err(),
+                "{len} bytes decoded"
+            );
+        }
+    }
+
+    #[test]
+    fn later_fields_are_ignored() {
+        let mut bytes = BytesMut::from(&Handshake::Hello(hello()).encode()[..]);
+        bytes.put_slice(b"from the future");
+        assert_eq!(
+            Handshake::decode(&bytes).unwrap(),
+            Handshake::Hello(hello())
+        );
+    }
+
+    #[test]
+    fn only_framed_versions_are_spoken() {
+        assert_eq!(negotiate(LEGACY_VERSION), None);
+        assert_eq!(negotiate(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1), Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn proofs_cover_the_whole_handshake() {
+        let passphrase = Passphrase::new("open sesame");
+        let mut hello = hello();
+        let client = hello.sign(&passphrase);
+        // what the host decodes is what the client signed
+        let Handshake::Hello(sent) =
+            Handshake::decode(&Handshake::Hello(hello.clone()).encode()).unwrap()
+        else {
+            panic!("not a hello");
+        };
+        let host = sent.verify(&passphrase).unwrap();
+        assert!(sent.verify(&Passphrase::new("open sesame!")).is_none());
+        let tampered = Hello {
+            side: Some(Side::White),
+            ..sent.clone()
+        };
+        assert!(tampered.verify(&passphrase).is_none());
+
+        let mut welcome = welcome();
+        welcome.sign(&host, &sent);
+        assert!(welcome.verify(&client, &hello));
+        // a welcome meant for another hello or changed on the way does not pass
+        assert!(!welcome.verify(&client, &tampered));
+        let tampered = Welcome {
+            side: Side::Black,
+            ..welcome.clone()
+        };
+        assert!(!tampered.verify(&client, &hello));
+        assert!(!Welcome {
+            auth: None,
+            ..welcome
+        }
+        .verify(&client, &hello));
+    }
+}
@@ -1,140 +1,498 @@
-use super::{Message, MessageQueue, NetBuf, SessId, MAGIC_N};
-use anyhow::{anyhow, Result};
-use std::io::{ErrorKind, Read, Write};
-use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use super::discovery::{Announcement, Beacon};
+use super::handshake::{
+    self, Features, Handshake, HandshakeError, Hello, Peer, Refused, Role, Welcome, LEGACY_VERSION,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use super::secure::{Passphrase, Secret};
+use super::stream::{FramedStream, Frames};
+use super::websocket::Upgrade;
+use super::{Message, MessageQueue, SessId, MAGIC_N, RESUME_T};
+use crate::board::{BoardMove, Side};
+use anyhow::{anyhow, bail, Result};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
+/// Connections which do not get through to a hello in time are let go, so that half-open ones
+/// do not pile up
+const HELLO_T: Duration = Duration::from_secs(10);
+
+/// Keeps listening for the whole game, so that a client which lost its connection can come back
+/// with the session id and spectators can join
 pub struct Host {
-    state: HostConnection,
     send: MessageQueue,
     recv: MessageQueue,
-    list: TcpListener,
-    tcp: Option<TcpStream>,
+    /// `None` when the client was brought by a relay, which is the only way it can come
+    list: Option<TcpListener>,
+    /// the player's connection, `None` until a client joined and while it is away
+    tcp: Option<Box<dyn Frames>>,
+    /// accepted connections which did not say hello yet, with when they were accepted
+    pending: Vec<(Incoming, Instant)>,
+    /// set while the player is away, the game is given up once it passes
+    resume_deadline: Option<Instant>,
     shutdown: bool,
     addr: SocketAddr,
     session_id: SessId,
-    buf: NetBuf,
-}
-
-enum HostConnection {
-    Begin,
-    HandshakeRead,
-    HandshakeRespond,
-    Connected,
+    /// what the host tells clients about itself
+    hello: Hello,
+    /// the player, kept while they are away
+    peer: Option<Peer>,
+    /// each with a session id of its own, so that none of them can take over the player's
+    spectators: Vec<(Box<dyn Frames>, SessId)>,
+    /// every move made final so far, followed through the messages of the host
+    history: Vec<BoardMove>,
+    /// the client's last move, final once the host accepts it
+    proposed: Option<BoardMove>,
+    /// the last time the host's clocks were sent, for spectators which join later
+    clock: Option<Message>,
+    /// clients have to know it and their connections are encrypted with it when set
+    passphrase: Option<Passphrase>,
+    /// tells the local network about the game while it waits for a client, `None` when
+    /// broadcasting is not possible there
+    beacon: Option<Beacon>,
 }
 
 impl Host {
-    pub fn new(address: &str) -> Result<Self> {
+    pub fn new(address: &str, hello: Hello) -> Result<Self> {
         let list = TcpListener::bind(address)?;
         let session_id: SessId = rand::random();
         list.set_nonblocking(true)?;
+        // the game can still be joined by its address
+        let beacon = Beacon::new(list.local_addr()?)
+            .inspect_err(|e| eprintln!("could not announce the game: {e}"))
+            .ok();
         Ok(Self {
-            state: HostConnection::Begin,
-            list,
+            list: Some(list),
             session_id,
-            buf: [0; 128],
             tcp: None,
+            pending: vec![],
+            resume_deadline: None,
             addr: SocketAddr::from_str(address)?,
             recv: MessageQueue::new(),
             send: MessageQueue::new(),
             shutdown: false,
+            hello,
+            peer: None,
+            spectators: vec![],
+            history: vec![],
+            proposed: None,
+            clock: None,
+            passphrase: None,
+            beacon,
+        })
+    }
+    /// Hosts the game for a client the relay server paired the host with, on the connection to
+    /// the server. Losing it ends the game, there is no way back to the same client.
+    pub fn relayed(tcp: FramedStream, hello: Hello) -> Result<Self> {
+        Ok(Self {
+            list: None,
+            session_id: rand::random(),
+            addr: tcp.get_ref().peer_addr()?,
+            tcp: None,
+            pending: vec![(Incoming::Ready(Box::new(tcp)), Instant::now())],
+            resume_deadline: None,
+            recv: MessageQueue::new(),
+            send: MessageQueue::new(),
+            shutdown: false,
+            hello,
+            peer: None,
+            spectators: vec![],
+            history: vec![],
+            proposed: None,
+            clock: None,
+            passphrase: None,
+            beacon: None,
         })
     }
+    pub fn with_passphrase(mut self, passphrase: Option<Passphrase>) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+    fn accept(&mut self) -> Result<()> {
+        let Some(list) = self.list.as_ref() else {
+            return Ok(());
+        };
+        loop {
+            match list.accept() {
+                Ok((tcp, _)) => {
+                    tcp.set_nonblocking(true)?;
+                    self.pending.push((Incoming::Sniffing(tcp), Instant::now()));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(anyhow!("{e}")),
+            }
+        }
+    }
+    /// Lets the local network know about the game while nobody plays in it
+    fn announce(&mut self) {
+        if self.peer.is_some() {
+            return;
+        }
+        let Some(beacon) = self.beacon.as_mut() else {
+            return;
+        };
+        if !beacon.is_due() {
+            return;
+        }
+        let announcement = Announcement {
+            version: PROTOCOL_VERSION,
+            name: self.hello.name.clone(),
+            variant: "standard".to_owned(),
+            time_control: self.hello.time_control,
+            port: self
+                .list
+                .as_ref()
+                .and_then(|list| list.local_addr().ok())
+                .map_or(0, |addr| addr.port()),
+            protected: self.passphrase.is_some(),
+        };
+        if let Err(e) = beacon.send(&announcement) {
+            eprintln!("could not announce the game: {e}");
+            self.beacon = None;
+        }
+    }
+    /// Waits for the client's hello and welcomes it with what both ends support. Clients which
+    /// cannot be played with are let go.
+    /// # Returns
+    /// the connection back while its hello has not arrived yet
+    fn handshake(&mut self, mut tcp: Box<dyn Frames>) -> Result<Option<Box<dyn Frames>>> {
+        let frames = match tcp.recv_frames() {
+            Ok(frames) => frames,
+            // version 2 clients send the magic without a length in front
+            Err(_) if tcp.buffered().starts_with(&MAGIC_N[..2]) => {
+                return Err(turn_away(tcp, &self.session_id, Some(LEGACY_VERSION)))
+            }
+            Err(e) => {
+                eprintln!("client left during the handshake: {e}");
+                return Ok(None);
+            }
+        };
+        let Some(frame) = frames.first() else {
+            return Ok(Some(tcp));
+        };
+        let hello = match Handshake::decode(frame) {
+            Ok(Handshake::Hello(hello)) => hello,
+            Ok(_) | Err(_) => return Err(turn_away(tcp, &self.session_id, None)),
+        };
+        let Some(version) = handshake::negotiate(hello.version) else {
+            return Err(turn_away(tcp, &self.session_id, Some(hello.version)));
+        };
+        let secret = match (&self.passphrase, &hello.auth) {
+            (None, None) => None,
+            (None, Some(_)) => {
+                refuse(tcp, &self.session_id, "the game has no passphrase");
+                return Ok(None);
+            }
+            (Some(_), None) => {
+                refuse(tcp, &self.session_id, "a passphrase is needed");
+                return Ok(None);
+            }
+            (Some(passphrase), Some(_)) => match hello.verify(passphrase) {
+                Some(secret) => Some(secret),
+                None => {
+                    refuse(tcp, &self.session_id, "wrong passphrase");
+                    return Err(HandshakeError::WrongPassphrase.into());
+                }
+            },
+        };
+        if hello.role == Role::Spectator {
+            // a spectator coming back keeps its id, which never is the player's
+            let session_id = hello
+                .resume
+                .filter(|id| *id != self.session_id)
+                .unwrap_or_else(rand::random);
+            let sync = Message::Sync(self.history.clone());
+            // a spectator which is gone already is no reason to stop hosting
+            if self
+                .welcome(tcp.as_mut(), version, &hello, session_id, secret.as_ref())
+                .is_ok()
+                && super::send_message(tcp.as_mut(), sync, &session_id).is_ok()
+                && self.clock.clone().is_none_or(|clock| {
+                    super::send_message(tcp.as_mut(), clock, &session_id).is_ok()
+                })
+            {
+                self.spectators.push((tcp, session_id));
+            }
+            return Ok(None);
+        }
+        // only players get here, so only the player can take their seat back
+        match (hello.resume, &self.peer) {
+            (Some(id), Some(_)) if id == self.session_id => {
+                // the old connection may not have noticed it is gone yet
+                if let Some(mut old) = self.tcp.take() {
+                    old.hang_up();
+                }
+                // the client asks for the whole game, which has everything queued since
+                self.send.clear();
+                self.resume_deadline = None;
+            }
+            (Some(_), _) => {
+                refuse(
+                    tcp,
+                    &self.session_id,
+                    "the game to resume is not hosted here",
+                );
+                return Ok(None);
+            }
+            (None, Some(_)) => {
+                refuse(tcp, &self.session_id, "a game is already being played");
+                return Ok(None);
+            }
+            (None, None) => {
+                if self.hello.time_control.is_some() && !hello.features.contains(Features::CLOCKS) {
+                    refuse(tcp, &self.session_id, "the game is played with clocks");
+                    return Ok(None);
+                }
+                // resuming clients and spectators get the time control agreed on with the first
+                self.hello.time_control = self
+                    .hello
+                    .time_control
+                    .or(hello.time_control)
+                    .filter(|_| hello.features.contains(Features::CLOCKS));
+                // the host's side from now on, so that resuming clients get theirs back
+                self.hello.side = Some(match (self.hello.side, hello.side) {
+                    (Some(side), _) => side,
+                    (None, Some(theirs)) => theirs.opposite(),
+                    (None, None) if rand::random() => Side::White,
+                    (None, None) => Side::Black,
+                });
+            }
+        }
+        let session_id = self.session_id;
+        let features = self.welcome(tcp.as_mut(), version, &hello, session_id, secret.as_ref())?;
+        if let Ok(addr) = tcp.socket().peer_addr() {
+            self.addr = addr;
+        }
+        self.tcp = Some(tcp);
+        self.peer = Some(Peer {
+            name: hello.name,
+            version,
+            features,
+            time_control: self.hello.time_control,
+            side: self.client_side(),
+        });
+        Ok(None)
+    }
+    /// Everything after the welcome is encrypted when both ends have the passphrase
+    /// # Returns
+    /// the features both ends support
+    fn welcome(
+        &self,
+        tcp: &mut dyn Frames,
+        version: u8,
+        hello: &Hello,
+        session_id: SessId,
+        secret: Option<&Secret>,
+    ) -> Result<Features> {
+        let features = self.hello.features.common(hello.features);
+        let mut welcome = Welcome {
+            version,
+            features,
+            session_id,
+            name: self.hello.name.clone(),
+            time_control: self.hello.time_control,
+            side: self.client_side(),
+            auth: None,
+        };
+        if let Some(secret) = secret {
+            welcome.sign(secret, hello);
+        }
+        let cipher = secret
+            .zip(welcome.auth)
+            .map(|(s, ours)| s.session(&ours.nonce, true));
+        tcp.send_frame(&Handshake::Welcome(welcome).encode())?;
+        if let Some(cipher) = cipher {
+            tcp.set_cipher(cipher);
+        }
+        Ok(features)
+    }
+    /// The host plays white unless it was agreed otherwise
+    fn client_side(&self) -> Side {
+        self.hello.side.unwrap_or(Side::White).opposite()
+    }
+    /// Follows the game through the host's messages, so that spectators see every move once it
+    /// is final
+    fn record(&mut self, msg: &Message) {
+        let m = match msg {
+            Message::Moved(m) => *m,
+            Message::Accepted() => match self.proposed.take() {
+                Some(m) => m,
+                None => return,
+            },
+            Message::Rejected() => {
+                self.proposed = None;
+                return;
+            }
+            Message::GameDone() | Message::Ended(_) => {
+                self.broadcast(msg.clone());
+                return;
+            }
+            Message::Clock(..) => {
+                self.clock = Some(msg.clone());
+                self.broadcast(msg.clone());
+                return;
+            }
+            Message::Takeback(plies) => {
+                self.history.truncate(*plies as usize);
+                self.proposed = None;
+                self.broadcast(Message::Sync(self.history.clone()));
+                return;
+            }
+            _ => return,
+        };
+        self.history.push(m);
+        self.broadcast(Message::Moved(m));
+    }
+    /// Spectators which cannot keep up are dropped
+    fn broadcast(&mut self, msg: Message) {
+        self.spectators
+            .retain_mut(|(tcp, id)| super::send_message(tcp.as_mut(), msg.clone(), id).is_ok());
+    }
+    /// Whatever spectators send is read and thrown away
+    fn poll_spectators(&mut self) {
+        self.spectators
+            .retain_mut(|(tcp, _)| tcp.recv_frames().is_ok() && tcp.flush().is_ok());
+    }
 }
 
-impl super::Connection for Host {
-    fn poll(&mut self) -> Result<()> {
-        self.state = match self.state {
-            HostConnection::Begin => match self.list.accept() {
-                Err(e) => {
-                    if e.kind() == ErrorKind::WouldBlock {
-                        Ok(HostConnection::Begin)
-                    } else {
-                        Err(anyhow!("{e}"))
+/// A connection accepted by the host which may not carry frames yet
+pub enum Incoming {
+    /// nothing arrived yet which tells raw frames from a WebSocket upgrade request
+    Sniffing(TcpStream),
+    /// a browser asked for a WebSocket
+    Upgrading(Upgrade),
+    Ready(Box<dyn Frames>),
+}
+
+impl Incoming {
+    /// Moves the connection on as far as what arrived allows
+    fn advance(self) -> Result<Self> {
+        match self {
+            Incoming::Sniffing(tcp) => {
+                let mut first = [0];
+                match tcp.peek(&mut first) {
+                    Ok(0) => bail!("connection closed"),
+                    // raw frames start with their length, which is never that long
+                    Ok(_) if first[0] == b'G' => {
+                        Incoming::Upgrading(Upgrade::start(tcp)?).advance()
                     }
+                    Ok(_) => Ok(Incoming::Ready(Box::new(FramedStream::new(tcp)))),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(Incoming::Sniffing(tcp)),
+                    Err(e) => Err(e.into()),
                 }
-                Ok((recv, peer)) => {
-                    recv.set_nonblocking(true).unwrap();
-                    self.tcp = Some(recv);
-                    self.addr = peer;
-                    Ok(HostConnection::HandshakeRead)
-                }
+            }
+            Incoming::Upgrading(upgrade) => match upgrade.advance()? {
+                Upgrade::Done(ws) => Ok(Incoming::Ready(ws)),
+                upgrade => Ok(Incoming::Upgrading(upgrade)),
             },
-            HostConnection::HandshakeRead => {
-                match self.tcp.as_mut().unwrap().read(&mut self.buf[..8]) {
-                    Err(e) => {
-                        if e.kind() == ErrorKind::WouldBlock {
-                            Ok(HostConnection::HandshakeRead)
-                        } else {
-                            Err(anyhow!("{e}"))
-                        }
-                    }
-                    Ok(recieved) => {
-                        if recieved != 5 {
-                            return Err(anyhow!("handshake length was improper"));
-                        }
-                        if self.buf[0..4] != MAGIC_N {
-                            return Err(anyhow!("handshake failed"));
-                        }
-                        if self.buf[4..5] != [0x02] {
-                            return Err(anyhow!("handshake failed"));
-                        }
-                        self.buf.fill(0);
-                        Ok(HostConnection::HandshakeRespond)
-                    }
+            ready => Ok(ready),
+        }
+    }
+}
+
+/// Tells the client why, as far as it can understand, and hangs up
+fn turn_away(mut tcp: Box<dyn Frames>, session_id: &SessId, theirs: Option<u8>) -> anyhow::Error {
+    if theirs.is_some_and(|v| v != LEGACY_VERSION) {
+        let reason = format!("protocol {MIN_PROTOCOL_VERSION} or newer is needed");
+        refuse(tcp, session_id, &reason);
+    } else {
+        super::close(tcp.as_mut(), session_id, &mut MessageQueue::new());
+    }
+    HandshakeError::Incompatible {
+        ours: PROTOCOL_VERSION,
+        theirs,
+    }
+    .into()
+}
+
+fn refuse(mut tcp: Box<dyn Frames>, session_id: &SessId, reason: &str) {
+    let refused = Handshake::Refused(Refused {
+        version: PROTOCOL_VERSION,
+        reason: reason.to_owned(),
+    });
+    let _ = tcp.send_frame(&refused.encode());
+    super::close(tcp.as_mut(), session_id, &mut MessageQueue::new());
+}
+
+impl super::Connection for Host {
+    fn poll(&mut self) -> Result<()> {
+        self.accept()?;
+        self.announce();
+        // a client which cannot play is reported once the others had their turn, but only while
+        // the host waits for its player, later it is no concern of the game
+        let mut refused = Ok(());
+        for (incoming, accepted) in std::mem::take(&mut self.pending) {
+            if accepted.elapsed() >= HELLO_T {
+                if self.list.is_none() {
+                    bail!("the relay's player did not say hello");
                 }
+                eprintln!("gave up on a client which did not say hello");
+                continue;
             }
-            HostConnection::HandshakeRespond => {
-                self.buf[..4].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
-                self.buf[4..8].copy_from_slice(&self.session_id);
-                let res = self.tcp.as_mut().unwrap().write_all(&self.buf[..8]);
-                match res {
-                    Err(e) => {
-                        if e.kind() == ErrorKind::WouldBlock {
-                            Ok(HostConnection::HandshakeRespond)
-                        } else {
-                            Err(anyhow!("{e}"))
-                        }
-                    }
-                    Ok(_) => {
-                        self.buf.fill(0);
-                        Ok(HostConnection::Connected)
-                    }
+            let tcp = match incoming.advance() {
+                Ok(Incoming::Ready(tcp)) => tcp,
+                Ok(incoming) => {
+                    self.pending.push((incoming, accepted));
+                    continue;
                 }
+                Err(e) => {
+                    eprintln!("client left before the handshake: {e}");
+                    continue;
+                }
+            };
+            match self.handshake(tcp) {
+                Ok(Some(tcp)) => self.pending.push((Incoming::Ready(tcp), accepted)),
+                Ok(None) => (),
+                Err(e) if self.peer.is_some() => eprintln!("turned a client away: {e}"),
+                Err(e) => refused = Err(e),
             }
-            HostConnection::Connected => {
-                if let Some(msgs) = super::recv_messages(
-                    self.tcp.as_mut().unwrap(),
-                    &mut self.buf,
-                    &self.session_id,
-                )? {
-                    for msg in msgs {
-                        self.recv.push_back(msg);
-                    }
+        }
+        self.poll_spectators();
+        if let Some(tcp) = self.tcp.as_mut() {
+            let received = self.recv.len();
+            let res = super::exchange(
+                tcp.as_mut(),
+                &self.session_id,
+                &mut self.send,
+                &mut self.recv,
+            );
+            for msg in self.recv.range(received..) {
+                if let Message::Moved(m) = msg {
+                    self.proposed = Some(*m);
                 }
-                while let Some(mess) = self.send.pop_front() {
-                    super::send_message(self.tcp.as_mut().unwrap(), mess, &self.session_id)
-                        .unwrap();
+            }
+            if let Err(e) = res {
+                if self.list.is_none() {
+                    bail!("lost the relay: {e}");
                 }
-                Ok(HostConnection::Connected)
+                eprintln!("lost the client: {e}");
+                self.tcp = None;
+                self.resume_deadline = Some(Instant::now() + RESUME_T);
             }
-        }?;
-        Ok(())
+        }
+        if self.resume_deadline.is_some_and(|t| Instant::now() >= t) {
+            bail!("the client did not come back");
+        }
+        refused
     }
     fn send(&mut self, msg: Message) {
+        self.record(&msg);
         self.send.push_back(msg);
     }
     fn recv(&mut self) -> Option<Message> {
         self.recv.pop_front()
     }
     fn is_connected(&self) -> bool {
-        matches!(&self.state, HostConnection::Connected)
+        self.tcp.is_some()
     }
     fn shutdown(&mut self) {
         if !self.shutdown {
-            if let Some(tcp) = &self.tcp {
-                tcp.shutdown(Shutdown::Both)
-                    .expect("TcpStream shutdown failed");
+            if let Some(tcp) = self.tcp.as_mut() {
+                super::close(tcp.as_mut(), &self.session_id, &mut self.send);
+            }
+            for (tcp, id) in &mut self.spectators {
+                super::close(tcp.as_mut(), id, &mut MessageQueue::new());
             }
             self.shutdown = true;
         }
@@ -142,4 +500,164 @@ impl super::Connection for Host {
     fn is_shutdown(&self) -> bool {
         self.shutdown
     }
+    fn peer(&self) -> Option<&Peer> {
+        self.peer.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::Client;
+    use crate::network::Connection;
+    use std::io::Write;
+
+    fn poll_until(
+        host: &mut Host,
+        client: &mut Client,
+        mut done: impl FnMut(&mut Host, &mut Client) -> bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(host, client) {
+            assert!(Instant::now() < deadline, "timed out");
+            host.poll().unwrap();
+            client.poll().unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    /// Connects a stranger which speaks the unframed version 2 handshake
+    fn legacy_client(addr: &str) -> TcpStream {
+        let mut tcp = TcpStream::connect(addr).unwrap();
+        tcp.write_all(&[0xDE, 0xAD, 0xBE, 0xEF, LEGACY_VERSION])
+            .unwrap();
+        tcp
+    }
+
+    #[test]
+    fn refused_clients_do_not_end_the_game() {
+        let addr = "127.0.0.1:39871";
+        let mut host = Host::new(addr, Hello::new("host").with_side(Some(Side::White))).unwrap();
+        let mut client = Client::new(addr, Hello::new("client")).unwrap();
+        poll_until(&mut host, &mut client, |h, c| {
+            h.is_connected() && c.is_connected()
+        });
+        let _legacy = legacy_client(addr);
+        let mut junk = TcpStream::connect(addr).unwrap();
+        junk.write_all(b"\x00\x05junk!").unwrap();
+        let mut polls = 0;
+        poll_until(&mut host, &mut client, |_, _| {
+            polls += 1;
+            polls > 50
+        });
+        assert!(host.is_connected());
+        assert_eq!(host.peer().map(|p| p.name.as_str()), Some("client"));
+
+        let m = BoardMove::from_uci("e2e4").unwrap();
+        host.send(Message::Moved(m));
+        let mut received = None;
+        poll_until(&mut host, &mut client, |_, c| {
+            received = c.recv();
+            received.is_some()
+        });
+        assert!(matches!(received, Some(Message::Moved(got)) if got == m));
+    }
+
+    /// Says `hello` on a new connection and waits for the answer
+    fn handshake(host: &mut Host, addr: &str, hello: Hello) -> Handshake {
+        let tcp = TcpStream::connect(addr).unwrap();
+        tcp.set_nonblocking(true).unwrap();
+        let mut tcp = FramedStream::new(tcp);
+        tcp.send_frame(&Handshake::Hello(hello).encode()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            assert!(Instant::now() < deadline, "timed out");
+            host.poll().unwrap();
+            tcp.flush().unwrap();
+            if let Some(frame) = tcp.recv_frame().unwrap() {
+                return Handshake::decode(&frame).unwrap();
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    #[test]
+    fn spectators_cannot_take_the_players_seat() {
+        let addr = "127.0.0.1:39873";
+        let mut host = Host::new(addr, Hello::new("host")).unwrap();
+        let mut client = Client::new(addr, Hello::new("client")).unwrap();
+        poll_until(&mut host, &mut client, |h, c| {
+            h.is_connected() && c.is_connected()
+        });
+        let Handshake::Welcome(welcome) = handshake(&mut host, addr, Hello::spectator("watcher"))
+        else {
+            panic!("the spectator was not welcomed");
+        };
+        assert_ne!(welcome.session_id, host.session_id);
+        // neither the spectator's id nor asking as a spectator for the player's gets the seat
+        let resume = Hello {
+            resume: Some(welcome.session_id),
+            ..Hello::new("intruder")
+        };
+        assert!(matches!(
+            handshake(&mut host, addr, resume),
+            Handshake::Refused(_)
+        ));
+        let watch = Hello {
+            resume: Some(host.session_id),
+            ..Hello::spectator("intruder")
+        };
+        let Handshake::Welcome(welcome) = handshake(&mut host, addr, watch) else {
+            panic!("the spectator was not welcomed");
+        };
+        assert_ne!(welcome.session_id, host.session_id);
+        assert!(host.is_connected());
+        assert_eq!(host.peer().map(|p| p.name.as_str()), Some("client"));
+    }
+
+    #[test]
+    fn silent_connections_are_let_go() {
+        let addr = "127.0.0.1:39874";
+        let mut host = Host::new(addr, Hello::new("host")).unwrap();
+        let mut one_byte = TcpStream::connect(addr).unwrap();
+        one_byte.write_all(b"\x00").unwrap();
+        let mut half_upgrade = TcpStream::connect(addr).unwrap();
+        half_upgrade.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while host.pending.len() < 2 {
+            assert!(Instant::now() < deadline, "timed out");
+            host.poll().unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        host.poll().unwrap();
+        assert_eq!(host.pending.len(), 2);
+        for (_, accepted) in &mut host.pending {
+            *accepted = Instant::now().checked_sub(HELLO_T).unwrap();
+        }
+        host.poll().unwrap();
+        assert!(host.pending.is_empty());
+    }
+
+    #[test]
+    fn refused_clients_are_reported_while_waiting() {
+        let addr = "127.0.0.1:39872";
+        let mut host = Host::new(addr, Hello::new("host")).unwrap();
+        let _legacy = legacy_client(addr);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let err = loop {
+            assert!(Instant::now() < deadline, "timed out");
+            if let Err(e) = host.poll() {
+                break e;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        };
+        assert!(matches!(
+            err.downcast_ref::<HandshakeError>(),
+            Some(HandshakeError::Incompatible {
+                theirs: Some(LEGACY_VERSION),
+                ..
+            })
+        ));
+        assert!(host.peer().is_none());
+    }
 }
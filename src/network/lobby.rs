@@ -0,0 +1,124 @@
+//! What players and the relay server say before a game starts. A player either opens a game,
+//! which waits in the server's list until someone joins it, or asks for the list and joins one.
+//! Once two players are paired the server passes every frame on to the other one unchanged, so
+//! the game's own handshake and messages, encrypted or not, go through it as they would over a
+//! direct connection. The server knows nothing about chess, this is all it speaks besides the
+//! framing.
+
+use super::handshake::{get_string, get_u8, put_string};
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Every lobby frame starts with it, so that game frames sent too early are not mistaken for one
+pub const LOBBY_MAGIC: [u8; 4] = *b"PHLB";
+/// Where relay servers listen unless told otherwise
+pub const DEFAULT_RELAY_PORT: u16 = 7878;
+/// Longer lists are cut short, so that they fit in a frame
+pub const MAX_LISTED: usize = 30;
+
+pub type GameId = u32;
+
+/// A game waiting in the server's list
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenGame {
+    pub id: GameId,
+    /// the player who opened it
+    pub name: String,
+    /// what they said about it, like the time control
+    pub details: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lobby {
+    /// to the server: list a game and wait for someone to join it
+    Open { name: String, details: String }, // 0x01
+    /// to the player: the game is listed under this id
+    Opened(GameId), // 0x02
+    /// to the server: which games are waiting
+    List(), // 0x03
+    /// to the player
+    Games(Vec<OpenGame>), // 0x04
+    /// to the server: pair me with the player of this game
+    Join(GameId), // 0x05
+    /// to both players: every frame from now on comes from and goes to the other one
+    Paired(), // 0x06
+    /// to the player: the server cannot do what was asked and hangs up
+    Refused(String), // 0x07
+}
+
+impl Lobby {
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        bytes.put(LOBBY_MAGIC.as_slice());
+        match self {
+            Lobby::Open { name, details } => {
+                bytes.put_u8(0x01);
+                put_string(&mut bytes, name);
+                put_string(&mut bytes, details);
+            }
+            Lobby::Opened(id) => {
+                bytes.put_u8(0x02);
+                bytes.put_u32(*id);
+            }
+            Lobby::List() => bytes.put_u8(0x03),
+            Lobby::Games(games) => {
+                bytes.put_u8(0x04);
+                let games = &games[..games.len().min(MAX_LISTED)];
+                bytes.put_u8(games.len() as u8);
+                for game in games {
+                    bytes.put_u32(game.id);
+                    put_string(&mut bytes, &game.name);
+                    put_string(&mut bytes, &game.details);
+                }
+            }
+            Lobby::Join(id) => {
+                bytes.put_u8(0x05);
+                bytes.put_u32(*id);
+            }
+            Lobby::Paired() => bytes.put_u8(0x06),
+            Lobby::Refused(reason) => {
+                bytes.put_u8(0x07);
+                put_string(&mut bytes, reason);
+            }
+        }
+        bytes.into()
+    }
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < LOBBY_MAGIC.len() + 1 || bytes[..LOBBY_MAGIC.len()] != LOBBY_MAGIC {
+            bail!("Lobby: not a lobby frame");
+        }
+        bytes.advance(LOBBY_MAGIC.len());
+        let msg = match bytes.get_u8() {
+            0x01 => Lobby::Open {
+                name: get_string(&mut bytes)?,
+                details: get_string(&mut bytes)?,
+            },
+            0x02 => Lobby::Opened(get_u32(&mut bytes)?),
+            0x03 => Lobby::List(),
+            0x04 => {
+                let count = get_u8(&mut bytes)?;
+                let mut games = vec![];
+                for _ in 0..count {
+                    games.push(OpenGame {
+                        id: get_u32(&mut bytes)?,
+                        name: get_string(&mut bytes)?,
+                        details: get_string(&mut bytes)?,
+                    });
+                }
+                Lobby::Games(games)
+            }
+            0x05 => Lobby::Join(get_u32(&mut bytes)?),
+            0x06 => Lobby::Paired(),
+            0x07 => Lobby::Refused(get_string(&mut bytes)?),
+            _ => bail!("Lobby: invalid kind"),
+        };
+        Ok(msg)
+    }
+}
+
+fn get_u32(bytes: &mut &[u8]) -> Result<u32> {
+    if bytes.remaining() < 4 {
+        bail!("Lobby: truncated");
+    }
+    Ok(bytes.get_u32())
+}
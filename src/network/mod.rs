@@ -1,17 +1,35 @@
-use crate::board::{BoardMove, BoardPos};
+use crate::board::pgn::GameResult;
+use crate::board::{BoardMove, BoardPos, Side};
+use crate::clock::ChessClock;
 use anyhow::{bail, Result};
 use bytes::{BufMut, Bytes, BytesMut};
+use handshake::Peer;
+use lobby::GameId;
 use std::collections::VecDeque;
-use std::io::{ErrorKind, Read, Write};
-use std::net::TcpStream;
+use std::time::Duration;
+use stream::Frames;
 
+pub mod background;
+pub mod bot;
 pub mod client;
+pub mod discovery;
+pub mod handshake;
 pub mod host;
+pub mod lobby;
+pub mod relay;
+pub mod secure;
+pub mod server;
+pub mod stream;
+pub mod uci;
+pub mod websocket;
 
 pub type SessId = [u8; 4];
-pub type NetBuf = [u8; NETBUF_SIZE];
+/// Every handshake frame starts with it, so that strangers are told apart from peers
 pub const MAGIC_N: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
-pub const NETBUF_SIZE: usize = 128;
+/// How long a game waits for a client which lost its connection to come back
+pub const RESUME_T: Duration = Duration::from_secs(60);
+/// Longest chat message in bytes, longer ones are refused by the decoder
+pub const MAX_CHAT: usize = 280;
 pub type MessageQueue = VecDeque<Message>;
 
 pub trait Connection {
@@ -21,88 +39,227 @@ pub trait Connection {
     fn poll(&mut self) -> Result<()>;
     fn send(&mut self, msg: Message);
     fn recv(&mut self) -> Option<Message>;
+    /// Who is on the other end, known once the handshake is done
+    fn peer(&self) -> Option<&Peer> {
+        None
+    }
+    /// What a relay server lists the game as, known once it opened the game there
+    fn relay_game(&self) -> Option<GameId> {
+        None
+    }
 }
 
-pub fn recv_messages(
-    sock: &mut TcpStream,
-    buf: &mut NetBuf,
+/// Reads every message which arrived in full, frames carrying another session id are dropped
+pub fn recv_messages(stream: &mut dyn Frames, session_id: &SessId) -> Result<Vec<Message>> {
+    read_messages(stream.recv_frames()?, session_id)
+}
+/// Decodes frames which were already read, like the ones which arrived along with the handshake
+pub fn read_messages(
+    frames: impl IntoIterator<Item = Bytes>,
     session_id: &SessId,
-) -> Result<Option<Vec<Message>>> {
+) -> Result<Vec<Message>> {
     let mut ret = vec![];
-    match sock.read(buf) {
-        Ok(0) => anyhow::bail!("connection closed"),
-        Ok(n) => {
-            let mut cursor = 0;
-            while cursor < n {
-                // would be too short for a valid message
-                if n - cursor < 5 {
-                    return Ok(None);
-                };
-                // ensure the session id matches
-                if buf[cursor..cursor + 4] != *session_id {
-                    return Ok(None);
-                }
-                cursor += 4;
-                while let Ok((msg, off)) = decode_message(&buf[cursor..]) {
-                    ret.push(msg);
-                    cursor += off;
-                }
-            }
-            buf.fill(0);
-            Ok(Some(ret))
+    for frame in frames {
+        let Some(body) = frame.strip_prefix(session_id.as_slice()) else {
+            eprintln!("dropping a message for another session");
+            continue;
+        };
+        let (msg, len) = decode_message(body)?;
+        if len != body.len() {
+            bail!("Decoder: {} stray bytes after message", body.len() - len);
         }
-        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
-        Err(other) => Err(other.into()),
+        ret.push(msg);
     }
+    Ok(ret)
 }
-pub fn send_message(sock: &mut TcpStream, msg: Message, session_id: &SessId) -> Result<Option<()>> {
+/// Queues the message, it is written right away as far as the socket allows and the rest goes
+/// out with the next [`Frames::flush`]
+pub fn send_message(stream: &mut dyn Frames, msg: Message, session_id: &SessId) -> Result<()> {
     let mut bytes = BytesMut::new();
     bytes.put(session_id.as_slice());
-    let encoded_message = encode_message(&msg);
-    let encoded_message = encoded_message.iter().as_slice();
-    bytes.put(encoded_message);
-    match sock.write(&bytes) {
-        Ok(_) => Ok(Some(())),
-        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
-        Err(e) => Err(e.into()),
+    bytes.put(encode_message(&msg));
+    stream.send_frame(&bytes)
+}
+/// Moves messages both ways on an established connection
+pub fn exchange(
+    stream: &mut dyn Frames,
+    session_id: &SessId,
+    send: &mut MessageQueue,
+    recv: &mut MessageQueue,
+) -> Result<()> {
+    recv.extend(recv_messages(stream, session_id)?);
+    while let Some(msg) = send.pop_front() {
+        send_message(stream, msg, session_id)?;
+    }
+    stream.flush()
+}
+/// Writes whatever is still queued, waiting for the socket if needed, and closes the connection.
+/// The peer may already be gone so nothing here can fail.
+pub fn close(stream: &mut dyn Frames, session_id: &SessId, send: &mut MessageQueue) {
+    if stream.socket().set_nonblocking(false).is_ok() {
+        while let Some(msg) = send.pop_front() {
+            if send_message(stream, msg, session_id).is_err() {
+                break;
+            }
+        }
+        let _ = stream.flush();
     }
+    stream.hang_up();
 }
 /// # Return value
 /// None or a Message and cursor offset after decoding it
 fn decode_message(bytes: &[u8]) -> Result<(Message, usize)> {
     const MOVED_SZ: usize = 7;
-    match bytes[0] {
-        0x01 if bytes.len() >= MOVED_SZ => {
-            let mess = Message::Moved(BoardMove::new(
-                BoardPos {
-                    row: bytes[1] as usize,
-                    col: bytes[2] as usize,
-                },
-                BoardPos {
-                    row: bytes[3] as usize,
-                    col: bytes[4] as usize,
-                },
-            ));
-            Ok((mess, MOVED_SZ))
-        }
+    let Some(&kind) = bytes.first() else {
+        bail!("Decoder: empty message");
+    };
+    match kind {
+        0x01 if bytes.len() >= MOVED_SZ => Ok((Message::Moved(decode_move(&bytes[1..])), MOVED_SZ)),
+        0x01 => bail!("Decoder: truncated move"),
         0x02 => Ok((Message::Rejected(), 1)),
         0x03 => Ok((Message::Accepted(), 1)),
         0x04 => Ok((Message::GameDone(), 1)),
         0x05 => Ok((Message::HeartBeat(), 1)),
+        0x06 if bytes.len() >= 3 => {
+            let count = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+            let len = 3 + count * MOVE_SZ;
+            if bytes.len() < len {
+                bail!("Decoder: truncated move list");
+            }
+            let moves = bytes[3..len]
+                .chunks_exact(MOVE_SZ)
+                .map(decode_move)
+                .collect();
+            Ok((Message::Sync(moves), len))
+        }
+        0x07 => Ok((Message::SyncRequest(), 1)),
+        0x08 if bytes.len() >= 3 => {
+            let len = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+            if len > MAX_CHAT {
+                bail!("Decoder: chat message of {len} bytes is too long");
+            }
+            let Some(text) = bytes.get(3..3 + len) else {
+                bail!("Decoder: truncated chat message");
+            };
+            let text = clean_chat(std::str::from_utf8(text)?);
+            Ok((Message::Chat(text), 3 + len))
+        }
+        0x09 => Ok((Message::Resign(), 1)),
+        0x0A if bytes.len() >= 4 => {
+            let plies = u16::from_be_bytes([bytes[2], bytes[3]]);
+            Ok((Message::Offer(decode_proposal(bytes[1])?, plies), 4))
+        }
+        0x0B if bytes.len() >= 2 => Ok((Message::Agree(decode_proposal(bytes[1])?), 2)),
+        0x0C => Ok((Message::Decline(), 1)),
+        0x0D if bytes.len() >= 3 => {
+            let plies = u16::from_be_bytes([bytes[1], bytes[2]]);
+            Ok((Message::Takeback(plies), 3))
+        }
+        0x0E if bytes.len() >= 2 => {
+            let result = match bytes[1] {
+                0x01 => GameResult::WhiteWon,
+                0x02 => GameResult::BlackWon,
+                0x03 => GameResult::Draw,
+                _ => bail!("Decoder: invalid game result"),
+            };
+            Ok((Message::Ended(result), 2))
+        }
+        0x0F if bytes.len() >= 9 => {
+            let millis = |at: usize| {
+                let ms =
+                    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+                Duration::from_millis(ms as u64)
+            };
+            Ok((Message::Clock(millis(1), millis(5)), 9))
+        }
+        0x10 if bytes.len() >= 11 => {
+            let plies = u16::from_be_bytes([bytes[1], bytes[2]]);
+            let mut key = [0; 8];
+            key.copy_from_slice(&bytes[3..11]);
+            Ok((Message::Position(plies, u64::from_be_bytes(key)), 11))
+        }
+        // the kinds above which were cut short
+        0x06 | 0x08 | 0x0A | 0x0B | 0x0D..=0x10 => bail!("Decoder: truncated message"),
         _ => bail!("Decoder: invalid message kind"),
     }
 }
-#[derive(Debug)]
+fn decode_proposal(byte: u8) -> Result<Proposal> {
+    match byte {
+        0x01 => Ok(Proposal::Draw),
+        0x02 => Ok(Proposal::Takeback),
+        _ => bail!("Decoder: invalid proposal"),
+    }
+}
+/// Size of a move as written by [`BoardMove::to_bytes`]
+const MOVE_SZ: usize = 6;
+fn decode_move(bytes: &[u8]) -> BoardMove {
+    BoardMove::new(
+        BoardPos {
+            row: bytes[0] as usize,
+            col: bytes[1] as usize,
+        },
+        BoardPos {
+            row: bytes[2] as usize,
+            col: bytes[3] as usize,
+        },
+    )
+}
+#[derive(Clone, Debug)]
 pub enum Message {
     Moved(super::board::BoardMove), // 0x01
     Rejected(),                     // 0x02
     Accepted(),                     // 0x03
     GameDone(),                     // 0x04
     HeartBeat(),                    // 0x05
+    /// every move of the game so far, which the receiver replays from the starting position
+    Sync(Vec<BoardMove>), // 0x06
+    SyncRequest(),                  // 0x07
+    /// UTF-8 text of at most [`MAX_CHAT`] bytes
+    Chat(String), // 0x08
+    /// the sender gives the game up
+    Resign(), // 0x09
+    /// made in the position after this many moves, it is turned down if a move crossed it
+    Offer(Proposal, u16), // 0x0A
+    /// a takeback the client agrees to only happens once the host sends [`Message::Takeback`]
+    Agree(Proposal), // 0x0B
+    /// turns down the last offer
+    Decline(), // 0x0C
+    /// the host's word that the game goes back to its first this many moves
+    Takeback(u16), // 0x0D
+    /// how a game which did not end with a king being taken ended, so that spectators know
+    Ended(GameResult), // 0x0E
+    /// the host's clocks of white and black right after the last move, the side to move's runs
+    Clock(Duration, Duration), // 0x0F
+    /// the Polyglot key of the host's position after this many moves, a client whose own differs
+    /// asks for the whole game
+    Position(u16, u64), // 0x10
+}
+/// What one player can ask the other to agree to during a game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Proposal {
+    Draw, // 0x01
+    /// taking back moves until it is the asking player's turn again
+    Takeback, // 0x02
+}
+/// The host's clocks as of now, for the client and spectators to show
+pub fn clock_message(clock: &ChessClock) -> Message {
+    Message::Clock(clock.left(Side::White), clock.left(Side::Black))
+}
+/// Chat is shown on the other end, so control characters are dropped and the text is cut down
+/// to [`MAX_CHAT`] bytes
+pub fn clean_chat(text: &str) -> String {
+    let mut ret = String::new();
+    for c in text.trim().chars().filter(|c| !c.is_control()) {
+        if ret.len() + c.len_utf8() > MAX_CHAT {
+            break;
+        }
+        ret.push(c);
+    }
+    ret
 }
 fn encode_message(msg: &Message) -> Bytes {
     let mut bytes = BytesMut::new();
-    match *msg {
+    match msg {
         Message::Moved(m) => {
             bytes.put_u8(0x01);
             bytes.put(m.to_bytes());
@@ -119,6 +276,115 @@ fn encode_message(msg: &Message) -> Bytes {
         Message::HeartBeat() => {
             bytes.put_u8(0x05);
         }
+        Message::Sync(moves) => {
+            bytes.put_u8(0x06);
+            bytes.put_u16(moves.len() as u16);
+            for m in moves {
+                bytes.put(m.to_bytes());
+            }
+        }
+        Message::SyncRequest() => {
+            bytes.put_u8(0x07);
+        }
+        Message::Chat(text) => {
+            let text = clean_chat(text);
+            bytes.put_u8(0x08);
+            bytes.put_u16(text.len() as u16);
+            bytes.put(text.as_bytes());
+        }
+        Message::Resign() => {
+            bytes.put_u8(0x09);
+        }
+        Message::Offer(proposal, plies) => {
+            bytes.put_u8(0x0A);
+            bytes.put_u8(encode_proposal(*proposal));
+            bytes.put_u16(*plies);
+        }
+        Message::Agree(proposal) => {
+            bytes.put_u8(0x0B);
+            bytes.put_u8(encode_proposal(*proposal));
+        }
+        Message::Decline() => {
+            bytes.put_u8(0x0C);
+        }
+        Message::Takeback(plies) => {
+            bytes.put_u8(0x0D);
+            bytes.put_u16(*plies);
+        }
+        Message::Ended(result) => {
+            bytes.put_u8(0x0E);
+            bytes.put_u8(match result {
+                GameResult::WhiteWon => 0x01,
+                GameResult::BlackWon => 0x02,
+                // games are not ended without a result
+                GameResult::Draw | GameResult::Unknown => 0x03,
+            });
+        }
+        Message::Clock(white, black) => {
+            bytes.put_u8(0x0F);
+            for left in [white, black] {
+                bytes.put_u32(left.as_millis().min(u32::MAX as u128) as u32);
+            }
+        }
+        Message::Position(plies, key) => {
+            bytes.put_u8(0x10);
+            bytes.put_u16(*plies);
+            bytes.put_u64(*key);
+        }
     }
     bytes.into()
 }
+fn encode_proposal(proposal: Proposal) -> u8 {
+    match proposal {
+        Proposal::Draw => 0x01,
+        Proposal::Takeback => 0x02,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chat message whose text is `text` as it is, without cleaning it up
+    fn raw_chat(text: &[u8], len: u16) -> Vec<u8> {
+        let mut ret = vec![0x08];
+        ret.extend_from_slice(&len.to_be_bytes());
+        ret.extend_from_slice(text);
+        ret
+    }
+
+    fn decode_err(bytes: &[u8]) -> String {
+        decode_message(bytes).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn chat_is_decoded_without_control_characters() {
+        let text = "hi\x07 there\u{1b}[2J\n";
+        let (msg, len) = decode_message(&raw_chat(text.as_bytes(), text.len() as u16)).unwrap();
+        assert!(matches!(msg, Message::Chat(text) if text == "hi there[2J"));
+        assert_eq!(len, 3 + text.len());
+        let (msg, _) = decode_message(&encode_message(&Message::Chat("gg".to_owned()))).unwrap();
+        assert!(matches!(msg, Message::Chat(text) if text == "gg"));
+    }
+
+    #[test]
+    fn broken_chat_is_refused() {
+        let long = vec![b'a'; MAX_CHAT + 1];
+        assert!(decode_err(&raw_chat(&long, long.len() as u16)).contains("too long"));
+        assert!(decode_err(&raw_chat(b"abc", 5)).contains("truncated chat"));
+        assert!(decode_message(&raw_chat(&[0xff, 0xfe], 2)).is_err());
+        assert!(decode_err(&[0x08, 0]).contains("truncated"));
+    }
+
+    #[test]
+    fn short_moves_are_truncated_not_unknown() {
+        let moved = encode_message(&Message::Moved(BoardMove::new(
+            BoardPos { row: 6, col: 4 },
+            BoardPos { row: 4, col: 4 },
+        )));
+        assert!(decode_message(&moved).is_ok());
+        assert!(decode_err(&moved[..moved.len() - 1]).contains("truncated move"));
+        assert!(decode_err(&[0x01]).contains("truncated move"));
+        assert!(decode_err(&[0x42]).contains("invalid message kind"));
+    }
+}
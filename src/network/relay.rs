@@ -0,0 +1,246 @@
+//! Playing through a relay server, for players who cannot reach each other directly. Both
+//! connect to the server, one opens a game there and the other joins it, and once the server
+//! paired them the game goes on as between a [`Host`] and a [`Client`] on the connection to it.
+
+use super::client::Client;
+use super::handshake::{self, HandshakeError, Hello, Peer};
+use super::host::Host;
+use super::lobby::{GameId, Lobby, OpenGame, DEFAULT_RELAY_PORT};
+use super::secure::Passphrase;
+use super::stream::FramedStream;
+use super::{Connection, Message, MessageQueue};
+use anyhow::{anyhow, bail, Result};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// How long [`list_games`] waits for the server
+const LIST_T: Duration = Duration::from_secs(3);
+/// How long connecting to the server may take
+const CONNECT_T: Duration = Duration::from_secs(10);
+
+pub struct Relay {
+    stage: Stage,
+    /// for the peer once the server paired the players
+    hello: Option<Hello>,
+    /// sent before the server paired the players, passed on once it did
+    send: MessageQueue,
+    /// the game to join, a new one is opened when `None`, which makes the player its host
+    join: Option<GameId>,
+    /// what the server lists the game as once it opened it
+    opened: Option<GameId>,
+    /// the server was asked to open or join the game
+    asked: bool,
+    passphrase: Option<Passphrase>,
+    shutdown: bool,
+}
+
+enum Stage {
+    /// talking to the server
+    Lobby(Box<FramedStream>),
+    Host(Box<Host>),
+    Client(Box<Client>),
+    /// the server turned the player away, which ends the connection
+    Refused(String),
+}
+
+impl Relay {
+    /// Opens a game on the server and waits for someone to join it
+    pub fn open(server: &str, hello: Hello) -> Result<Self> {
+        Self::new(server, hello, None)
+    }
+    /// Joins the game the server listed under `id`
+    pub fn join(server: &str, hello: Hello, id: GameId) -> Result<Self> {
+        Self::new(server, hello, Some(id))
+    }
+    fn new(server: &str, hello: Hello, join: Option<GameId>) -> Result<Self> {
+        Ok(Self {
+            stage: Stage::Lobby(Box::new(connect(server)?)),
+            hello: Some(hello),
+            send: MessageQueue::new(),
+            join,
+            opened: None,
+            asked: false,
+            passphrase: None,
+            shutdown: false,
+        })
+    }
+    /// Only the players need it, the server passes the encrypted frames on without seeing into
+    /// them
+    pub fn with_passphrase(mut self, passphrase: Option<Passphrase>) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+    /// Waits for the server to pair the players
+    /// # Returns
+    /// what the game goes on as once it did
+    fn lobby(&mut self) -> Result<Option<Stage>> {
+        let request = (!self.asked).then(|| self.request());
+        let Stage::Lobby(tcp) = &mut self.stage else {
+            return Ok(None);
+        };
+        if let Some(request) = request {
+            tcp.send_frame(&request.encode())?;
+            self.asked = true;
+        }
+        tcp.flush()?;
+        // one frame at a time, the peer's hello may follow right after the pairing
+        while let Some(frame) = tcp.recv_frame()? {
+            match Lobby::decode(&frame)? {
+                Lobby::Opened(id) => self.opened = Some(id),
+                Lobby::Paired() => return self.paired().map(Some),
+                Lobby::Refused(reason) => return Ok(Some(Stage::Refused(reason))),
+                msg => bail!("Lobby: unexpected {msg:?}"),
+            }
+        }
+        Ok(None)
+    }
+    /// Games are listed with the player's name, the time control and whether they need a
+    /// passphrase
+    fn request(&self) -> Lobby {
+        if let Some(id) = self.join {
+            return Lobby::Join(id);
+        }
+        let (name, time_control) = self
+            .hello
+            .as_ref()
+            .map_or((String::new(), None), |h| (h.name.clone(), h.time_control));
+        let clock = time_control.map_or("untimed".to_owned(), |tc| tc.to_string());
+        let details = match self.passphrase {
+            Some(_) => format!("{clock}, passphrase"),
+            None => clock,
+        };
+        Lobby::Open { name, details }
+    }
+    fn paired(&mut self) -> Result<Stage> {
+        // the placeholder is replaced with what is returned right away
+        let placeholder = Stage::Refused(String::new());
+        let (Stage::Lobby(tcp), Some(hello)) = (
+            std::mem::replace(&mut self.stage, placeholder),
+            self.hello.take(),
+        ) else {
+            unreachable!("only paired from the lobby");
+        };
+        let passphrase = self.passphrase.clone();
+        let mut stage = if self.join.is_none() {
+            Stage::Host(Box::new(
+                Host::relayed(*tcp, hello)?.with_passphrase(passphrase),
+            ))
+        } else {
+            Stage::Client(Box::new(
+                Client::relayed(*tcp, hello).with_passphrase(passphrase),
+            ))
+        };
+        if let Some(conn) = stage.connection() {
+            for msg in self.send.drain(..) {
+                conn.send(msg);
+            }
+        }
+        Ok(stage)
+    }
+}
+
+impl Stage {
+    fn connection(&mut self) -> Option<&mut dyn Connection> {
+        match self {
+            Stage::Host(host) => Some(host.as_mut()),
+            Stage::Client(client) => Some(client.as_mut()),
+            Stage::Lobby(_) | Stage::Refused(_) => None,
+        }
+    }
+}
+
+impl Connection for Relay {
+    fn poll(&mut self) -> Result<()> {
+        if let Some(stage) = self.lobby()? {
+            self.stage = stage;
+        }
+        match &mut self.stage {
+            Stage::Host(host) => host.poll(),
+            Stage::Client(client) => client.poll(),
+            Stage::Refused(reason) => bail!("refused by the relay: {reason}"),
+            Stage::Lobby(_) => Ok(()),
+        }
+    }
+    fn send(&mut self, msg: Message) {
+        match self.stage.connection() {
+            Some(conn) => conn.send(msg),
+            None => self.send.push_back(msg),
+        }
+    }
+    fn recv(&mut self) -> Option<Message> {
+        self.stage.connection()?.recv()
+    }
+    fn is_connected(&self) -> bool {
+        match &self.stage {
+            Stage::Host(host) => host.is_connected(),
+            Stage::Client(client) => client.is_connected(),
+            Stage::Lobby(_) | Stage::Refused(_) => false,
+        }
+    }
+    fn shutdown(&mut self) {
+        if !self.shutdown {
+            match &mut self.stage {
+                Stage::Lobby(tcp) => {
+                    let _ = tcp.get_ref().shutdown(Shutdown::Both);
+                }
+                Stage::Host(host) => host.shutdown(),
+                Stage::Client(client) => client.shutdown(),
+                Stage::Refused(_) => (),
+            }
+            self.shutdown = true;
+        }
+    }
+    fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+    fn peer(&self) -> Option<&Peer> {
+        match &self.stage {
+            Stage::Host(host) => host.peer(),
+            Stage::Client(client) => client.peer(),
+            Stage::Lobby(_) | Stage::Refused(_) => None,
+        }
+    }
+    fn relay_game(&self) -> Option<GameId> {
+        self.opened
+    }
+}
+
+/// Asks the server which games wait for someone to join them
+pub fn list_games(server: &str) -> Result<Vec<OpenGame>> {
+    let mut tcp = connect(server)?;
+    tcp.send_frame(&Lobby::List().encode())?;
+    let deadline = Instant::now() + LIST_T;
+    while Instant::now() < deadline {
+        tcp.flush()?;
+        if let Some(frame) = tcp.recv_frame()? {
+            let _ = tcp.get_ref().shutdown(Shutdown::Both);
+            return match Lobby::decode(&frame)? {
+                Lobby::Games(games) => Ok(games
+                    .into_iter()
+                    .map(|game| OpenGame {
+                        name: handshake::clean_name(&game.name),
+                        details: handshake::clean_name(&game.details),
+                        ..game
+                    })
+                    .collect()),
+                Lobby::Refused(reason) => Err(HandshakeError::Refused(reason).into()),
+                msg => bail!("Lobby: unexpected {msg:?}"),
+            };
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    bail!("the relay server did not answer")
+}
+
+/// The server's port can be left out, servers which cannot be reached are given up on after
+/// [`CONNECT_T`]
+fn connect(server: &str) -> Result<FramedStream> {
+    let addr = server
+        .to_socket_addrs()
+        .or_else(|_| (server, DEFAULT_RELAY_PORT).to_socket_addrs())?
+        .next()
+        .ok_or(anyhow!("{server} does not resolve"))?;
+    let tcp = TcpStream::connect_timeout(&addr, CONNECT_T)?;
+    tcp.set_nonblocking(true)?;
+    Ok(FramedStream::new(tcp))
+}
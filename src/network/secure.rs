@@ -0,0 +1,155 @@
+//! Optional encryption of a connection with a passphrase both players know. Every handshake
+//! stretches the passphrase into a key salted with the client's nonce, the hello and the welcome
+//! prove with it that each end knows the passphrase and what the other end said, and the frames
+//! after them are sealed with ChaCha20-Poly1305 under keys drawn from both ends' nonces, so that
+//! nobody without the passphrase can read or inject them.
+
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Bytes a sealed frame is longer than the plain one
+pub const OVERHEAD: usize = 16;
+/// Makes guessing passphrases slow, the derivation runs once per handshake
+const ROUNDS: u32 = 100_000;
+const SALT: &[u8] = b"pawn hearts passphrase";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A nonce and the proof that whoever sent it knows the passphrase
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Auth {
+    pub nonce: [u8; 16],
+    pub proof: [u8; 32],
+}
+
+/// The passphrase as it was typed, never sent anywhere
+#[derive(Clone)]
+pub struct Passphrase {
+    text: String,
+}
+
+impl Passphrase {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+        }
+    }
+    /// The key of the handshake the client opened with `client_nonce`, a table of keys
+    /// stretched in advance is no use since every handshake has its own salt
+    pub fn stretch(&self, client_nonce: &[u8; 16]) -> Secret {
+        let salt = [SALT, client_nonce].concat();
+        Secret {
+            key: pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(self.text.as_bytes(), &salt, ROUNDS),
+            client_nonce: *client_nonce,
+        }
+    }
+}
+
+/// The key one handshake stretched from the passphrase
+pub struct Secret {
+    key: [u8; 32],
+    client_nonce: [u8; 16],
+}
+
+impl Secret {
+    /// Proves knowing the passphrase along with what was said in the handshake so far, so that
+    /// none of it can be changed on the way
+    pub fn proof(&self, label: &[u8], transcript: &[&[u8]]) -> [u8; 32] {
+        self.mac(label, transcript).finalize().into_bytes().into()
+    }
+    pub fn verify(&self, label: &[u8], transcript: &[&[u8]], proof: &[u8]) -> bool {
+        self.mac(label, transcript).verify_slice(proof).is_ok()
+    }
+    /// The ciphers of one connection, each direction has its own key
+    pub fn session(&self, host_nonce: &[u8; 16], is_host: bool) -> Cipher {
+        let salt = [self.client_nonce, *host_nonce].concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), &self.key);
+        let key = |info: &[u8]| {
+            let mut key = [0; 32];
+            // 32 bytes are well within what HKDF can expand to
+            hkdf.expand(info, &mut key).unwrap();
+            ChaCha20Poly1305::new(Key::from_slice(&key))
+        };
+        let (to_host, to_client) = (key(b"client to host"), key(b"host to client"));
+        let (seal, open) = if is_host {
+            (to_client, to_host)
+        } else {
+            (to_host, to_client)
+        };
+        Cipher {
+            seal,
+            open,
+            sealed: 0,
+            opened: 0,
+        }
+    }
+    fn mac(&self, label: &[u8], transcript: &[&[u8]]) -> HmacSha256 {
+        // HMAC takes keys of any length
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.key).unwrap();
+        mac.update(label);
+        for part in transcript {
+            mac.update(&(part.len() as u32).to_be_bytes());
+            mac.update(part);
+        }
+        mac
+    }
+}
+
+/// Seals outgoing frames and opens incoming ones. Frames are numbered in each direction, so one
+/// which was dropped, replayed or reordered does not open.
+pub struct Cipher {
+    seal: ChaCha20Poly1305,
+    open: ChaCha20Poly1305,
+    sealed: u64,
+    opened: u64,
+}
+
+impl Cipher {
+    pub fn seal(&mut self, frame: &[u8]) -> Vec<u8> {
+        let nonce = counter_nonce(self.sealed);
+        self.sealed += 1;
+        // only fails for frames far longer than any of ours
+        self.seal.encrypt(&nonce, frame).unwrap()
+    }
+    pub fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>> {
+        let nonce = counter_nonce(self.opened);
+        self.opened += 1;
+        self.open
+            .decrypt(&nonce, frame)
+            .map_err(|_| anyhow!("a frame failed authentication"))
+    }
+}
+
+fn counter_nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_talk_to_each_other_only() {
+        let passphrase = Passphrase::new("open sesame");
+        let secret = passphrase.stretch(&[1; 16]);
+        let mut client = secret.session(&[2; 16], false);
+        let mut host = secret.session(&[2; 16], true);
+        let sealed = client.seal(b"e2e4");
+        assert_eq!(sealed.len(), 4 + OVERHEAD);
+        assert_eq!(host.open(&sealed).unwrap(), b"e2e4");
+        assert_eq!(client.open(&host.seal(b"e7e5")).unwrap(), b"e7e5");
+        // replayed frames do not open
+        assert!(host.open(&sealed).is_err());
+        // another nonce of either end makes other keys
+        let mut other = passphrase.stretch(&[3; 16]).session(&[2; 16], true);
+        assert!(other.open(&client.seal(b"d2d4")).is_err());
+        let mut other = secret.session(&[4; 16], true);
+        assert!(other.open(&client.seal(b"d2d4")).is_err());
+    }
+}
@@ -0,0 +1,458 @@
+//! The relay server for players who cannot reach each other directly, usually because both are
+//! behind NAT. Players connect to it, open games or join the ones listed, and once two are paired
+//! every frame one sends is passed on to the other. It only speaks the lobby protocol and the
+//! framing, the games themselves go through it unread.
+
+use super::lobby::{GameId, Lobby, OpenGame};
+use super::stream::FramedStream;
+use anyhow::{anyhow, Result};
+use std::io::ErrorKind;
+use std::net::{Shutdown, SocketAddr, TcpListener};
+use std::time::{Duration, Instant};
+
+/// Pause between rounds of passing frames on, short enough not to be noticed in a game
+const POLL_T: Duration = Duration::from_millis(5);
+/// Players in the lobby who did not open a game are let go once they said nothing for this long
+const IDLE_T: Duration = Duration::from_secs(10);
+/// Most players waiting in the lobby at once, the ones coming later are turned away
+const MAX_LOBBY: usize = 256;
+/// Bytes which may wait for a player who does not read, before the server stops reading from
+/// the other one
+const MAX_BACKLOG: usize = 64 * 1024;
+/// A pair whose backlog stays over [`MAX_BACKLOG`] for this long is let go
+const STALL_T: Duration = Duration::from_secs(30);
+
+/// A player who is not paired yet
+struct Waiting {
+    tcp: FramedStream,
+    /// set once they opened a game
+    game: Option<OpenGame>,
+    /// when they last said something
+    heard: Instant,
+}
+
+/// The host of a game and the player who joined it
+struct Pair {
+    host: FramedStream,
+    joiner: FramedStream,
+    /// since when one of them has not been reading what the other sends
+    stalled: Option<Instant>,
+}
+
+pub struct Server {
+    list: TcpListener,
+    lobby: Vec<Waiting>,
+    /// players beyond it are turned away, [`MAX_LOBBY`] unless a test lowers it
+    max_lobby: usize,
+    pairs: Vec<Pair>,
+    next_id: GameId,
+}
+
+impl Server {
+    pub fn bind(address: &str) -> Result<Self> {
+        let list = TcpListener::bind(address)?;
+        list.set_nonblocking(true)?;
+        Ok(Self {
+            list,
+            lobby: vec![],
+            max_lobby: MAX_LOBBY,
+            pairs: vec![],
+            next_id: 1,
+        })
+    }
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.list.local_addr()?)
+    }
+    /// Takes in new players, answers the ones in the lobby and passes frames on between the
+    /// paired ones
+    pub fn poll(&mut self) {
+        self.accept();
+        self.poll_lobby();
+        self.relay();
+    }
+    /// Failing to accept someone, like when the server is out of file descriptors, is no reason
+    /// to stop serving the others
+    fn accept(&mut self) {
+        loop {
+            let tcp = match self.list.accept() {
+                Ok((tcp, _)) => tcp,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    eprintln!("could not accept a player: {e}");
+                    return;
+                }
+            };
+            if let Err(e) = tcp.set_nonblocking(true) {
+                eprintln!("could not accept a player: {e}");
+                continue;
+            }
+            let mut tcp = FramedStream::new(tcp);
+            if self.lobby.len() >= self.max_lobby {
+                let full = Lobby::Refused("the server is full".to_owned());
+                let _ = tcp.send_frame(&full.encode());
+                let _ = tcp.get_ref().shutdown(Shutdown::Both);
+                continue;
+            }
+            self.lobby.push(Waiting {
+                tcp,
+                game: None,
+                heard: Instant::now(),
+            });
+        }
+    }
+    /// Answers what the players in the lobby ask for and pairs the ones which join a game with
+    /// its host. Players who leave, or say something the server does not understand, are let go.
+    fn poll_lobby(&mut self) {
+        let open = self
+            .lobby
+            .iter()
+            .filter_map(|p| p.game.clone())
+            .collect::<Vec<_>>();
+        let mut gone = vec![false; self.lobby.len()];
+        let mut joins = vec![];
+        for (n, player) in self.lobby.iter_mut().enumerate() {
+            // one frame at a time, whatever follows a join belongs to the game
+            loop {
+                let frame = match player.tcp.recv_frame() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => {
+                        // whoever opened a game waits quietly for someone to join it
+                        gone[n] = player.game.is_none() && player.heard.elapsed() >= IDLE_T;
+                        break;
+                    }
+                    Err(_) => {
+                        gone[n] = true;
+                        break;
+                    }
+                };
+                player.heard = Instant::now();
+                let answer = match Lobby::decode(&frame) {
+                    Ok(Lobby::Open { name, details }) if player.game.is_none() => {
+                        let id = self.next_id;
+                        self.next_id = self.next_id.wrapping_add(1);
+                        eprintln!("{name} opened game {id}");
+                        player.game = Some(OpenGame { id, name, details });
+                        Lobby::Opened(id)
+                    }
+                    Ok(Lobby::List()) => Lobby::Games(open.clone()),
+                    Ok(Lobby::Join(id)) if player.game.is_none() => {
+                        joins.push((n, id));
+                        break;
+                    }
+                    _ => {
+                        gone[n] = true;
+                        Lobby::Refused("the request was not understood".to_owned())
+                    }
+                };
+                if player.tcp.send_frame(&answer.encode()).is_err() {
+                    gone[n] = true;
+                }
+                if gone[n] {
+                    break;
+                }
+            }
+        }
+        let mut pairs: Vec<(usize, usize)> = vec![];
+        for (joiner, id) in joins {
+            let host = self
+                .lobby
+                .iter()
+                .position(|p| p.game.as_ref().is_some_and(|g| g.id == id))
+                .filter(|host| !gone[*host] && !pairs.iter().any(|(h, _)| h == host));
+            match host {
+                Some(host) => pairs.push((host, joiner)),
+                None => {
+                    let refused = Lobby::Refused(format!("game {id} is not open"));
+                    let _ = self.lobby[joiner].tcp.send_frame(&refused.encode());
+                    gone[joiner] = true;
+                }
+            }
+        }
+        let mut players = std::mem::take(&mut self.lobby)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        for (host, joiner) in pairs {
+            let (Some(mut host), Some(mut joiner)) = (players[host].take(), players[joiner].take())
+            else {
+                continue;
+            };
+            if let Some(game) = &host.game {
+                eprintln!("game {} started", game.id);
+            }
+            let paired = Lobby::Paired().encode();
+            let _ = host.tcp.send_frame(&paired);
+            let _ = joiner.tcp.send_frame(&paired);
+            self.pairs.push(Pair {
+                host: host.tcp,
+                joiner: joiner.tcp,
+                stalled: None,
+            });
+        }
+        for (n, player) in players.into_iter().enumerate() {
+            match player {
+                Some(player) if !gone[n] => self.lobby.push(player),
+                Some(player) => {
+                    let _ = player.tcp.get_ref().shutdown(Shutdown::Both);
+                }
+                None => (),
+            }
+        }
+    }
+    /// Passes frames on between paired players, once either leaves, or stops reading for too
+    /// long, the other is let go too
+    fn relay(&mut self) {
+        self.pairs.retain_mut(|pair| {
+            let res = pass_on(&mut pair.host, &mut pair.joiner)
+                .and_then(|_| pass_on(&mut pair.joiner, &mut pair.host))
+                .and_then(|_| pair.check_backlog());
+            if let Err(e) = &res {
+                eprintln!("a game ended: {e}");
+                for tcp in [&mut pair.host, &mut pair.joiner] {
+                    let _ = tcp.flush();
+                    let _ = tcp.get_ref().shutdown(Shutdown::Both);
+                }
+            }
+            res.is_ok()
+        });
+    }
+}
+
+impl Pair {
+    fn check_backlog(&mut self) -> Result<()> {
+        if self.host.unsent().max(self.joiner.unsent()) <= MAX_BACKLOG {
+            self.stalled = None;
+            return Ok(());
+        }
+        let since = *self.stalled.get_or_insert_with(Instant::now);
+        if since.elapsed() >= STALL_T {
+            return Err(anyhow!("a player stopped reading"));
+        }
+        Ok(())
+    }
+}
+
+/// Passes what `from` sent on to `to`, unless too much already waits for `to` to read it, then
+/// `from` is left to wait
+fn pass_on(from: &mut FramedStream, to: &mut FramedStream) -> Result<()> {
+    to.flush()?;
+    if to.unsent() > MAX_BACKLOG {
+        return Ok(());
+    }
+    for frame in from.recv_frames()? {
+        to.send_frame(&frame)?;
+    }
+    Ok(())
+}
+
+/// Relays games on `address` for good
+pub fn run(address: &str) -> Result<()> {
+    let mut server = Server::bind(address)?;
+    println!("relaying games on {address}");
+    loop {
+        server.poll();
+        std::thread::sleep(POLL_T);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::handshake::Hello;
+    use crate::network::relay::Relay;
+    use crate::network::stream::MAX_FRAME;
+    use crate::network::{Connection, Message};
+    use bytes::Bytes;
+    use std::net::TcpStream;
+
+    fn server() -> (Server, String) {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        (server, addr)
+    }
+
+    /// Someone talking to the server frame by frame
+    fn player(addr: &str) -> FramedStream {
+        let tcp = TcpStream::connect(addr).unwrap();
+        tcp.set_nonblocking(true).unwrap();
+        FramedStream::new(tcp)
+    }
+
+    /// Runs the server until `player` gets a frame
+    fn next_frame(server: &mut Server, player: &mut FramedStream) -> Result<Bytes> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            server.poll();
+            player.flush()?;
+            if let Some(frame) = player.recv_frame()? {
+                return Ok(frame);
+            }
+            std::thread::sleep(POLL_T);
+        }
+        Err(anyhow!("no frame"))
+    }
+
+    fn ask(server: &mut Server, player: &mut FramedStream, request: Lobby) -> Lobby {
+        player.send_frame(&request.encode()).unwrap();
+        Lobby::decode(&next_frame(server, player).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn games_are_listed_and_joined_once() {
+        let (mut server, addr) = server();
+        let open = |name: &str| Lobby::Open {
+            name: name.to_owned(),
+            details: "untimed".to_owned(),
+        };
+        let (mut alice, mut bob) = (player(&addr), player(&addr));
+        assert_eq!(
+            ask(&mut server, &mut alice, open("alice")),
+            Lobby::Opened(1)
+        );
+        assert_eq!(ask(&mut server, &mut bob, open("bob")), Lobby::Opened(2));
+        let mut carol = player(&addr);
+        let Lobby::Games(games) = ask(&mut server, &mut carol, Lobby::List()) else {
+            panic!("no list");
+        };
+        let names = games.iter().map(|g| g.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["alice", "bob"]);
+
+        assert_eq!(
+            ask(&mut server, &mut carol, Lobby::Join(2)),
+            Lobby::Paired()
+        );
+        let paired = next_frame(&mut server, &mut bob).unwrap();
+        assert_eq!(Lobby::decode(&paired).unwrap(), Lobby::Paired());
+        let mut dave = player(&addr);
+        assert!(matches!(
+            ask(&mut server, &mut dave, Lobby::Join(2)),
+            Lobby::Refused(_)
+        ));
+        let mut erin = player(&addr);
+        let Lobby::Games(games) = ask(&mut server, &mut erin, Lobby::List()) else {
+            panic!("no list");
+        };
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].name, "alice");
+
+        // paired players get each other's frames as they are
+        carol.send_frame(b"to bob").unwrap();
+        assert_eq!(next_frame(&mut server, &mut bob).unwrap(), &b"to bob"[..]);
+        bob.send_frame(b"to carol").unwrap();
+        assert_eq!(
+            next_frame(&mut server, &mut carol).unwrap(),
+            &b"to carol"[..]
+        );
+        // and alice's game is not disturbed by theirs
+        assert!(alice.recv_frame().unwrap().is_none());
+        // once one of them leaves the other is let go too
+        carol.get_ref().shutdown(Shutdown::Both).unwrap();
+        assert!(next_frame(&mut server, &mut bob).is_err());
+    }
+
+    #[test]
+    fn relayed_players_play_through_the_server() {
+        let (mut server, addr) = server();
+        let mut host = Relay::open(&addr, Hello::new("alice")).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.lobby.iter().all(|p| p.game.is_none()) {
+            assert!(Instant::now() < deadline, "the game was not opened");
+            host.poll().unwrap();
+            server.poll();
+            std::thread::sleep(POLL_T);
+        }
+        let mut client = Relay::join(&addr, Hello::new("bob"), 1).unwrap();
+        let mut received = None;
+        while received.is_none() {
+            assert!(Instant::now() < deadline, "nothing got through");
+            server.poll();
+            host.poll().unwrap();
+            client.poll().unwrap();
+            if host.is_connected() && client.is_connected() {
+                host.send(Message::Chat("hello".to_owned()));
+            }
+            received = client.recv();
+            std::thread::sleep(POLL_T);
+        }
+        assert!(matches!(received, Some(Message::Chat(text)) if text == "hello"));
+        assert_eq!(host.peer().unwrap().name, "bob");
+        assert_eq!(client.peer().unwrap().name, "alice");
+        // the host can tell the other player which game to join
+        assert_eq!(host.relay_game(), Some(1));
+        assert_eq!(client.relay_game(), None);
+    }
+
+    #[test]
+    fn the_lobby_lets_go_of_idle_players_and_turns_away_extra_ones() {
+        let (mut server, addr) = server();
+        server.max_lobby = 2;
+        let mut host = player(&addr);
+        let open = Lobby::Open {
+            name: "alice".to_owned(),
+            details: "untimed".to_owned(),
+        };
+        assert_eq!(ask(&mut server, &mut host, open), Lobby::Opened(1));
+        let mut idle = player(&addr);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.lobby.len() < 2 {
+            assert!(Instant::now() < deadline, "the player was not taken in");
+            server.poll();
+        }
+        let mut extra = player(&addr);
+        assert!(matches!(
+            Lobby::decode(&next_frame(&mut server, &mut extra).unwrap()).unwrap(),
+            Lobby::Refused(_)
+        ));
+        assert_eq!(server.lobby.len(), 2);
+
+        // only the one without a game goes once they were quiet for long enough
+        for player in &mut server.lobby {
+            player.heard = Instant::now().checked_sub(IDLE_T).unwrap();
+        }
+        server.poll();
+        assert_eq!(server.lobby.len(), 1);
+        assert!(server.lobby[0].game.is_some());
+        assert!(next_frame(&mut server, &mut idle).is_err());
+    }
+
+    #[test]
+    fn players_who_do_not_read_hold_back_the_other_and_are_let_go() {
+        let (mut server, addr) = server();
+        let mut host = player(&addr);
+        let open = Lobby::Open {
+            name: "alice".to_owned(),
+            details: "untimed".to_owned(),
+        };
+        assert_eq!(ask(&mut server, &mut host, open), Lobby::Opened(1));
+        // the joiner never reads anything after being paired
+        let mut joiner = player(&addr);
+        assert_eq!(
+            ask(&mut server, &mut joiner, Lobby::Join(1)),
+            Lobby::Paired()
+        );
+        let paired = next_frame(&mut server, &mut host).unwrap();
+        assert_eq!(Lobby::decode(&paired).unwrap(), Lobby::Paired());
+
+        let frame = vec![0; MAX_FRAME];
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while server.pairs[0].joiner.unsent() <= MAX_BACKLOG {
+            assert!(
+                Instant::now() < deadline,
+                "the joiner's socket never filled up"
+            );
+            host.send_frame(&frame).unwrap();
+            server.poll();
+        }
+        let backlog = server.pairs[0].joiner.unsent();
+        for _ in 0..50 {
+            host.send_frame(&frame).unwrap();
+            server.poll();
+        }
+        assert_eq!(server.pairs[0].joiner.unsent(), backlog);
+        assert!(server.pairs[0].stalled.is_some());
+
+        server.pairs[0].stalled = Instant::now().checked_sub(STALL_T);
+        server.poll();
+        assert!(server.pairs.is_empty());
+    }
+}
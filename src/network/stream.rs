@@ -0,0 +1,353 @@
+//! Length prefixed frames on top of a non-blocking byte stream. Every frame starts with its
+//! length as a big endian `u16`, so that messages survive being split across reads or glued
+//! together by TCP. Once a [`Cipher`] is set every frame body is sealed by it.
+//!
+//! Hosts and clients only see [`Frames`], which WebSocket connections provide as well.
+
+use super::secure::{Cipher, OVERHEAD};
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+
+/// Frames longer than this are refused, anything legitimate is far shorter
+pub const MAX_FRAME: usize = 16 * 1024;
+const LENGTH_SZ: usize = 2;
+const READ_CHUNK: usize = 4096;
+
+/// A connection carrying whole frames, whichever way they are told apart on the wire. Hosts and
+/// clients may run on a thread of their own, so it has to be able to go there with them.
+pub trait Frames: Send {
+    /// Reads everything available without blocking
+    /// # Returns
+    /// the bodies of all the frames completed so far, partial ones are kept for later
+    fn recv_frames(&mut self) -> Result<Vec<Bytes>>;
+    /// Queues the frame and writes as much as the socket takes right now
+    fn send_frame(&mut self, body: &[u8]) -> Result<()>;
+    /// Writes queued bytes until the socket would block, the rest waits for the next call
+    fn flush(&mut self) -> Result<()>;
+    /// Seals the frames sent from now on and opens the ones received
+    fn set_cipher(&mut self, cipher: Cipher);
+    /// Opens a frame which was received before the cipher was set
+    fn open(&mut self, frame: &[u8]) -> Result<Bytes>;
+    /// Whether the peer closed its side
+    fn is_closed(&self) -> bool;
+    /// Bytes read which do not make up a whole frame yet
+    fn buffered(&self) -> &[u8];
+    /// The socket underneath, for its address and for blocking while closing
+    fn socket(&self) -> &TcpStream;
+    /// Ends the connection without waiting for the peer, which cannot fail as it may be gone
+    fn hang_up(&mut self);
+}
+
+pub struct FramedStream<S: Read + Write = TcpStream> {
+    inner: S,
+    /// bytes read which do not make up a whole frame yet
+    recv_buf: BytesMut,
+    /// frames, or the rest of them, which the socket did not take yet
+    send_buf: BytesMut,
+    /// the peer closed its side, reported once the frames before it are handed out
+    closed: bool,
+    /// set once the handshake agreed on a passphrase
+    cipher: Option<Cipher>,
+}
+
+impl<S: Read + Write> FramedStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            recv_buf: BytesMut::new(),
+            send_buf: BytesMut::new(),
+            closed: false,
+            cipher: None,
+        }
+    }
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    /// Seals the frames sent from now on and opens the ones received
+    pub fn set_cipher(&mut self, cipher: Cipher) {
+        self.cipher = Some(cipher);
+    }
+    /// Opens a frame which was received before the cipher was set, like the ones which arrived
+    /// along with the handshake
+    pub fn open(&mut self, frame: &[u8]) -> Result<Bytes> {
+        match self.cipher.as_mut() {
+            Some(cipher) => Ok(cipher.open(frame)?.into()),
+            None => Ok(Bytes::copy_from_slice(frame)),
+        }
+    }
+    /// Reads everything available without blocking
+    /// # Returns
+    /// the bodies of all the frames completed so far, partial ones are kept for later
+    pub fn recv_frames(&mut self) -> Result<Vec<Bytes>> {
+        if !self.closed {
+            self.fill()?;
+        }
+        let mut frames = vec![];
+        while let Some(frame) = self.next_frame()? {
+            frames.push(frame);
+        }
+        if self.closed && frames.is_empty() {
+            bail!("connection closed");
+        }
+        Ok(frames)
+    }
+    /// Like [`FramedStream::recv_frames`], but only the first frame is taken and the ones after
+    /// it are left for whoever reads next
+    pub fn recv_frame(&mut self) -> Result<Option<Bytes>> {
+        if !self.closed {
+            self.fill()?;
+        }
+        match self.next_frame()? {
+            Some(frame) => Ok(Some(frame)),
+            None if self.closed => bail!("connection closed"),
+            None => Ok(None),
+        }
+    }
+    /// Reads until the socket would block or the peer closed its side
+    fn fill(&mut self) -> Result<()> {
+        let mut chunk = [0; READ_CHUNK];
+        loop {
+            match self.inner.read(&mut chunk) {
+                Ok(0) => {
+                    self.closed = true;
+                    return Ok(());
+                }
+                Ok(n) => self.recv_buf.put_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    /// Takes the first complete frame out of what was read
+    fn next_frame(&mut self) -> Result<Option<Bytes>> {
+        if self.recv_buf.len() < LENGTH_SZ {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([self.recv_buf[0], self.recv_buf[1]]) as usize;
+        if len > MAX_FRAME + OVERHEAD {
+            bail!("frame of {len} bytes is too long");
+        }
+        if self.recv_buf.len() < LENGTH_SZ + len {
+            return Ok(None);
+        }
+        self.recv_buf.advance(LENGTH_SZ);
+        let frame = self.recv_buf.split_to(len).freeze();
+        Ok(Some(match self.cipher.as_mut() {
+            Some(cipher) => cipher.open(&frame)?.into(),
+            None => frame,
+        }))
+    }
+    /// Queues the frame and writes as much as the socket takes right now
+    pub fn send_frame(&mut self, body: &[u8]) -> Result<()> {
+        if body.len() > MAX_FRAME {
+            bail!("frame of {} bytes is too long", body.len());
+        }
+        match self.cipher.as_mut() {
+            Some(cipher) => {
+                let sealed = cipher.seal(body);
+                self.send_buf.put_u16(sealed.len() as u16);
+                self.send_buf.put_slice(&sealed);
+            }
+            None => {
+                self.send_buf.put_u16(body.len() as u16);
+                self.send_buf.put_slice(body);
+            }
+        }
+        self.flush()
+    }
+    /// Writes queued bytes until the socket would block, the rest waits for the next call
+    pub fn flush(&mut self) -> Result<()> {
+        while !self.send_buf.is_empty() {
+            match self.inner.write(&self.send_buf) {
+                Ok(0) => bail!("connection closed"),
+                Ok(n) => self.send_buf.advance(n),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+    /// Bytes read which do not make up a whole frame yet
+    pub fn buffered(&self) -> &[u8] {
+        &self.recv_buf
+    }
+    /// Whether the peer closed its side
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+    /// Whether some bytes are still waiting to be written
+    pub fn is_pending(&self) -> bool {
+        !self.send_buf.is_empty()
+    }
+    /// How many bytes are still waiting to be written
+    pub fn unsent(&self) -> usize {
+        self.send_buf.len()
+    }
+}
+
+impl Frames for FramedStream {
+    fn recv_frames(&mut self) -> Result<Vec<Bytes>> {
+        FramedStream::recv_frames(self)
+    }
+    fn send_frame(&mut self, body: &[u8]) -> Result<()> {
+        FramedStream::send_frame(self, body)
+    }
+    fn flush(&mut self) -> Result<()> {
+        FramedStream::flush(self)
+    }
+    fn set_cipher(&mut self, cipher: Cipher) {
+        FramedStream::set_cipher(self, cipher)
+    }
+    fn open(&mut self, frame: &[u8]) -> Result<Bytes> {
+        FramedStream::open(self, frame)
+    }
+    fn is_closed(&self) -> bool {
+        FramedStream::is_closed(self)
+    }
+    fn buffered(&self) -> &[u8] {
+        FramedStream::buffered(self)
+    }
+    fn socket(&self) -> &TcpStream {
+        &self.inner
+    }
+    fn hang_up(&mut self) {
+        let _ = self.inner.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io;
+
+    /// A socket which hands out reads as they are queued and takes only as many bytes as there
+    /// is room for
+    #[derive(Default)]
+    struct Mock {
+        /// what each read returns, `None` would block and an empty read is the end
+        reads: VecDeque<Option<Vec<u8>>>,
+        written: Vec<u8>,
+        room: usize,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.reads.pop_front() {
+                Some(Some(data)) => {
+                    buf[..data.len()].copy_from_slice(&data);
+                    Ok(data.len())
+                }
+                Some(None) | None => Err(ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.room);
+            self.written.extend_from_slice(&buf[..n]);
+            self.room -= n;
+            Ok(n)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn framed(reads: impl IntoIterator<Item = Option<Vec<u8>>>) -> FramedStream<Mock> {
+        FramedStream::new(Mock {
+            reads: reads.into_iter().collect(),
+            ..Mock::default()
+        })
+    }
+
+    /// The bytes of `body` as a frame on the wire
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut ret = (body.len() as u16).to_be_bytes().to_vec();
+        ret.extend_from_slice(body);
+        ret
+    }
+
+    #[test]
+    fn frames_split_across_reads_are_put_together() {
+        let wire = frame(b"hello");
+        let mut stream = framed([Some(wire[..3].to_vec()), None, Some(wire[3..].to_vec())]);
+        assert!(stream.recv_frames().unwrap().is_empty());
+        assert_eq!(stream.buffered(), &wire[..3]);
+        assert_eq!(stream.recv_frames().unwrap(), [&b"hello"[..]]);
+        assert!(stream.buffered().is_empty());
+    }
+
+    #[test]
+    fn frames_read_together_are_told_apart() {
+        let wire = [frame(b"one"), frame(b"two"), frame(b"three")].concat();
+        let mut stream = framed([Some(wire.clone())]);
+        assert_eq!(
+            stream.recv_frames().unwrap(),
+            [&b"one"[..], &b"two"[..], &b"three"[..]]
+        );
+        // one at a time, the others wait for the next reader
+        let mut stream = framed([Some(wire)]);
+        assert_eq!(stream.recv_frame().unwrap().unwrap(), &b"one"[..]);
+        assert_eq!(stream.recv_frame().unwrap().unwrap(), &b"two"[..]);
+        assert_eq!(stream.recv_frames().unwrap(), [&b"three"[..]]);
+    }
+
+    #[test]
+    fn unwritten_bytes_wait_for_the_next_flush() {
+        let mut stream = framed([]);
+        stream.get_mut().room = 3;
+        stream.send_frame(b"hello").unwrap();
+        assert_eq!(stream.get_ref().written, frame(b"hello")[..3]);
+        assert_eq!(stream.unsent(), 4);
+        // a socket which would block takes nothing
+        stream.flush().unwrap();
+        assert!(stream.is_pending());
+        stream.get_mut().room = 100;
+        stream.send_frame(b"again").unwrap();
+        assert_eq!(
+            stream.get_ref().written,
+            [frame(b"hello"), frame(b"again")].concat()
+        );
+        assert!(!stream.is_pending());
+    }
+
+    #[test]
+    fn oversize_frames_are_refused() {
+        let mut stream = framed([]);
+        stream.get_mut().room = usize::MAX;
+        assert!(stream.send_frame(&vec![0; MAX_FRAME + 1]).is_err());
+        assert!(stream.get_ref().written.is_empty());
+        stream.send_frame(&vec![0; MAX_FRAME]).unwrap();
+
+        let len = (MAX_FRAME + OVERHEAD + 1) as u16;
+        let mut stream = framed([Some(len.to_be_bytes().to_vec())]);
+        let err = stream.recv_frames().unwrap_err();
+        assert!(err.to_string().contains("too long"));
+    }
+
+    #[test]
+    fn frames_before_the_close_are_handed_out_first() {
+        let wire = [frame(b"one"), frame(b"two")].concat();
+        let mut stream = framed([Some(wire.clone()), Some(vec![])]);
+        assert_eq!(stream.recv_frames().unwrap(), [&b"one"[..], &b"two"[..]]);
+        assert!(stream.is_closed());
+        assert!(stream.recv_frames().is_err());
+
+        let mut stream = framed([Some(wire), Some(vec![])]);
+        assert_eq!(stream.recv_frame().unwrap().unwrap(), &b"one"[..]);
+        assert_eq!(stream.recv_frame().unwrap().unwrap(), &b"two"[..]);
+        assert!(stream.recv_frame().is_err());
+    }
+}
@@ -0,0 +1,385 @@
+use super::{Connection, Message, MessageQueue};
+use crate::board::{BoardMove, ChessBoard, ChessPiece, Side};
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct UciConfig {
+    pub path: PathBuf,
+    /// each one is sent to the engine as `setoption name <name> value <value>`
+    pub options: Vec<(String, String)>,
+    pub movetime: Duration,
+}
+
+/// A running engine process, whatever it prints is collected line by line
+pub struct EngineProcess {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl EngineProcess {
+    pub fn spawn(path: &Path) -> Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("could not start engine {path:?}"))?;
+        let stdin = child.stdin.take().ok_or(anyhow!("engine stdin missing"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(anyhow!("engine stdout missing"))?;
+        // reading blocks so it happens on its own thread, polling only drains the channel
+        let (tx, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+        })
+    }
+    pub fn write(&mut self, command: &str) -> Result<()> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+    /// Skips over everything the engine printed until a line starting with `prefix`
+    /// # Returns
+    /// the matching line or `None` if it has not arrived yet
+    pub fn wait_for(&mut self, prefix: &str) -> Result<Option<String>> {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) if line.trim_start().starts_with(prefix) => return Ok(Some(line)),
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(anyhow!("engine exited")),
+            }
+        }
+    }
+    /// Same as [`Self::wait_for`] but blocks until the line arrives
+    /// # Returns
+    /// every line read up to and including the matching one, or `None` on timeout
+    pub fn wait_for_timeout(
+        &mut self,
+        prefix: &str,
+        timeout: Duration,
+    ) -> Result<Option<Vec<String>>> {
+        let deadline = Instant::now() + timeout;
+        let mut ret = vec![];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(left) {
+                Ok(line) => {
+                    let found = line.trim_start().starts_with(prefix);
+                    ret.push(line);
+                    if found {
+                        return Ok(Some(ret));
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("engine exited")),
+            }
+        }
+    }
+    /// Asks the engine to quit and kills it if it does not
+    pub fn quit(&mut self) {
+        // the engine may already be gone, in which case there is nothing to stop
+        let _ = self.write("quit");
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// An external engine speaking UCI over its standard input and output, used as a local opponent
+/// the same way as [`super::bot::Bot`]
+pub struct UciEngine {
+    state: EngineState,
+    config: UciConfig,
+    process: EngineProcess,
+    board: ChessBoard,
+    side: Side,
+    to_move: Side,
+    /// every move played so far, in UCI notation
+    moves: Vec<String>,
+    recv: MessageQueue,
+    shutdown: bool,
+}
+
+enum EngineState {
+    Begin,
+    WaitUciOk,
+    WaitReadyOk,
+    Ready,
+    Thinking,
+}
+
+impl UciEngine {
+    pub fn new(config: UciConfig, side: Side) -> Result<Self> {
+        Ok(Self {
+            state: EngineState::Begin,
+            process: EngineProcess::spawn(&config.path)?,
+            config,
+            board: ChessBoard::new_full(),
+            side,
+            to_move: Side::White,
+            moves: vec![],
+            recv: MessageQueue::new(),
+            shutdown: false,
+        })
+    }
+    fn write(&mut self, command: &str) -> Result<()> {
+        self.process.write(command)
+    }
+    fn wait_for(&mut self, prefix: &str) -> Result<Option<String>> {
+        self.process.wait_for(prefix)
+    }
+    fn position_command(&self) -> String {
+        if self.moves.is_empty() {
+            "position startpos".to_owned()
+        } else {
+            format!("position startpos moves {}", self.moves.join(" "))
+        }
+    }
+    fn play(&mut self, m: BoardMove) -> bool {
+        let uci = m.to_uci(&self.board);
+        if self.board.move_piece(m).is_some() {
+            self.moves.push(uci);
+            self.to_move = self.to_move.opposite();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Connection for UciEngine {
+    fn poll(&mut self) -> Result<()> {
+        if self.shutdown {
+            return Ok(());
+        }
+        self.state = match self.state {
+            EngineState::Begin => {
+                self.write("uci")?;
+                EngineState::WaitUciOk
+            }
+            EngineState::WaitUciOk => match self.wait_for("uciok")? {
+                Some(_) => {
+                    for (name, value) in self.config.options.clone() {
+                        self.write(&format!("setoption name {name} value {value}"))?;
+                    }
+                    self.write("ucinewgame")?;
+                    self.write("isready")?;
+                    EngineState::WaitReadyOk
+                }
+                None => EngineState::WaitUciOk,
+            },
+            EngineState::WaitReadyOk => match self.wait_for("readyok")? {
+                Some(_) => EngineState::Ready,
+                None => EngineState::WaitReadyOk,
+            },
+            EngineState::Ready if self.to_move == self.side => {
+                self.write(&self.position_command())?;
+                self.write(&format!("go movetime {}", self.config.movetime.as_millis()))?;
+                EngineState::Thinking
+            }
+            EngineState::Ready => EngineState::Ready,
+            EngineState::Thinking => match self.wait_for("bestmove")? {
+                Some(line) => {
+                    let uci = line
+                        .split_whitespace()
+                        .nth(1)
+                        .ok_or(anyhow!("engine sent an empty bestmove"))?;
+                    let m = BoardMove::from_uci(uci)
+                        .ok_or(anyhow!("engine sent an unreadable move '{uci}'"))?;
+                    // the board only promotes to queens, which would be another move than the
+                    // engine's
+                    if let Some(piece) =
+                        BoardMove::promotion_from_uci(uci).filter(|p| *p != ChessPiece::Queen)
+                    {
+                        bail!("engine promoted to a {piece:?} in '{uci}', pawns are only promoted to queens");
+                    }
+                    if !self.play(m) {
+                        return Err(anyhow!("engine played an invalid move '{uci}'"));
+                    }
+                    self.recv.push_back(Message::Moved(m));
+                    EngineState::Ready
+                }
+                None => EngineState::Thinking,
+            },
+        };
+        Ok(())
+    }
+    fn send(&mut self, msg: Message) {
+        match msg {
+            Message::Moved(m) if self.to_move != self.side => {
+                if self.play(m) {
+                    self.recv.push_back(Message::Accepted());
+                } else {
+                    self.recv.push_back(Message::Rejected());
+                }
+            }
+            Message::Moved(_) => self.recv.push_back(Message::Rejected()),
+            _ => (),
+        }
+    }
+    fn recv(&mut self) -> Option<Message> {
+        self.recv.pop_front()
+    }
+    fn is_connected(&self) -> bool {
+        matches!(self.state, EngineState::Ready | EngineState::Thinking)
+    }
+    fn shutdown(&mut self) {
+        if !self.shutdown {
+            self.process.quit();
+            self.shutdown = true;
+        }
+    }
+    fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+}
+
+/// Engines left running would outlive the game, so they are stopped when it drops them
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::board::{BoardPos, ChessBoardCell, LongStart};
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Writes a shell script which speaks just enough UCI and answers every `go` with `bestmove`
+    fn fake_engine(name: &str, bestmove: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pawn_hearts_{name}_{}.sh", std::process::id()));
+        let script = format!(
+            "#!/bin/sh\n\
+             while read -r line; do\n\
+             case \"$line\" in\n\
+             uci) echo 'id name fake'; echo uciok ;;\n\
+             isready) echo readyok ;;\n\
+             go*) echo 'info depth 1'; echo 'bestmove {bestmove}' ;;\n\
+             quit) exit 0 ;;\n\
+             esac\n\
+             done\n"
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn config(path: PathBuf) -> UciConfig {
+        UciConfig {
+            path,
+            options: vec![("Hash".to_owned(), "16".to_owned())],
+            movetime: Duration::from_millis(10),
+        }
+    }
+
+    /// Polls the engine until it sends a message or `timeout` runs out
+    fn next_message(engine: &mut UciEngine, timeout: Duration) -> Result<Option<Message>> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            engine.poll()?;
+            if let Some(msg) = engine.recv() {
+                return Ok(Some(msg));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Ok(None)
+    }
+
+    fn mv(uci: &str) -> BoardMove {
+        BoardMove::from_uci(uci).unwrap()
+    }
+
+    #[test]
+    fn missing_engine_is_an_error() {
+        let path = std::env::temp_dir().join("pawn_hearts_no_such_engine");
+        let err = UciEngine::new(config(path), Side::Black).err().unwrap();
+        assert!(format!("{err:#}").contains("could not start engine"));
+    }
+
+    #[test]
+    fn engine_answers_a_move() {
+        let path = fake_engine("answers", "e7e5");
+        let mut engine = UciEngine::new(config(path.clone()), Side::Black).unwrap();
+        engine.send(Message::Moved(mv("e2e4")));
+        let accepted = next_message(&mut engine, Duration::from_secs(5)).unwrap();
+        assert!(matches!(accepted, Some(Message::Accepted())));
+        let reply = next_message(&mut engine, Duration::from_secs(5)).unwrap();
+        assert!(matches!(reply, Some(Message::Moved(m)) if m == mv("e7e5")));
+        assert!(engine.is_connected());
+        engine.shutdown();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn moves_out_of_turn_are_rejected() {
+        let path = fake_engine("out_of_turn", "e2e4");
+        let mut engine = UciEngine::new(config(path.clone()), Side::White).unwrap();
+        engine.send(Message::Moved(mv("e7e5")));
+        assert!(matches!(engine.recv(), Some(Message::Rejected())));
+        engine.shutdown();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_engine_move_is_an_error() {
+        let path = fake_engine("invalid", "e2e5");
+        let mut engine = UciEngine::new(config(path.clone()), Side::White).unwrap();
+        let err = next_message(&mut engine, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("invalid move 'e2e5'"));
+        engine.shutdown();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn underpromotions_are_an_error() {
+        let path = fake_engine("underpromotes", "e7e8n");
+        let mut engine = UciEngine::new(config(path.clone()), Side::White).unwrap();
+        let mut board = ChessBoard::new_empty();
+        let pawn = ChessBoardCell::White(ChessPiece::Pawn(LongStart::After));
+        board.place_at(BoardPos { row: 1, col: 4 }, pawn).unwrap();
+        engine.board = board;
+        let err = next_message(&mut engine, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("promoted to a Knight"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dropped_engines_are_stopped() {
+        let path = fake_engine("dropped", "e2e4");
+        let engine = UciEngine::new(config(path.clone()), Side::Black).unwrap();
+        let pid = engine.process.child.id().to_string();
+        drop(engine);
+        let alive = Command::new("kill").args(["-0", &pid]).status().unwrap();
+        assert!(!alive.success());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! Frames carried by WebSocket, so that browsers can join games. Every binary message holds the
+//! body of exactly one frame, which is what a [`FramedStream`] would send after the length: the
+//! handshake frames first, then the session id followed by an encoded message, sealed once a
+//! passphrase was agreed on. The WebSocket framing keeps the bodies apart, so no length goes in
+//! front of them. Hosts answer upgrade requests on the port they listen on for raw connections.
+//!
+//! [`FramedStream`]: super::stream::FramedStream
+
+use super::secure::{Cipher, OVERHEAD};
+use super::stream::{Frames, MAX_FRAME};
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use std::io::ErrorKind;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use tungstenite::handshake::server::{NoCallback, ServerHandshake};
+use tungstenite::handshake::{HandshakeError, MidHandshake};
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Message, WebSocket};
+
+/// How long a client waits for the host to answer its upgrade request
+const UPGRADE_T: Duration = Duration::from_secs(2);
+/// Where `ws://` addresses without a port point to
+const DEFAULT_WS_PORT: u16 = 80;
+
+pub struct WsStream {
+    ws: WebSocket<TcpStream>,
+    /// the peer closed the connection, reported once the frames before it are handed out
+    closed: bool,
+    /// set once the handshake agreed on a passphrase
+    cipher: Option<Cipher>,
+}
+
+/// The host's side of an upgrade
+pub enum Upgrade {
+    Done(Box<WsStream>),
+    /// the request did not arrive in full yet, or the answer waits for the socket
+    Pending(Box<MidHandshake<ServerHandshake<TcpStream, NoCallback>>>),
+}
+
+impl Upgrade {
+    /// Answers the upgrade request of a connection accepted by the host
+    pub fn start(tcp: TcpStream) -> Result<Self> {
+        Self::from(tungstenite::accept_with_config(tcp, Some(config())))
+    }
+    /// Goes on with the upgrade as far as the socket allows
+    pub fn advance(self) -> Result<Self> {
+        match self {
+            Upgrade::Pending(mid) => Self::from(mid.handshake()),
+            done => Ok(done),
+        }
+    }
+    fn from(
+        res: Result<WebSocket<TcpStream>, HandshakeError<ServerHandshake<TcpStream, NoCallback>>>,
+    ) -> Result<Self> {
+        match res {
+            Ok(ws) => Ok(Upgrade::Done(Box::new(WsStream::new(ws)))),
+            Err(HandshakeError::Interrupted(mid)) => Ok(Upgrade::Pending(Box::new(mid))),
+            Err(HandshakeError::Failure(e)) => Err(anyhow!("WebSocket upgrade failed: {e}")),
+        }
+    }
+}
+
+impl WsStream {
+    fn new(ws: WebSocket<TcpStream>) -> Self {
+        Self {
+            ws,
+            closed: false,
+            cipher: None,
+        }
+    }
+    /// Connects to a host given as `ws://<address>[/<path>]` and asks for the upgrade, which
+    /// blocks until the host answered it
+    pub fn connect(url: &str, timeout: Duration) -> Result<Self> {
+        let tcp = TcpStream::connect_timeout(&resolve(url)?, timeout)?;
+        tcp.set_read_timeout(Some(UPGRADE_T))?;
+        let (ws, _) = tungstenite::client::client_with_config(url, tcp, Some(config()))
+            .map_err(|e| anyhow!("WebSocket upgrade failed: {e}"))?;
+        ws.get_ref().set_read_timeout(None)?;
+        ws.get_ref().set_nonblocking(true)?;
+        Ok(Self::new(ws))
+    }
+}
+
+/// Where the host of a `ws://` address listens
+pub fn resolve(url: &str) -> Result<SocketAddr> {
+    let Some(rest) = url.strip_prefix("ws://") else {
+        bail!("{url} is not a ws:// address");
+    };
+    let authority = rest.split('/').next().unwrap_or(rest);
+    authority
+        .to_socket_addrs()
+        .or_else(|_| (authority, DEFAULT_WS_PORT).to_socket_addrs())?
+        .next()
+        .ok_or(anyhow!("{authority} does not resolve"))
+}
+
+/// Messages longer than the longest sealed frame are refused
+fn config() -> WebSocketConfig {
+    WebSocketConfig::default()
+        .max_message_size(Some(MAX_FRAME + OVERHEAD))
+        .max_frame_size(Some(MAX_FRAME + OVERHEAD))
+}
+
+/// Socket errors are passed on as such, so that they can be told apart like those of raw
+/// connections
+fn unwrap_io(e: tungstenite::Error) -> anyhow::Error {
+    match e {
+        tungstenite::Error::Io(e) => e.into(),
+        e => e.into(),
+    }
+}
+
+fn would_block(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e) if e.kind() == ErrorKind::WouldBlock)
+}
+
+impl Frames for WsStream {
+    fn recv_frames(&mut self) -> Result<Vec<Bytes>> {
+        let mut frames = vec![];
+        while !self.closed {
+            match self.ws.read() {
+                Ok(Message::Binary(frame)) => frames.push(match self.cipher.as_mut() {
+                    Some(cipher) => cipher.open(&frame)?.into(),
+                    None => frame,
+                }),
+                Ok(Message::Text(_)) => bail!("WebSocket: text messages are not understood"),
+                // pings are answered on their own, the close is confirmed by the next read
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => (),
+                Ok(Message::Close(_)) => (),
+                Err(e) if would_block(&e) => break,
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    self.closed = true;
+                }
+                Err(e) => return Err(unwrap_io(e)),
+            }
+        }
+        if self.closed && frames.is_empty() {
+            bail!("connection closed");
+        }
+        Ok(frames)
+    }
+    fn send_frame(&mut self, body: &[u8]) -> Result<()> {
+        if body.len() > MAX_FRAME {
+            bail!("frame of {} bytes is too long", body.len());
+        }
+        let body = match self.cipher.as_mut() {
+            Some(cipher) => cipher.seal(body).into(),
+            None => Bytes::copy_from_slice(body),
+        };
+        match self.ws.write(Message::Binary(body)) {
+            // kept by the socket's buffer until the next flush
+            Err(e) if would_block(&e) => (),
+            res => res.map_err(unwrap_io)?,
+        }
+        self.flush()
+    }
+    fn flush(&mut self) -> Result<()> {
+        match self.ws.flush() {
+            Err(e) if would_block(&e) => Ok(()),
+            res => res.map_err(unwrap_io),
+        }
+    }
+    fn set_cipher(&mut self, cipher: Cipher) {
+        self.cipher = Some(cipher);
+    }
+    fn open(&mut self, frame: &[u8]) -> Result<Bytes> {
+        match self.cipher.as_mut() {
+            Some(cipher) => Ok(cipher.open(frame)?.into()),
+            None => Ok(Bytes::copy_from_slice(frame)),
+        }
+    }
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+    /// Partial messages are kept by the WebSocket, never by the stream
+    fn buffered(&self) -> &[u8] {
+        &[]
+    }
+    fn socket(&self) -> &TcpStream {
+        self.ws.get_ref()
+    }
+    /// Browsers are told with a close message, so that they see a clean end
+    fn hang_up(&mut self) {
+        let _ = self.ws.close(None);
+        let _ = self.ws.flush();
+        let _ = self.ws.get_ref().shutdown(Shutdown::Both);
+    }
+}
@@ -0,0 +1,224 @@
+//! The rules of a network game which do not depend on how it is shown. The window and the
+//! terminal client keep the moves and the position themselves, these functions tell them what
+//! follows from them and what to send to the other end.
+
+use crate::board::pgn::GameResult;
+use crate::board::{BoardMove, ChessBoard, ChessBoardCell, Side};
+use crate::clock::ChessClock;
+use crate::engine::book;
+use crate::network::{self, Message};
+
+/// How a game ended for one of its ends, spectators see it from white's side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Won,
+    Lost,
+    Drawn,
+}
+
+impl Outcome {
+    pub fn of(result: GameResult, side: Side) -> Self {
+        if result == GameResult::won_by(side) {
+            Outcome::Won
+        } else if result == GameResult::won_by(side.opposite()) {
+            Outcome::Lost
+        } else {
+            Outcome::Drawn
+        }
+    }
+    /// The result of the game which ended like this for `side`
+    pub fn result(self, side: Side) -> GameResult {
+        match self {
+            Outcome::Won => GameResult::won_by(side),
+            Outcome::Lost => GameResult::won_by(side.opposite()),
+            Outcome::Drawn => GameResult::Draw,
+        }
+    }
+}
+
+/// How many of `plies` moves are left once the last one of `side` is taken back, so that it is
+/// their turn again
+pub fn takeback_target(plies: usize, side: Side) -> Option<usize> {
+    let undone = if Side::to_move_after(plies) == side {
+        2
+    } else {
+        1
+    };
+    plies.checked_sub(undone)
+}
+
+/// What the host sends once it went back to the first `plies` moves, so that the client does the
+/// same and has the times of then. The client sends nothing.
+pub fn take_back(is_host: bool, plies: usize, clock: Option<&ChessClock>) -> Vec<Message> {
+    if !is_host {
+        return Vec::new();
+    }
+    let mut msgs = vec![Message::Takeback(plies as u16)];
+    msgs.extend(clock.map(network::clock_message));
+    msgs
+}
+
+/// Ends a game in which no king was taken, the host tells the client and spectators how
+pub fn conclude(is_host: bool, result: GameResult) -> Option<Message> {
+    is_host.then_some(Message::Ended(result))
+}
+
+/// Whether the host can accept the client's move `m` in the position after `plies` moves, which
+/// has to be a move of the side to move that the board allows
+pub fn accepts(board: &ChessBoard, plies: usize, m: BoardMove) -> bool {
+    let mover = board.at(m.from()).and_then(ChessBoardCell::side);
+    mover == Some(Side::to_move_after(plies)) && board.clone().move_piece(m).is_some()
+}
+
+/// What the host tells the client after every final move, so that a client which went astray
+/// notices
+pub fn position(board: &ChessBoard, plies: usize) -> Message {
+    let key = book::polyglot_key(board, Side::to_move_after(plies));
+    Message::Position(plies as u16, key)
+}
+
+/// Asks the host for the whole game when the position after the client's `own_plies` moves is not
+/// the one the host had after `plies`
+pub fn check_position(
+    board: &ChessBoard,
+    own_plies: usize,
+    plies: usize,
+    key: u64,
+) -> Option<Message> {
+    let own = book::polyglot_key(board, Side::to_move_after(own_plies));
+    if plies == own_plies && key == own {
+        return None;
+    }
+    eprintln!(
+        "the position after {own_plies} moves is not the host's after {plies}, asking for the game"
+    );
+    Some(Message::SyncRequest())
+}
+
+/// The host stops the clock of the side which just moved, the times are sent to the client
+pub fn press_clock(clock: &mut ChessClock) -> Message {
+    clock.press();
+    network::clock_message(clock)
+}
+
+/// How the game ends once `to_move` is out of time, which is a draw when the other side has
+/// nothing left to mate with. `None` while there is time left.
+pub fn flag_fell(clock: &ChessClock, board: &ChessBoard, to_move: Side) -> Option<GameResult> {
+    if !clock.left(to_move).is_zero() {
+        return None;
+    }
+    let winner = to_move.opposite();
+    Some(if board.can_mate(winner) {
+        GameResult::won_by(winner)
+    } else {
+        GameResult::Draw
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{BoardPos, ChessPiece};
+    use crate::clock::TimeControl;
+    use std::time::Duration;
+
+    #[test]
+    fn takebacks_go_back_to_the_asking_side() {
+        assert_eq!(takeback_target(0, Side::White), None);
+        // black to move, white's move is taken back alone
+        assert_eq!(takeback_target(1, Side::White), Some(0));
+        assert_eq!(takeback_target(1, Side::Black), None);
+        // white to move, black's reply goes along with white's move
+        assert_eq!(takeback_target(2, Side::White), Some(0));
+        assert_eq!(takeback_target(2, Side::Black), Some(1));
+    }
+
+    #[test]
+    fn only_the_host_sends_takebacks_and_results() {
+        let clock = ChessClock::new(TimeControl::from_secs(300, 0, 0), Side::White);
+        let msgs = take_back(true, 3, Some(&clock));
+        assert!(matches!(
+            msgs.as_slice(),
+            [Message::Takeback(3), Message::Clock(..)]
+        ));
+        assert!(matches!(
+            take_back(true, 3, None).as_slice(),
+            [Message::Takeback(3)]
+        ));
+        assert!(take_back(false, 3, Some(&clock)).is_empty());
+        assert!(matches!(
+            conclude(true, GameResult::Draw),
+            Some(Message::Ended(GameResult::Draw))
+        ));
+        assert!(conclude(false, GameResult::Draw).is_none());
+    }
+
+    #[test]
+    fn hosts_accept_legal_moves_of_the_side_to_move() {
+        let board = ChessBoard::new_full();
+        let pawn =
+            |from, to| BoardMove::new(BoardPos { row: from, col: 3 }, BoardPos { row: to, col: 3 });
+        assert!(accepts(&board, 0, pawn(6, 4)));
+        // not white's turn after a move
+        assert!(!accepts(&board, 1, pawn(6, 4)));
+        assert!(accepts(&board, 1, pawn(1, 3)));
+        assert!(!accepts(&board, 0, pawn(6, 3)));
+        assert!(!accepts(&board, 0, pawn(4, 3)));
+    }
+
+    #[test]
+    fn clients_ask_for_the_game_when_the_position_differs() {
+        let mut board = ChessBoard::new_full();
+        let Message::Position(plies, key) = position(&board, 0) else {
+            panic!("not a position");
+        };
+        assert_eq!(plies, 0);
+        assert!(check_position(&board, 0, 0, key).is_none());
+        assert!(matches!(
+            check_position(&board, 0, 1, key),
+            Some(Message::SyncRequest())
+        ));
+        let pawn = BoardMove::new(BoardPos { row: 6, col: 3 }, BoardPos { row: 4, col: 3 });
+        board.move_piece(pawn).unwrap();
+        assert!(matches!(
+            check_position(&board, 0, 0, key),
+            Some(Message::SyncRequest())
+        ));
+    }
+
+    #[test]
+    fn flags_fall_to_a_win_unless_there_is_no_mate() {
+        let mut clock = ChessClock::new(TimeControl::from_secs(300, 0, 0), Side::White);
+        let full = ChessBoard::new_full();
+        assert_eq!(flag_fell(&clock, &full, Side::White), None);
+        clock.set(Duration::ZERO, Duration::from_secs(1), Side::White);
+        assert_eq!(
+            flag_fell(&clock, &full, Side::White),
+            Some(GameResult::BlackWon)
+        );
+        assert_eq!(flag_fell(&clock, &full, Side::Black), None);
+        // a lone king cannot win on time
+        let mut kings = ChessBoard::new_empty();
+        let white = ChessBoardCell::White(ChessPiece::King(false));
+        let black = ChessBoardCell::Black(ChessPiece::King(false));
+        kings.place_at(BoardPos { row: 7, col: 4 }, white).unwrap();
+        kings.place_at(BoardPos { row: 0, col: 4 }, black).unwrap();
+        assert_eq!(
+            flag_fell(&clock, &kings, Side::White),
+            Some(GameResult::Draw)
+        );
+    }
+
+    #[test]
+    fn outcomes_match_results() {
+        for side in [Side::White, Side::Black] {
+            for outcome in [Outcome::Won, Outcome::Lost, Outcome::Drawn] {
+                assert_eq!(Outcome::of(outcome.result(side), side), outcome);
+            }
+        }
+        assert_eq!(
+            Outcome::of(GameResult::WhiteWon, Side::Black),
+            Outcome::Lost
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Tactics puzzles read from CSV files laid out like the Lichess puzzle database:
+//! `PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,...`
+//!
+//! The FEN is the position before the opponent's move, which is the first of the moves. The
+//! player has to find every other move.
+
+use crate::board::{BoardMove, ChessBoard, ChessPiece, Side};
+use anyhow::{anyhow, bail, Result};
+use std::path::{Path, PathBuf};
+
+/// Where the player's results are kept, in the working directory
+pub const STATS_FILE: &str = "puzzle_stats.txt";
+const DEFAULT_RATING: i32 = 1500;
+/// How far a single puzzle can move the rating
+const K_FACTOR: f32 = 32.;
+/// Puzzles are picked from within this distance of the player's rating when there are any
+const RATING_WINDOW: i32 = 200;
+
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub id: String,
+    /// position before the opponent's first move
+    pub board: ChessBoard,
+    /// the side to move in the position, the opponent of the player
+    pub side: Side,
+    pub moves: Vec<BoardMove>,
+    /// the piece each of the moves promotes to
+    pub promotions: Vec<Option<ChessPiece>>,
+    pub rating: i32,
+    pub themes: Vec<String>,
+}
+
+impl Puzzle {
+    pub fn player(&self) -> Side {
+        self.side.opposite()
+    }
+}
+
+/// Column of every field the game uses
+struct Columns {
+    id: usize,
+    fen: usize,
+    moves: usize,
+    rating: usize,
+    themes: Option<usize>,
+}
+
+impl Columns {
+    const LICHESS: Self = Self {
+        id: 0,
+        fen: 1,
+        moves: 2,
+        rating: 3,
+        themes: Some(7),
+    };
+    fn from_header(header: &[&str]) -> Result<Self> {
+        let find = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+        Ok(Self {
+            id: find("PuzzleId").unwrap_or(usize::MAX),
+            fen: find("FEN").ok_or(anyhow!("no FEN column"))?,
+            moves: find("Moves").ok_or(anyhow!("no Moves column"))?,
+            rating: find("Rating").ok_or(anyhow!("no Rating column"))?,
+            themes: find("Themes"),
+        })
+    }
+}
+
+/// Reads every valid puzzle of the file, rows which do not parse or whose moves are not valid
+/// on the board are skipped
+pub fn load(path: &Path) -> Result<Vec<Puzzle>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty()).peekable();
+    let columns = match lines.peek() {
+        Some(first) if first.contains("FEN") => {
+            let header = split_row(first);
+            lines.next();
+            Columns::from_header(&header)?
+        }
+        _ => Columns::LICHESS,
+    };
+    let mut puzzles = vec![];
+    let mut skipped = 0;
+    for (n, line) in lines.enumerate() {
+        match parse_row(&split_row(line), &columns, n) {
+            Ok(p) => puzzles.push(p),
+            Err(e) => {
+                skipped += 1;
+                eprintln!("skipping puzzle on row {}: {e}", n + 1);
+            }
+        }
+    }
+    if puzzles.is_empty() {
+        bail!("no valid puzzles in {} ({skipped} skipped)", path.display());
+    }
+    Ok(puzzles)
+}
+
+fn split_row(line: &str) -> Vec<&str> {
+    line.split(',')
+        .map(|f| f.trim().trim_matches('"'))
+        .collect()
+}
+
+fn parse_row(fields: &[&str], columns: &Columns, n: usize) -> Result<Puzzle> {
+    let field = |at: usize| {
+        fields
+            .get(at)
+            .copied()
+            .ok_or(anyhow!("missing column {at}"))
+    };
+    let (board, side) = ChessBoard::from_fen(field(columns.fen)?)?;
+    let ucis = field(columns.moves)?.split_whitespace().collect::<Vec<_>>();
+    // the opponent's move and at least one for the player
+    if ucis.len() < 2 {
+        bail!("not enough moves");
+    }
+    let (mut moves, mut promotions) = (vec![], vec![]);
+    let mut scratch = board.clone();
+    for uci in ucis {
+        let m = BoardMove::from_uci(uci).ok_or(anyhow!("unreadable move '{uci}'"))?;
+        let promotion = BoardMove::promotion_from_uci(uci);
+        // the board promotes to queens only, so other promotions could be neither played nor
+        // found by the player
+        match (promotion, m.promotion(&scratch)) {
+            (given, played) if given == played => (),
+            (_, None) => bail!("move {uci} is not a promotion"),
+            (None, Some(_)) => bail!("move {uci} does not say what it promotes to"),
+            (Some(piece), Some(_)) => {
+                bail!("move {uci} promotes to a {piece:?}, pawns are only promoted to queens")
+            }
+        }
+        scratch.move_piece(m).ok_or(anyhow!("invalid move {uci}"))?;
+        moves.push(m);
+        promotions.push(promotion);
+    }
+    Ok(Puzzle {
+        id: fields
+            .get(columns.id)
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| (n + 1).to_string()),
+        board,
+        side,
+        moves,
+        promotions,
+        rating: field(columns.rating)?.parse()?,
+        themes: columns
+            .themes
+            .and_then(|at| fields.get(at))
+            .map(|t| t.split_whitespace().map(str::to_owned).collect())
+            .unwrap_or_default(),
+    })
+}
+
+/// The player's results, kept in a small `name=value` file between runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PuzzleStats {
+    pub rating: i32,
+    pub streak: u32,
+    pub best_streak: u32,
+    pub solved: u32,
+    pub failed: u32,
+}
+
+impl Default for PuzzleStats {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            streak: 0,
+            best_streak: 0,
+            solved: 0,
+            failed: 0,
+        }
+    }
+}
+
+impl PuzzleStats {
+    /// Starts over when the file is missing or broken
+    pub fn load(path: &Path) -> Self {
+        let mut stats = Self::default();
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return stats;
+        };
+        for (name, value) in text.lines().filter_map(|l| l.split_once('=')) {
+            let value = value.trim();
+            match name.trim() {
+                "rating" => stats.rating = value.parse().unwrap_or(DEFAULT_RATING),
+                "streak" => stats.streak = value.parse().unwrap_or_default(),
+                "best_streak" => stats.best_streak = value.parse().unwrap_or_default(),
+                "solved" => stats.solved = value.parse().unwrap_or_default(),
+                "failed" => stats.failed = value.parse().unwrap_or_default(),
+                _ => (),
+            }
+        }
+        stats
+    }
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = format!(
+            "rating={}\nstreak={}\nbest_streak={}\nsolved={}\nfailed={}\n",
+            self.rating, self.streak, self.best_streak, self.solved, self.failed
+        );
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+    /// Elo update treating the puzzle as an opponent of its rating
+    fn record(&mut self, puzzle_rating: i32, solved: bool) {
+        let expected = 1. / (1. + 10f32.powf((puzzle_rating - self.rating) as f32 / 400.));
+        let score = if solved { 1. } else { 0. };
+        self.rating += (K_FACTOR * (score - expected)).round() as i32;
+        if solved {
+            self.solved += 1;
+            self.streak += 1;
+            self.best_streak = self.best_streak.max(self.streak);
+        } else {
+            self.failed += 1;
+            self.streak = 0;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attempt {
+    /// the right move, the opponent replies next
+    Correct,
+    Solved,
+    /// carries the move which was expected
+    Wrong(BoardMove),
+}
+
+/// Hands out puzzles and follows the player through their solutions
+pub struct Trainer {
+    puzzles: Vec<Puzzle>,
+    current: usize,
+    /// index of the next solution move
+    ply: usize,
+    pub stats: PuzzleStats,
+    stats_path: PathBuf,
+    /// whether the current puzzle is over and how it went
+    pub outcome: Option<Attempt>,
+}
+
+impl Trainer {
+    pub fn new(puzzles: Vec<Puzzle>, stats_path: PathBuf) -> Self {
+        Self {
+            puzzles,
+            current: 0,
+            ply: 0,
+            stats: PuzzleStats::load(&stats_path),
+            stats_path,
+            outcome: None,
+        }
+    }
+    pub fn puzzle(&self) -> &Puzzle {
+        &self.puzzles[self.current]
+    }
+    /// Picks a random puzzle close to the player's rating
+    /// # Returns
+    /// the position the puzzle starts in
+    pub fn next_puzzle(&mut self) -> ChessBoard {
+        let near = (0..self.puzzles.len())
+            .filter(|&i| i != self.current || self.puzzles.len() == 1)
+            .filter(|&i| (self.puzzles[i].rating - self.stats.rating).abs() <= RATING_WINDOW)
+            .collect::<Vec<_>>();
+        self.current = if near.is_empty() {
+            rand::random_range(0..self.puzzles.len())
+        } else {
+            near[rand::random_range(0..near.len())]
+        };
+        self.ply = 0;
+        self.outcome = None;
+        self.puzzle().board.clone()
+    }
+    /// The opponent's move if it is their turn, taking it as played
+    pub fn reply(&mut self) -> Option<BoardMove> {
+        if self.outcome.is_some() || !self.ply.is_multiple_of(2) {
+            return None;
+        }
+        let m = self.puzzle().moves.get(self.ply).copied();
+        self.ply += 1;
+        m
+    }
+    /// Checks the player's move and the piece it promotes to against the solution. A wrong move
+    /// ends the puzzle.
+    pub fn attempt(&mut self, m: BoardMove, promotion: Option<ChessPiece>) -> Attempt {
+        let Some(&expected) = self.puzzle().moves.get(self.ply) else {
+            return Attempt::Solved;
+        };
+        let ret = if m != expected || promotion != self.puzzle().promotions[self.ply] {
+            Attempt::Wrong(expected)
+        } else if self.ply + 1 == self.puzzle().moves.len() {
+            Attempt::Solved
+        } else {
+            Attempt::Correct
+        };
+        self.ply += 1;
+        if ret != Attempt::Correct {
+            self.finish(ret);
+        }
+        ret
+    }
+    fn finish(&mut self, outcome: Attempt) {
+        self.outcome = Some(outcome);
+        let rating = self.puzzle().rating;
+        self.stats.record(rating, outcome == Attempt::Solved);
+        if let Err(e) = self.stats.save(&self.stats_path) {
+            eprintln!("could not save puzzle stats: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// White to move plays a7a8 after black's king steps aside
+    const PROMOTION: &str = "p1,4k3/P7/8/8/8/8/8/4K3 b - - 0 1,e8f7 a7a8q,1500,0,0,0,promotion";
+
+    fn parse(row: &str) -> Result<Puzzle> {
+        parse_row(&split_row(row), &Columns::LICHESS, 0)
+    }
+
+    /// Tries `promotion` with the solution's move and cleans up the stats it saved
+    fn attempt_promotion(promotion: Option<ChessPiece>) -> Attempt {
+        let puzzle = parse(PROMOTION).unwrap();
+        let solution = puzzle.moves[1];
+        let stats = std::env::temp_dir().join(format!(
+            "pawn_hearts_puzzle_stats_{promotion:?}_{}.txt",
+            std::process::id()
+        ));
+        let mut trainer = Trainer::new(vec![puzzle], stats.clone());
+        trainer.next_puzzle();
+        trainer.reply();
+        let ret = trainer.attempt(solution, promotion);
+        let _ = std::fs::remove_file(stats);
+        ret
+    }
+
+    #[test]
+    fn promotions_are_read() {
+        let puzzle = parse(PROMOTION).unwrap();
+        assert_eq!(puzzle.promotions, vec![None, Some(ChessPiece::Queen)]);
+    }
+
+    #[test]
+    fn rows_with_unplayable_promotions_are_skipped() {
+        assert!(parse(&PROMOTION.replace("a7a8q", "a7a8n")).is_err());
+        assert!(parse(&PROMOTION.replace("a7a8q", "a7a8")).is_err());
+        assert!(parse(&PROMOTION.replace("e8f7", "e8f7q")).is_err());
+    }
+
+    #[test]
+    fn promotion_is_part_of_the_solution() {
+        assert_eq!(attempt_promotion(Some(ChessPiece::Queen)), Attempt::Solved);
+        assert!(matches!(attempt_promotion(None), Attempt::Wrong(_)));
+        assert!(matches!(
+            attempt_promotion(Some(ChessPiece::Knight)),
+            Attempt::Wrong(_)
+        ));
+    }
+}
@@ -0,0 +1,364 @@
+//! Headless matches between two players, to measure whether a change made the engine stronger
+
+pub mod openings;
+pub mod player;
+
+use self::openings::Opening;
+use self::player::{Player, PlayerConfig};
+use crate::board::pgn::{GameResult, PgnGame, PgnMove};
+use crate::board::{BoardMove, ChessBoard, ChessBoardCell, ChessPiece, Side};
+use crate::clock::{Clocks, TimeControl};
+use crate::engine::book::Book;
+use crate::engine::tablebase::Tablebase;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Games which go on for this many plies without a result are drawn
+const MAX_PLIES: usize = 400;
+/// Two sided 95% confidence
+const Z_95: f64 = 1.96;
+
+/// What a player gets to see when it is their turn
+pub struct Position {
+    /// FEN the game started from, the standard position when `None`
+    pub start: Option<String>,
+    /// every move so far in UCI notation
+    pub uci_moves: Vec<String>,
+    pub board: ChessBoard,
+    pub side: Side,
+}
+
+#[derive(Clone)]
+pub struct MatchConfig {
+    pub players: [PlayerConfig; 2],
+    pub games: usize,
+    pub time_control: Option<TimeControl>,
+    /// when empty every game starts from the standard position
+    pub openings: Vec<Opening>,
+    /// where the games get written to
+    pub pgn: Option<PathBuf>,
+    pub book: Option<Arc<Book>>,
+    pub tablebase: Option<Arc<Tablebase>>,
+}
+
+/// Results of the first player against the second one
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Score {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+    /// Points per game between 0 and 1
+    pub fn ratio(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.) / self.games().max(1) as f64
+    }
+    /// Elo difference implied by the score and the half width of its 95% confidence interval,
+    /// either can be infinite when nothing but wins or losses were played
+    pub fn elo(&self) -> (f64, f64) {
+        let n = self.games().max(1) as f64;
+        let s = self.ratio();
+        let deviation = |points: f64, count: u32| count as f64 * (points - s).powi(2);
+        let variance =
+            (deviation(1., self.wins) + deviation(0., self.losses) + deviation(0.5, self.draws))
+                / n;
+        let error = Z_95 * (variance / n).sqrt();
+        let margin = (elo_difference(s + error) - elo_difference(s - error)) / 2.;
+        (elo_difference(s), margin)
+    }
+}
+
+fn elo_difference(score: f64) -> f64 {
+    if score <= 0. {
+        f64::NEG_INFINITY
+    } else if score >= 1. {
+        f64::INFINITY
+    } else {
+        400. * (score / (1. - score)).log10()
+    }
+}
+
+/// How a single game ended, with the reason written the way PGN's `Termination` comments are
+struct Outcome {
+    result: GameResult,
+    reason: String,
+}
+
+impl Outcome {
+    fn won_by(side: Side, reason: impl Into<String>) -> Self {
+        Self {
+            result: GameResult::won_by(side),
+            reason: reason.into(),
+        }
+    }
+    fn draw(reason: impl Into<String>) -> Self {
+        Self {
+            result: GameResult::Draw,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Plays the whole match, printing the standings after every game
+pub fn run(config: MatchConfig) -> Result<()> {
+    let mut players = [
+        config.players[0].start(config.book.clone(), config.tablebase.clone())?,
+        config.players[1].start(config.book.clone(), config.tablebase.clone())?,
+    ];
+    let names = [players[0].name().to_owned(), players[1].name().to_owned()];
+    let mut pgn = match &config.pgn {
+        Some(path) => Some(File::create(path)?),
+        None => None,
+    };
+    let openings = if config.openings.is_empty() {
+        vec![Opening::default()]
+    } else {
+        config.openings.clone()
+    };
+    let mut score = Score::default();
+    for round in 0..config.games {
+        // every opening is played twice so that both players get each side of it
+        let opening = &openings[(round / 2) % openings.len()];
+        let first_is_white = round.is_multiple_of(2);
+        let (white, black) = players.split_at_mut(1);
+        let (white, black) = if first_is_white {
+            (&mut white[0], &mut black[0])
+        } else {
+            (&mut black[0], &mut white[0])
+        };
+        let (game, outcome) =
+            play_game(white.as_mut(), black.as_mut(), opening, config.time_control)?;
+        let first_side = if first_is_white {
+            Side::White
+        } else {
+            Side::Black
+        };
+        match outcome.result {
+            GameResult::Draw => score.draws += 1,
+            r if r == GameResult::won_by(first_side) => score.wins += 1,
+            _ => score.losses += 1,
+        }
+        let (white_name, black_name) = if first_is_white {
+            (&names[0], &names[1])
+        } else {
+            (&names[1], &names[0])
+        };
+        println!(
+            "Finished game {} ({white_name} vs {black_name}): {} {{{}}}",
+            round + 1,
+            outcome.result.as_str(),
+            outcome.reason
+        );
+        println!(
+            "Score of {} vs {}: {} - {} - {} [{:.3}] {}",
+            names[0],
+            names[1],
+            score.wins,
+            score.losses,
+            score.draws,
+            score.ratio(),
+            score.games()
+        );
+        if let Some(file) = pgn.as_mut() {
+            let mut game = game
+                .tag("Event", "Pawn Hearts match")
+                .tag("Round", (round + 1).to_string())
+                .tag("White", white_name.clone())
+                .tag("Black", black_name.clone())
+                .tag("Termination", outcome.reason);
+            if let Some(tc) = config.time_control {
+                game = game.tag("TimeControl", tc.to_string());
+            }
+            writeln!(file, "{}", game.to_pgn()?)?;
+        }
+    }
+    let (elo, margin) = score.elo();
+    println!("Elo difference: {elo:.1} +/- {margin:.1}");
+    for player in &mut players {
+        player.quit();
+    }
+    Ok(())
+}
+
+fn play_game<'a>(
+    white: &'a mut dyn Player,
+    black: &'a mut dyn Player,
+    opening: &Opening,
+    time_control: Option<TimeControl>,
+) -> Result<(PgnGame, Outcome)> {
+    let (board, side) = match &opening.start {
+        Some(fen) => ChessBoard::from_fen(fen)?,
+        None => (ChessBoard::new_full(), Side::White),
+    };
+    let mut position = Position {
+        start: opening.start.clone(),
+        uci_moves: vec![],
+        board,
+        side,
+    };
+    let mut moves: Vec<PgnMove> = vec![];
+    for m in &opening.moves {
+        play(&mut position, *m).ok_or(anyhow!("invalid opening move"))?;
+        moves.push((*m).into());
+    }
+    let mut clocks = time_control.map(Clocks::new);
+    let mut seen = HashMap::new();
+    for player in [&mut *white, &mut *black] {
+        if let Err(e) = player.new_game() {
+            eprintln!("{}: {e}", player.name());
+        }
+    }
+    let outcome = loop {
+        let side = position.side;
+        if moves.len() >= MAX_PLIES {
+            break Outcome::draw("move limit");
+        }
+        if !position.board.can_mate(Side::White) && !position.board.can_mate(Side::Black) {
+            break Outcome::draw("insufficient material");
+        }
+        // repetitions are told apart by everything but the move counters
+        let key = position.board.to_fen(side);
+        let key = key.split_whitespace().take(4).collect::<Vec<_>>().join(" ");
+        let count = seen.entry(key).or_insert(0);
+        *count += 1;
+        if *count >= 3 {
+            break Outcome::draw("3-fold repetition");
+        }
+
+        let player = match side {
+            Side::White => &mut *white,
+            Side::Black => &mut *black,
+        };
+        let started = Instant::now();
+        let res = player.play(&position, clocks.as_ref());
+        let elapsed = started.elapsed();
+        if let Some(clocks) = clocks.as_mut() {
+            if !clocks.spend(side, elapsed) {
+                break Outcome::won_by(side.opposite(), "time forfeit");
+            }
+        }
+        let m = match res {
+            Ok(Some(m)) => m,
+            // nothing to play, which is only a loss if the king is about to be taken
+            Ok(None) if position.board.attacks_king(side.opposite()) => {
+                break Outcome::won_by(side.opposite(), "checkmate");
+            }
+            Ok(None) => break Outcome::draw("stalemate"),
+            Err(e) => {
+                eprintln!("{}: {e}", player.name());
+                break Outcome::won_by(side.opposite(), "forfeit");
+            }
+        };
+        let before = position.board.clone();
+        let Some(took_king) = play(&mut position, m) else {
+            eprintln!(
+                "{} played the invalid move {}",
+                player.name(),
+                m.to_uci(&before)
+            );
+            break Outcome::won_by(side.opposite(), "illegal move");
+        };
+        moves.push(m.into());
+        if took_king {
+            break Outcome::won_by(side, "king captured");
+        }
+        // the opponent could take the king next, which standard chess does not allow
+        if position.board.attacks_king(side.opposite()) {
+            eprintln!(
+                "{} left its king in check with {}",
+                player.name(),
+                m.to_uci(&before)
+            );
+            break Outcome::won_by(side.opposite(), "king left in check");
+        }
+    };
+    if let Some(last) = moves.last_mut() {
+        last.comment = Some(outcome.reason.clone());
+    }
+    let mut game = PgnGame::new(moves, outcome.result);
+    game.start = opening.start.clone();
+    Ok((game, outcome))
+}
+
+/// Plays `m` for the side to move
+/// # Returns
+/// whether a king was taken, or `None` if the move is not valid
+fn play(position: &mut Position, m: BoardMove) -> Option<bool> {
+    let piece_side = position.board.at(m.from()).and_then(|c| c.side());
+    if piece_side != Some(position.side) {
+        return None;
+    }
+    let uci = m.to_uci(&position.board);
+    let res = position.board.move_piece(m)?;
+    position.uci_moves.push(uci);
+    position.side = position.side.opposite();
+    Some(res.pieces_deleted.iter().any(|c| {
+        matches!(
+            c,
+            ChessBoardCell::White(ChessPiece::King(_)) | ChessBoardCell::Black(ChessPiece::King(_))
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the given moves in order and nothing once they run out
+    struct Scripted(Vec<&'static str>);
+
+    impl Player for Scripted {
+        fn name(&self) -> &str {
+            "scripted"
+        }
+        fn new_game(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn play(&mut self, _: &Position, _: Option<&Clocks>) -> Result<Option<BoardMove>> {
+            if self.0.is_empty() {
+                return Ok(None);
+            }
+            Ok(BoardMove::from_uci(self.0.remove(0)))
+        }
+    }
+
+    fn outcome(fen: &str, white: Vec<&'static str>, black: Vec<&'static str>) -> Outcome {
+        let opening = Opening {
+            start: Some(fen.to_owned()),
+            moves: vec![],
+        };
+        let (mut white, mut black) = (Scripted(white), Scripted(black));
+        play_game(&mut white, &mut black, &opening, None).unwrap().1
+    }
+
+    #[test]
+    fn leaving_the_king_in_check_loses() {
+        // the rook on e2 is pinned
+        let outcome = outcome("4r2k/8/8/8/8/8/4R3/4K3 w - - 0 1", vec!["e2a2"], vec![]);
+        assert_eq!(outcome.result, GameResult::BlackWon);
+        assert_eq!(outcome.reason, "king left in check");
+    }
+
+    #[test]
+    fn no_move_in_check_is_checkmate() {
+        let outcome = outcome("k7/8/8/8/8/8/5PPP/r5K1 w - - 0 1", vec![], vec![]);
+        assert_eq!(outcome.result, GameResult::BlackWon);
+        assert_eq!(outcome.reason, "checkmate");
+    }
+
+    #[test]
+    fn invalid_moves_lose() {
+        let outcome = outcome("k7/8/8/8/8/8/5PPP/6K1 w - - 0 1", vec!["f2f5"], vec![]);
+        assert_eq!(outcome.result, GameResult::BlackWon);
+        assert_eq!(outcome.reason, "illegal move");
+    }
+}
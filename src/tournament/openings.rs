@@ -0,0 +1,60 @@
+//! Opening suites which games of a match start from
+
+use crate::board::pgn;
+use crate::board::{BoardMove, ChessBoard};
+use anyhow::{anyhow, bail, Result};
+use std::path::Path;
+
+/// A starting position along with the moves which lead to where the players take over
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Opening {
+    /// FEN of the first position, the standard one when `None`
+    pub start: Option<String>,
+    pub moves: Vec<BoardMove>,
+}
+
+/// Reads an EPD file with one position per line, or a PGN file whose games are played up to
+/// their last move. The format is told apart by the extension.
+pub fn load(path: &Path) -> Result<Vec<Opening>> {
+    let text = std::fs::read_to_string(path)?;
+    let epd = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("epd"));
+    let openings = if epd {
+        read_epd(&text)?
+    } else {
+        read_pgn(&text)?
+    };
+    if openings.is_empty() {
+        bail!("no openings in {}", path.display());
+    }
+    Ok(openings)
+}
+
+fn read_epd(text: &str) -> Result<Vec<Opening>> {
+    let mut ret = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let fields = line.split_whitespace().take(4).collect::<Vec<_>>();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue;
+        }
+        // EPD has no move counters, the operations after the fourth field are not needed
+        let fen = format!("{} 0 1", fields.join(" "));
+        ChessBoard::from_fen(&fen).map_err(|e| anyhow!("line {}: {e}", n + 1))?;
+        ret.push(Opening {
+            start: Some(fen),
+            moves: vec![],
+        });
+    }
+    Ok(ret)
+}
+
+fn read_pgn(text: &str) -> Result<Vec<Opening>> {
+    Ok(pgn::read_games(text)?
+        .into_iter()
+        .map(|game| Opening {
+            start: game.start,
+            moves: game.moves.into_iter().map(|m| m.mv).collect(),
+        })
+        .collect())
+}
@@ -0,0 +1,215 @@
+//! The two kinds of contestants, the built-in search and external UCI engines
+
+use super::Position;
+use crate::board::BoardMove;
+use crate::clock::Clocks;
+use crate::engine::book::Book;
+use crate::engine::tablebase::Tablebase;
+use crate::engine::{Level, SearchLimits, MOVES_TO_GO};
+use crate::network::uci::{EngineProcess, UciConfig};
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long an engine gets to start up and to answer `isready`
+const STARTUP_T: Duration = Duration::from_secs(10);
+/// Extra time an engine gets to answer before it is asked to stop
+const LAG_T: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub enum PlayerConfig {
+    BuiltIn {
+        limits: SearchLimits,
+        /// what the player is called in the results
+        name: String,
+    },
+    Uci(UciConfig),
+}
+
+impl PlayerConfig {
+    /// A level or a number of milliseconds per move for the built-in search, anything else is
+    /// taken as the path of an engine
+    pub fn parse(s: &str, movetime: Duration) -> Self {
+        if let Ok(ms) = s.parse::<u64>() {
+            return PlayerConfig::BuiltIn {
+                limits: SearchLimits::movetime(Duration::from_millis(ms)),
+                name: format!("Pawn Hearts {ms}ms"),
+            };
+        }
+        if let Ok(level) = s.parse::<Level>() {
+            return PlayerConfig::BuiltIn {
+                limits: level.limits(),
+                name: format!("Pawn Hearts {}", level.name()),
+            };
+        }
+        PlayerConfig::Uci(UciConfig {
+            path: PathBuf::from(s),
+            options: vec![],
+            movetime,
+        })
+    }
+    pub fn start(
+        &self,
+        book: Option<Arc<Book>>,
+        tablebase: Option<Arc<Tablebase>>,
+    ) -> Result<Box<dyn Player>> {
+        Ok(match self {
+            PlayerConfig::BuiltIn { limits, name } => Box::new(BuiltIn {
+                limits: *limits,
+                name: name.clone(),
+                book,
+                tablebase,
+            }),
+            PlayerConfig::Uci(config) => Box::new(UciPlayer::start(config.clone())?),
+        })
+    }
+}
+
+pub trait Player {
+    fn name(&self) -> &str;
+    fn new_game(&mut self) -> Result<()>;
+    /// Finds a move for the side to move of `position`
+    /// # Returns
+    /// `None` when the player has no move to play
+    fn play(&mut self, position: &Position, clocks: Option<&Clocks>) -> Result<Option<BoardMove>>;
+    fn quit(&mut self) {}
+}
+
+struct BuiltIn {
+    limits: SearchLimits,
+    name: String,
+    book: Option<Arc<Book>>,
+    tablebase: Option<Arc<Tablebase>>,
+}
+
+impl Player for BuiltIn {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn new_game(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn play(&mut self, position: &Position, clocks: Option<&Clocks>) -> Result<Option<BoardMove>> {
+        if let Some(m) = self
+            .book
+            .as_ref()
+            .and_then(|b| b.pick(&position.board, position.side))
+        {
+            return Ok(Some(m));
+        }
+        if let Some((m, _)) = self
+            .tablebase
+            .as_ref()
+            .and_then(|t| t.best_move(&position.board, position.side))
+        {
+            return Ok(Some(m));
+        }
+        let mut limits = self.limits;
+        if let Some(clocks) = clocks {
+            let budget = crate::engine::time_budget(
+                clocks.left(position.side),
+                clocks.increment,
+                MOVES_TO_GO,
+            );
+            limits.movetime = Some(limits.movetime.map_or(budget, |t| t.min(budget)));
+        }
+        Ok(crate::engine::search(&position.board, position.side, &limits).map(|r| r.best))
+    }
+}
+
+struct UciPlayer {
+    config: UciConfig,
+    process: EngineProcess,
+    name: String,
+}
+
+impl UciPlayer {
+    fn start(config: UciConfig) -> Result<Self> {
+        let mut process = EngineProcess::spawn(&config.path)?;
+        process.write("uci")?;
+        let lines = process
+            .wait_for_timeout("uciok", STARTUP_T)?
+            .ok_or(anyhow!("{:?} did not answer uci", config.path))?;
+        let name = lines
+            .iter()
+            .find_map(|l| l.trim().strip_prefix("id name "))
+            .map(str::to_owned)
+            .or_else(|| {
+                config
+                    .path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "engine".to_owned());
+        for (option, value) in &config.options {
+            process.write(&format!("setoption name {option} value {value}"))?;
+        }
+        Ok(Self {
+            config,
+            process,
+            name,
+        })
+    }
+}
+
+impl Player for UciPlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn new_game(&mut self) -> Result<()> {
+        self.process.write("ucinewgame")?;
+        self.process.write("isready")?;
+        self.process
+            .wait_for_timeout("readyok", STARTUP_T)?
+            .ok_or(anyhow!("{} did not answer isready", self.name))?;
+        Ok(())
+    }
+    fn play(&mut self, position: &Position, clocks: Option<&Clocks>) -> Result<Option<BoardMove>> {
+        let mut command = match &position.start {
+            Some(fen) => format!("position fen {fen}"),
+            None => "position startpos".to_owned(),
+        };
+        if !position.uci_moves.is_empty() {
+            command += &format!(" moves {}", position.uci_moves.join(" "));
+        }
+        self.process.write(&command)?;
+        let timeout = match clocks {
+            Some(clocks) => {
+                self.process.write(&format!(
+                    "go wtime {} btime {} winc {} binc {}",
+                    clocks.white.as_millis(),
+                    clocks.black.as_millis(),
+                    clocks.increment.as_millis(),
+                    clocks.increment.as_millis()
+                ))?;
+                clocks.left(position.side)
+            }
+            None => {
+                self.process
+                    .write(&format!("go movetime {}", self.config.movetime.as_millis()))?;
+                self.config.movetime
+            }
+        };
+        let lines = match self.process.wait_for_timeout("bestmove", timeout + LAG_T)? {
+            Some(lines) => lines,
+            // too late either way, the move only decides whether it still counts
+            None => {
+                self.process.write("stop")?;
+                self.process
+                    .wait_for_timeout("bestmove", LAG_T)?
+                    .ok_or(anyhow!("{} stopped answering", self.name))?
+            }
+        };
+        let best = lines.last().and_then(|l| l.split_whitespace().nth(1));
+        match best {
+            None | Some("(none)") | Some("0000") => Ok(None),
+            Some(uci) => BoardMove::from_uci(uci)
+                .map(Some)
+                .ok_or(anyhow!("{} sent an unreadable move '{uci}'", self.name)),
+        }
+    }
+    fn quit(&mut self) {
+        self.process.quit();
+    }
+}
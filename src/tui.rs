@@ -0,0 +1,647 @@
+//! Terminal front end for playing over the network without a window, the board is printed
+//! with Unicode chess pieces and moves are typed in algebraic notation
+
+use crate::board::pgn::GameResult;
+use crate::board::{BoardMove, BoardPos, ChessBoard, ChessBoardCell, ChessPiece, Side};
+use crate::clock::{self, ChessClock, TimeControl};
+use crate::network::client::Client;
+use crate::network::handshake::{Features, HandshakeError, Hello};
+use crate::network::host::Host;
+use crate::network::lobby::GameId;
+use crate::network::relay::Relay;
+use crate::network::secure::Passphrase;
+use crate::network::{self, Connection, Message, Proposal};
+use crate::play::{self, Outcome};
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+const POLL_T: Duration = Duration::from_millis(20);
+const HEARTBEAT_T: Duration = Duration::from_secs(60);
+const HELP: &str =
+    "moves are typed in algebraic notation (e4, Nf3, exd5, O-O, e8=Q) or as coordinates \
+(e2e4)\ncommands: board, clock, say <message>, resign, draw, takeback, accept, decline, help, \
+quit";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Connecting,
+    Move,
+    /// the client waits for the host to accept its move
+    WaitReply(BoardMove),
+    WaitMove,
+    /// waiting for the opponent to answer an offer
+    Asking(Proposal),
+    /// the opponent's offer waits for an answer
+    Answering(Proposal),
+    Over,
+}
+
+struct Tui {
+    board: ChessBoard,
+    /// every move so far, sent to the client when it comes back after losing the connection
+    history: Vec<BoardMove>,
+    side: Side,
+    is_host: bool,
+    conn: Box<dyn Connection>,
+    state: State,
+    /// whether the connection was up the last time it was polled
+    connected: bool,
+    /// ANSI colours, turned off by setting `NO_COLOR`
+    color: bool,
+    /// when both ends agreed on a time control, the host keeps the time
+    clock: Option<ChessClock>,
+}
+
+/// Where the game is played
+pub enum Endpoint<'a> {
+    Direct {
+        address: &'a str,
+        is_host: bool,
+    },
+    /// through a relay server, opening a game there or joining the one with the id
+    Relay {
+        server: &'a str,
+        game: Option<GameId>,
+    },
+}
+
+pub fn run(
+    endpoint: Endpoint,
+    name: &str,
+    side: Option<Side>,
+    time_control: Option<TimeControl>,
+    passphrase: Option<&str>,
+) -> Result<()> {
+    let passphrase = passphrase.map(Passphrase::new);
+    let hello = Hello::new(name)
+        .with_time_control(time_control)
+        .with_side(side);
+    let (conn, is_host, relayed): (Box<dyn Connection>, _, _) = match endpoint {
+        Endpoint::Direct {
+            address,
+            is_host: true,
+        } => {
+            println!("waiting for a client on {address}");
+            let host = Host::new(address, hello)?.with_passphrase(passphrase);
+            (Box::new(host), true, false)
+        }
+        Endpoint::Direct {
+            address,
+            is_host: false,
+        } => {
+            println!("connecting to {address}");
+            let client = Client::new(address, hello)?.with_passphrase(passphrase);
+            (Box::new(client), false, false)
+        }
+        Endpoint::Relay { server, game: None } => {
+            println!("opening a game on {server}");
+            let relay = Relay::open(server, hello)?.with_passphrase(passphrase);
+            (Box::new(relay), true, true)
+        }
+        Endpoint::Relay {
+            server,
+            game: Some(id),
+        } => {
+            println!("joining game {id} on {server}");
+            let relay = Relay::join(server, hello, id)?.with_passphrase(passphrase);
+            (Box::new(relay), false, true)
+        }
+    };
+    let mut tui = Tui {
+        board: ChessBoard::new_full(),
+        history: vec![],
+        // settled in the handshake
+        side: Side::White,
+        is_host,
+        conn,
+        state: State::Connecting,
+        connected: false,
+        color: std::env::var_os("NO_COLOR").is_none(),
+        clock: None,
+    };
+    let input = spawn_input();
+    let mut next_heartbeat = Instant::now() + HEARTBEAT_T;
+    let mut listed = None;
+    while tui.state != State::Over {
+        match tui.conn.poll() {
+            Ok(()) => (),
+            // the host keeps listening for a client it can play with, unless the relay server
+            // turned it away
+            Err(e) if is_host && !relayed && e.is::<HandshakeError>() => println!("{e}"),
+            Err(e) if e.is::<HandshakeError>() => return Err(e),
+            Err(e) => return Err(anyhow!("connection lost: {e}")),
+        }
+        if tui.conn.relay_game() != listed {
+            listed = tui.conn.relay_game();
+            if let Some(id) = listed {
+                println!("the relay lists the game as {id}");
+            }
+        }
+        let connected = tui.conn.is_connected();
+        if tui.state != State::Connecting && connected != tui.connected {
+            if connected {
+                println!("connection restored");
+            } else if is_host {
+                println!("connection lost, waiting for the opponent to come back");
+            } else {
+                println!("connection lost, reconnecting");
+            }
+        }
+        tui.connected = connected;
+        if tui.state == State::Connecting && tui.conn.is_connected() {
+            if let Some(peer) = tui.conn.peer() {
+                tui.side = peer.side.opposite();
+            }
+            match tui.conn.peer() {
+                Some(peer) => println!(
+                    "connected to {}, you play {}, features: {}",
+                    peer.name,
+                    side_name(tui.side),
+                    peer.features
+                ),
+                None => println!("connected, you play {}", side_name(tui.side)),
+            }
+            if let Some(tc) = tui.conn.peer().and_then(|peer| peer.time_control) {
+                println!("clocks: {tc}");
+                tui.clock = Some(ChessClock::new(tc, Side::White));
+            }
+            println!("{HELP}");
+            tui.state = tui.turn_state();
+            tui.print_board();
+        }
+        while let Some(msg) = tui.conn.recv() {
+            tui.handle_message(msg);
+        }
+        tui.check_flag();
+        match input.try_recv() {
+            Ok(line) => tui.handle_input(line.trim()),
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => break,
+        }
+        if Instant::now() >= next_heartbeat {
+            tui.conn.send(Message::HeartBeat());
+            next_heartbeat = Instant::now() + HEARTBEAT_T;
+        }
+        std::thread::sleep(POLL_T);
+    }
+    // the last messages are written on shutdown
+    tui.conn.shutdown();
+    Ok(())
+}
+
+/// Lines typed by the user, read on their own thread so that the network keeps being polled
+fn spawn_input() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+impl Tui {
+    fn handle_input(&mut self, line: &str) {
+        if let Some(text) = line.strip_prefix("say ") {
+            self.say(text);
+            self.prompt();
+            return;
+        }
+        match line {
+            "" => (),
+            "quit" | "exit" => {
+                println!("bye");
+                self.state = State::Over;
+            }
+            "help" => println!("{HELP}"),
+            "board" => self.print_board(),
+            "clock" => self.print_clock(),
+            "resign" | "draw" | "takeback" | "accept" | "decline" => self.control(line),
+            _ if self.state != State::Move => println!("it is not your turn"),
+            san => match BoardMove::from_san(san, &self.board, self.side) {
+                Some(m) => self.play(m),
+                None => println!("'{san}' is not a valid move here"),
+            },
+        }
+        self.prompt();
+    }
+    fn say(&mut self, text: &str) {
+        let can_chat = self
+            .conn
+            .peer()
+            .is_some_and(|peer| peer.features.contains(Features::CHAT));
+        let text = network::clean_chat(text);
+        if !can_chat {
+            println!("the opponent cannot receive chat messages");
+        } else if !text.is_empty() {
+            self.conn.send(Message::Chat(text));
+        }
+    }
+    /// Resigning, offering a draw or a takeback and answering the opponent's offer
+    fn control(&mut self, command: &str) {
+        if self.conn.peer().is_none() {
+            println!("there is no opponent to {command} with yet");
+            return;
+        }
+        match (command, self.state) {
+            ("resign", State::Connecting | State::Over) => (),
+            ("resign", _) => {
+                self.conn.send(Message::Resign());
+                self.conclude(GameResult::won_by(self.side.opposite()));
+            }
+            ("takeback", State::Move | State::WaitMove)
+                if self.takeback_target(self.side).is_none() =>
+            {
+                println!("there is no move of yours to take back")
+            }
+            ("draw" | "takeback", State::Move | State::WaitMove) => {
+                let proposal = if command == "draw" {
+                    Proposal::Draw
+                } else {
+                    Proposal::Takeback
+                };
+                self.conn
+                    .send(Message::Offer(proposal, self.history.len() as u16));
+                self.state = State::Asking(proposal);
+            }
+            ("accept", State::Answering(proposal)) => self.accept(proposal),
+            ("decline", State::Answering(_)) => {
+                self.conn.send(Message::Decline());
+                self.state = self.turn_state();
+            }
+            _ => println!("there is nothing to {command} right now"),
+        }
+    }
+    /// The host has the last word on takebacks, a client agreeing to one waits for it
+    fn accept(&mut self, proposal: Proposal) {
+        match proposal {
+            Proposal::Draw => {
+                self.conn.send(Message::Agree(proposal));
+                self.conclude(GameResult::Draw);
+            }
+            Proposal::Takeback if self.is_host => {
+                match self.takeback_target(self.side.opposite()) {
+                    Some(plies) => self.take_back(plies),
+                    None => {
+                        self.conn.send(Message::Decline());
+                        self.state = self.turn_state();
+                    }
+                }
+            }
+            Proposal::Takeback => {
+                self.conn.send(Message::Agree(proposal));
+                self.state = State::WaitMove;
+            }
+        }
+    }
+    /// How many moves are left once the last one of `side` is taken back, so that it is their
+    /// turn again
+    fn takeback_target(&self, side: Side) -> Option<usize> {
+        play::takeback_target(self.history.len(), side)
+    }
+    /// Goes back to the first `plies` moves, the host tells the client to do the same
+    fn take_back(&mut self, plies: usize) {
+        println!("moves were taken back");
+        self.replay(self.history[..plies].to_vec());
+        for msg in play::take_back(self.is_host, plies, self.clock.as_ref()) {
+            self.conn.send(msg);
+        }
+        self.confirm_position();
+        self.print_board();
+    }
+    /// Ends a game in which no king was taken, the host tells spectators how
+    fn conclude(&mut self, result: GameResult) {
+        if let Some(msg) = play::conclude(self.is_host, result) {
+            self.conn.send(msg);
+        }
+        let msg = match Outcome::of(result, self.side) {
+            Outcome::Won => "You won",
+            Outcome::Lost => "You lost",
+            Outcome::Drawn => "Draw",
+        };
+        println!("{msg}");
+        self.state = State::Over;
+    }
+    /// The host tells the client where the game stands after every final move, so that a client
+    /// which went astray notices
+    fn confirm_position(&mut self) {
+        if self.is_host && self.conn.peer().is_some() {
+            let plies = self.history.len();
+            self.conn.send(play::position(&self.board, plies));
+        }
+    }
+    /// Asks the host for the whole game when the client's position is not the host's
+    fn check_position(&mut self, plies: usize, key: u64) {
+        let own = self.history.len();
+        if let Some(msg) = play::check_position(&self.board, own, plies, key) {
+            self.conn.send(msg);
+        }
+    }
+    /// The host stops the clock of the side which just moved and tells the client the times
+    fn press_clock(&mut self) {
+        if let Some(clock) = self.clock.as_mut().filter(|_| self.is_host) {
+            self.conn.send(play::press_clock(clock));
+        }
+    }
+    /// The host ends the game once the side to move is out of time
+    /// # Returns
+    /// whether the flag fell
+    fn check_flag(&mut self) -> bool {
+        let Some(clock) = self.clock.as_ref().filter(|_| self.is_host) else {
+            return false;
+        };
+        let side = Side::to_move_after(self.history.len());
+        if matches!(self.state, State::Connecting | State::Over) {
+            return false;
+        }
+        let Some(result) = play::flag_fell(clock, &self.board, side) else {
+            return false;
+        };
+        self.conn.send(network::clock_message(clock));
+        println!("\n{} ran out of time", side_name(side));
+        self.conclude(result);
+        true
+    }
+    /// The host's moves are final, the client has to wait for the host to accept them
+    fn play(&mut self, m: BoardMove) {
+        self.conn.send(Message::Moved(m));
+        if !self.is_host {
+            self.state = State::WaitReply(m);
+            return;
+        }
+        self.apply(m);
+        self.confirm_position();
+        self.press_clock();
+        if self.state != State::Over {
+            self.state = State::WaitMove;
+        }
+    }
+    fn handle_message(&mut self, msg: Message) {
+        // the opponent moved instead of answering
+        if let (Message::Moved(_), State::Asking(_)) = (&msg, self.state) {
+            println!("the offer was turned down");
+            self.state = self.turn_state();
+        }
+        match (msg, self.state) {
+            (Message::Moved(m), State::WaitMove) => {
+                let before = self.board.clone();
+                if self.is_host {
+                    // the client's time ran out while its move was on the way
+                    if self.check_flag() {
+                        return;
+                    }
+                    if !play::accepts(&self.board, self.history.len(), m) {
+                        self.conn.send(Message::Rejected());
+                        return;
+                    }
+                    self.conn.send(Message::Accepted());
+                }
+                println!("opponent played {}", m.to_san(&before));
+                self.apply(m);
+                self.confirm_position();
+                self.press_clock();
+                if self.state != State::Over {
+                    self.state = State::Move;
+                }
+            }
+            (Message::Moved(_), _) if self.is_host => self.conn.send(Message::Rejected()),
+            (Message::Accepted() | Message::GameDone(), State::WaitReply(m)) => {
+                self.apply(m);
+                if self.state != State::Over {
+                    self.state = State::WaitMove;
+                }
+            }
+            (Message::Rejected(), State::WaitReply(_)) => {
+                println!("the host rejected the move");
+                self.state = State::Move;
+            }
+            (Message::SyncRequest(), _) if self.is_host => {
+                self.conn.send(Message::Sync(self.history.clone()));
+                if let Some(clock) = &self.clock {
+                    self.conn.send(network::clock_message(clock));
+                }
+                return;
+            }
+            (Message::Position(plies, key), _) if !self.is_host => {
+                self.check_position(plies as usize, key);
+                return;
+            }
+            (Message::Clock(white, black), _) if !self.is_host => {
+                let running = Side::to_move_after(self.history.len());
+                if let Some(clock) = self.clock.as_mut() {
+                    clock.set(white, black, running);
+                }
+                return;
+            }
+            // the host's word, like when a flag fell
+            (Message::Ended(result), _) if !self.is_host && self.state != State::Over => {
+                println!();
+                self.conclude(result);
+                return;
+            }
+            (Message::Sync(moves), _) if !self.is_host => self.resync(moves),
+            (Message::Chat(text), _) => {
+                let name = self.conn.peer().map_or("opponent", |peer| &peer.name);
+                println!("\n{name}: {text}");
+            }
+            (Message::Resign(), _) => {
+                println!("\nthe opponent resigned");
+                self.conclude(GameResult::won_by(self.side));
+                return;
+            }
+            (Message::Offer(proposal, plies), State::Move | State::WaitMove)
+                if plies as usize == self.history.len() =>
+            {
+                match proposal {
+                    Proposal::Draw => println!("\nthe opponent offers a draw"),
+                    Proposal::Takeback => println!("\nthe opponent asks to take back a move"),
+                }
+                self.state = State::Answering(proposal);
+            }
+            // a move crossed it or the client's move is still on its way
+            (Message::Offer(..), _) => {
+                self.conn.send(Message::Decline());
+                return;
+            }
+            (Message::Agree(Proposal::Draw), State::Asking(Proposal::Draw)) => {
+                self.conclude(GameResult::Draw);
+                return;
+            }
+            // the host's word, whatever the client thought was going on
+            (Message::Agree(Proposal::Draw), _) if !self.is_host => {
+                self.conclude(GameResult::Draw);
+                return;
+            }
+            (Message::Agree(Proposal::Takeback), State::Asking(Proposal::Takeback))
+                if self.is_host =>
+            {
+                match self.takeback_target(self.side) {
+                    Some(plies) => self.take_back(plies),
+                    None => self.state = self.turn_state(),
+                }
+            }
+            (Message::Takeback(plies), _)
+                if !self.is_host && plies as usize <= self.history.len() =>
+            {
+                self.take_back(plies as usize)
+            }
+            (Message::Decline(), State::Asking(_)) => {
+                println!("\nthe opponent declined");
+                self.state = self.turn_state();
+            }
+            _ => return,
+        }
+        self.prompt();
+    }
+    /// Replaces the game with the host's, whose moves are known to be valid
+    fn resync(&mut self, moves: Vec<BoardMove>) {
+        self.replay(moves);
+        println!("the game was restored");
+        self.print_board();
+    }
+    fn replay(&mut self, moves: Vec<BoardMove>) {
+        self.board = ChessBoard::new_full();
+        for m in &moves {
+            self.board.move_piece(*m);
+        }
+        self.history = moves;
+        self.state = self.turn_state();
+        if let Some(clock) = self.clock.as_mut() {
+            clock.restart(Side::to_move_after(self.history.len()));
+        }
+    }
+    /// Whose turn it is after the moves so far
+    fn turn_state(&self) -> State {
+        if Side::to_move_after(self.history.len()) == self.side {
+            State::Move
+        } else {
+            State::WaitMove
+        }
+    }
+    /// Plays a move which is known to be valid and ends the game if it took a king
+    fn apply(&mut self, m: BoardMove) {
+        let Some(res) = self.board.move_piece(m) else {
+            return;
+        };
+        self.history.push(m);
+        self.print_board();
+        let taken_king = res.pieces_deleted.iter().find_map(|c| match c {
+            ChessBoardCell::White(ChessPiece::King(_)) => Some(Side::White),
+            ChessBoardCell::Black(ChessPiece::King(_)) => Some(Side::Black),
+            _ => None,
+        });
+        if let Some(loser) = taken_king {
+            self.conn.send(Message::GameDone());
+            println!(
+                "{}",
+                if loser == self.side {
+                    "You lost"
+                } else {
+                    "You won"
+                }
+            );
+            self.state = State::Over;
+        }
+    }
+    fn prompt(&self) {
+        match self.state {
+            State::Move => print!("your move> "),
+            State::WaitReply(_) | State::WaitMove | State::Asking(_) => {
+                print!("waiting for the opponent> ")
+            }
+            State::Answering(_) => print!("accept or decline> "),
+            State::Connecting | State::Over => return,
+        }
+        // nothing to do about a broken terminal
+        let _ = std::io::stdout().flush();
+    }
+    fn print_board(&self) {
+        println!();
+        println!("{}", render(&self.board, self.side, self.color));
+        self.print_clock();
+    }
+    fn print_clock(&self) {
+        if let Some(clock) = &self.clock {
+            println!(
+                "white {}  black {}",
+                clock::format(clock.left(Side::White)),
+                clock::format(clock.left(Side::Black))
+            );
+        }
+    }
+}
+
+/// The board seen from `side`, with ranks and files around it
+pub fn render(board: &ChessBoard, side: Side, color: bool) -> String {
+    let order = |n: usize| match side {
+        Side::White => n,
+        Side::Black => 7 - n,
+    };
+    let mut ret = String::new();
+    for r in 0..8 {
+        let row = order(r);
+        ret += &format!("{} ", 8 - row);
+        for c in 0..8 {
+            let col = order(c);
+            let cell = board.at(BoardPos { row, col }).copied();
+            let symbol = cell.map(|c| piece_symbol(c, color)).unwrap_or(' ');
+            if color {
+                let background = if (row + col) % 2 == 0 { 180 } else { 137 };
+                let foreground = match cell {
+                    Some(ChessBoardCell::White(_)) => 231,
+                    _ => 16,
+                };
+                ret += &format!("\x1b[48;5;{background}m\x1b[38;5;{foreground}m{symbol} \x1b[0m");
+            } else {
+                let empty = if (row + col) % 2 == 0 { '.' } else { ':' };
+                ret.push(if symbol == ' ' { empty } else { symbol });
+                ret.push(' ');
+            }
+        }
+        ret.push('\n');
+    }
+    ret += "  ";
+    for c in 0..8 {
+        ret.push((b'a' + order(c) as u8) as char);
+        ret.push(' ');
+    }
+    ret
+}
+
+/// With colours every piece uses the filled glyph and the colour tells the sides apart, which
+/// reads better on dark terminals
+fn piece_symbol(cell: ChessBoardCell, color: bool) -> char {
+    let (piece, white) = match cell {
+        ChessBoardCell::White(p) => (p, !color),
+        ChessBoardCell::Black(p) => (p, false),
+        ChessBoardCell::Empty => return ' ',
+    };
+    match (piece, white) {
+        (ChessPiece::King(_), true) => '♔',
+        (ChessPiece::Queen, true) => '♕',
+        (ChessPiece::Rook, true) => '♖',
+        (ChessPiece::Bishop, true) => '♗',
+        (ChessPiece::Knight, true) => '♘',
+        (ChessPiece::Pawn(_), true) => '♙',
+        (ChessPiece::King(_), false) => '♚',
+        (ChessPiece::Queen, false) => '♛',
+        (ChessPiece::Rook, false) => '♜',
+        (ChessPiece::Bishop, false) => '♝',
+        (ChessPiece::Knight, false) => '♞',
+        (ChessPiece::Pawn(_), false) => '♟',
+    }
+}
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::White => "white",
+        Side::Black => "black",
+    }
+}